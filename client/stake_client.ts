@@ -41,8 +41,8 @@ const u8 = (n: number) => Buffer.from([n & 0xff]);
 const u64le = (n: bigint) => { const b = Buffer.alloc(8); b.writeBigUInt64LE(n); return b; };
 const i64le = (n: bigint) => { const b = Buffer.alloc(8); b.writeBigInt64LE(n); return b; };
 
-function encodeInitializePool(rr: bigint, lp: bigint): Buffer {
-  return Buffer.concat([u8(IX.InitializePool), u64le(rr), i64le(lp)]);
+function encodeInitializePool(rr: bigint, lp: bigint, poolId: bigint, allowFreezableMint = false): Buffer {
+  return Buffer.concat([u8(IX.InitializePool), u64le(rr), i64le(lp), u64le(poolId), u8(allowFreezableMint ? 1 : 0)]);
 }
 function encodeUpdateConfig(rr: bigint | null, lp: bigint | null): Buffer {
   // Borsh Option<T>: 0x00 for None, 0x01 + T for Some
@@ -54,8 +54,38 @@ function encodeInitializeUser(): Buffer { return Buffer.from([IX.InitializeUser]
 function encodeStake(amount: bigint): Buffer { return Buffer.concat([u8(IX.Stake), u64le(amount)]); }
 function encodeNoArgs(tag: number): Buffer { return Buffer.from([tag]); }
 
- function findPoolPda(mint: PublicKey): [PublicKey, number] {
-   return PublicKey.findProgramAddressSync([Buffer.from('pool'), mint.toBuffer()], PROGRAM_ID);
+ // Pool 0 keeps the original ["pool", mint] seeds; other ids append the id as u64 LE
+ function findPoolPda(mint: PublicKey, poolId = 0n): [PublicKey, number] {
+   const seeds = [Buffer.from('pool'), mint.toBuffer()];
+   if (poolId !== 0n) seeds.push(u64le(poolId));
+   return PublicKey.findProgramAddressSync(seeds, PROGRAM_ID);
+ }
+ function findProtocolConfigPda(): [PublicKey, number] {
+   return PublicKey.findProgramAddressSync([Buffer.from('config')], PROGRAM_ID);
+ }
+ function findRegistryPda(index: number): [PublicKey, number] {
+   const seed = Buffer.alloc(4);
+   seed.writeUInt32LE(index);
+   return PublicKey.findProgramAddressSync([Buffer.from('registry'), seed], PROGRAM_ID);
+ }
+ // ProtocolConfig.pool_count: the registry index the next InitializePool takes (0 before the config exists)
+ const POOL_COUNT_OFFSET = 165;
+ async function nextRegistryIndex(connection: Connection): Promise<number> {
+   const config = await connection.getAccountInfo(findProtocolConfigPda()[0]);
+   return config ? config.data.readUInt32LE(POOL_COUNT_OFFSET) : 0;
+ }
+ // Walk the pool registry in creation order; each RegistryEntry starts with the pool and its mint
+ async function listPools(connection: Connection): Promise<{ index: number; pool: PublicKey; mint: PublicKey }[]> {
+   const count = await nextRegistryIndex(connection);
+   const keys = Array.from({ length: count }, (_, index) => findRegistryPda(index)[0]);
+   const entries = [];
+   // getMultipleAccounts takes at most 100 keys per request
+   for (let i = 0; i < keys.length; i += 100) {
+     entries.push(...(await connection.getMultipleAccountsInfo(keys.slice(i, i + 100))));
+   }
+   return entries.flatMap((entry, index) =>
+     entry ? [{ index, pool: new PublicKey(entry.data.subarray(0, 32)), mint: new PublicKey(entry.data.subarray(32, 64)) }] : [],
+   );
  }
  function findUserPda(pool: PublicKey, owner: PublicKey): [PublicKey, number] {
    return PublicKey.findProgramAddressSync([Buffer.from('user'), pool.toBuffer(), owner.toBuffer()], PROGRAM_ID);
@@ -104,7 +134,7 @@ function loadKeypair(file?: string): Keypair {
    console.log('Vault ATA:', vaultAta.toBase58());
 
    // InitializePool
-  const initData = encodeInitializePool(5_000_000n, 10n);
+  const initData = encodeInitializePool(5_000_000n, 10n, 0n);
    let initIx = new TransactionInstruction({
      programId: PROGRAM_ID,
      keys: [
@@ -117,11 +147,14 @@ function loadKeypair(file?: string): Keypair {
       { pubkey: ASSOCIATED_TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
        { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
       { pubkey: new PublicKey('SysvarRent111111111111111111111111111111111'), isSigner: false, isWritable: false },
+       { pubkey: findProtocolConfigPda()[0], isSigner: false, isWritable: true },
+       { pubkey: findRegistryPda(await nextRegistryIndex(connection))[0], isSigner: false, isWritable: true },
      ],
      data: initData,
    });
   let sigInitPool = await sendAndConfirmTransaction(connection, new Transaction().add(initIx), [payer, user]);
   console.log('InitializePool tx:', sigInitPool);
+  console.log('Registered pools:', (await listPools(connection)).map((p) => p.pool.toBase58()));
 
    // InitializeUser
    const initUserIx = new TransactionInstruction({
@@ -1,6 +1,7 @@
 
 
  use borsh::{BorshDeserialize, BorshSerialize};
+ use bytemuck::{Pod, Zeroable};
  use solana_program::{
      account_info::{next_account_info, AccountInfo},
      clock::Clock,
@@ -9,26 +10,139 @@
      msg,
      program::{invoke, invoke_signed},
      program_error::ProgramError,
-     program_pack::Pack,
+     program_pack::{IsInitialized, Pack, Sealed},
      pubkey::Pubkey,
      rent::Rent,
      sysvar::Sysvar,
  };
+ use mpl_token_metadata::{instructions as mpl_ix, types as mpl_types};
  use spl_associated_token_account::instruction as ata_ix;
  use spl_token::instruction as token_ix;
 
+ // Devnet deployment; forks and tests under another id use the `_with_program_id` helpers
+ solana_program::declare_id!("BC4G4EqWVGdBhy1nPLWBF9fdkMdgyygMDjAV9ATcTWVx");
 
- // Account size constants 
- // Keep these in sync with the structs below
- pub const STAKING_POOL_SIZE: usize = 112;
- pub const USER_STAKE_SIZE: usize = 104;
+ // Account size constants, derived from the Pod layouts so they cannot drift from the structs.
+ // The values themselves are pinned by the const assertions below the structs.
+ pub const STAKING_POOL_SIZE: usize = core::mem::size_of::<StakingPool>();
+ pub const USER_STAKE_SIZE: usize = core::mem::size_of::<UserStake>();
+ pub const GLOBAL_PAUSE_SIZE: usize = core::mem::size_of::<GlobalPause>();
+ pub const POOL_METADATA_SIZE: usize = core::mem::size_of::<PoolMetadata>();
+ pub const INCENTIVE_CAMPAIGN_SIZE: usize = core::mem::size_of::<IncentiveCampaign>();
+ pub const PROTOCOL_CONFIG_SIZE: usize = core::mem::size_of::<ProtocolConfig>();
+ pub const VESTING_CONFIG_SIZE: usize = core::mem::size_of::<VestingConfig>();
+ pub const REGISTRY_ENTRY_SIZE: usize = core::mem::size_of::<RegistryEntry>();
+ pub const PERMIT_NONCE_SIZE: usize = core::mem::size_of::<PermitNonce>();
+ pub const LOCK_TIER_CONFIG_SIZE: usize = core::mem::size_of::<LockTierConfig>();
+ pub const POOL_STATS_SIZE: usize = core::mem::size_of::<PoolStats>();
+ pub const CLEANUP_AUTHORITY_SIZE: usize = core::mem::size_of::<CleanupAuthority>();
+ pub const RENT_PAYER_SIZE: usize = core::mem::size_of::<RentPayer>();
+ pub const POOL_ROLES_SIZE: usize = core::mem::size_of::<PoolRoles>();
+ pub const POOL_DESCRIPTION_SIZE: usize = core::mem::size_of::<PoolDescription>();
+ pub const CLAIM_COOLDOWN_BYPASS_SIZE: usize = core::mem::size_of::<ClaimCooldownBypass>();
+ pub const GLOBAL_TVL_TRACKER_SIZE: usize = core::mem::size_of::<GlobalTVLTracker>();
+ pub const BOOST_CAMPAIGN_SIZE: usize = core::mem::size_of::<BoostCampaign>();
 
+ // Sizes earlier versions of the program created pools and user stakes at. Each older layout is a prefix of
+ // the current one, and MigrateAccount grows such an account to the current size.
+ pub const LEGACY_STAKING_POOL_SIZES: [usize; 4] = [112, 160, 256, 512];
+ pub const LEGACY_USER_STAKE_SIZES: [usize; 2] = [104, 160];
+
+ // PDA seeds; derive with the helpers below rather than by hand
+ /// Pool PDA: `[SEED_POOL, mint, pool_id LE]`, or `[SEED_POOL, mint]` for pool_id 0 (see `find_pool_pda`)
  pub const SEED_POOL: &[u8] = b"pool";
+ /// User stake PDA: `[SEED_USER, pool, owner]` (see `find_user_pda`)
  pub const SEED_USER: &[u8] = b"user";
+ /// Program-wide pause PDA: `[SEED_GLOBAL_PAUSE]`
+ pub const SEED_GLOBAL_PAUSE: &[u8] = b"global_pause";
+ /// Pool metadata PDA: `[SEED_META, pool]`
+ pub const SEED_META: &[u8] = b"meta";
+ /// Incentive campaign PDA: `[SEED_CAMPAIGN, pool, campaign_id as u64 LE]` (see `find_campaign_pda`)
+ pub const SEED_CAMPAIGN: &[u8] = b"campaign";
+ /// Protocol config PDA: `[SEED_PROTOCOL_CONFIG]` (see `find_protocol_config_pda`)
+ pub const SEED_PROTOCOL_CONFIG: &[u8] = b"config";
+ /// Vesting config PDA: `[SEED_VESTING, pool]` (see `find_vesting_pda`)
+ pub const SEED_VESTING: &[u8] = b"vest";
+ /// Pool registry entry PDA: `[SEED_REGISTRY, index as u32 LE]` (see `find_registry_pda`)
+ pub const SEED_REGISTRY: &[u8] = b"registry";
+ /// Mint PDA of position tokens minted before they got Metaplex metadata: `[SEED_POSITION, user_stake]`
+ /// (see `find_position_mint_pda`)
+ pub const SEED_POSITION: &[u8] = b"position";
+ /// Stake permit nonce PDA: `[SEED_PERMIT_NONCE, pool, owner]` (see `find_permit_nonce_pda`)
+ pub const SEED_PERMIT_NONCE: &[u8] = b"permit";
+ /// Lock tier config PDA: `[SEED_LOCK_TIER, pool]` (see `find_lock_tier_pda`)
+ pub const SEED_LOCK_TIER: &[u8] = b"ltier";
+ /// Pool analytics PDA: `[SEED_POOL_STATS, pool]` (see `find_pool_stats_pda`)
+ pub const SEED_POOL_STATS: &[u8] = b"stats";
+ /// Program-wide cleanup authority PDA: `[SEED_CLEANUP_AUTHORITY]` (see `find_cleanup_authority_pda`)
+ pub const SEED_CLEANUP_AUTHORITY: &[u8] = b"cleanup_auth";
+ /// Rent payer record PDA: `[SEED_RENT_PAYER, account]` (see `find_rent_payer_pda`)
+ pub const SEED_RENT_PAYER: &[u8] = b"rent_payer";
+ /// Pool roles PDA: `[SEED_POOL_ROLES, pool]` (see `find_pool_roles_pda`)
+ pub const SEED_POOL_ROLES: &[u8] = b"roles";
+ /// Pool description PDA: `[SEED_POOL_DESCRIPTION, pool]` (see `find_pool_description_pda`)
+ pub const SEED_POOL_DESCRIPTION: &[u8] = b"desc";
+ /// Seeded vault token account PDA, the alternative to the pool's ATA: `[SEED_VAULT, pool]` (see `find_vault_pda`)
+ pub const SEED_VAULT: &[u8] = b"vault";
+ /// Claim cooldown bypass PDA: `[SEED_CLAIM_COOLDOWN_BYPASS, user_stake]` (see `find_claim_cooldown_bypass_pda`)
+ pub const SEED_CLAIM_COOLDOWN_BYPASS: &[u8] = b"cooldown_bypass";
+ /// Program-wide TVL tracker PDA: `[SEED_GLOBAL_TVL]` (see `find_global_tvl_tracker_pda`)
+ pub const SEED_GLOBAL_TVL: &[u8] = b"tvl";
+ /// Boost campaign PDA: `[SEED_BOOST_CAMPAIGN, pool, campaign_id as u64 LE]` (see `find_boost_campaign_pda`)
+ pub const SEED_BOOST_CAMPAIGN: &[u8] = b"bcmp";
+
+ // Per-user reward multipliers, in basis points of the base reward
+ pub const MULTIPLIER_BPS_DENOMINATOR: u16 = 10_000;
+ pub const MIN_USER_MULTIPLIER_BPS: u16 = 100;
+ pub const MAX_USER_MULTIPLIER_BPS: u16 = 50_000;
+
+ // Lamports paid to whoever triggers an AutoCompound, taken from the stake PDA's balance above rent
+ pub const AUTO_COMPOUND_TIP_LAMPORTS: u64 = 5_000;
+
+ // Most stakes a single AirdropStake can credit
+ pub const MAX_AIRDROP_RECIPIENTS: usize = 8;
+
+ // Most user stake PDAs a single InitializeUserBatch can create
+ pub const MAX_INIT_USER_BATCH: usize = 8;
+
+ // Most incentive campaigns a single ClaimRewards can stack
+ pub const MAX_STACKED_CAMPAIGNS: usize = 4;
+
+ // protocol_version stamped on newly created pools
+ pub const INITIAL_PROTOCOL_VERSION: u8 = 1;
+
+ // Wallets besides the super admin that may create pools while pool creation is closed
+ pub const MAX_POOL_CREATORS: usize = 4;
 
+ // Rate epochs in a pool's reward schedule, counting reward_rate as the first
+ pub const MAX_REWARD_EPOCHS: usize = 4;
 
+ // Amount tiers a pool's LockTierConfig holds
+ pub const MAX_LOCK_TIERS: usize = 4;
 
- #[derive(thiserror::Error, Debug, Copy, Clone)]
+ // Seconds in the 365-day year APR helpers annualize over
+ pub const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+ // How far, in bps of the requested APR, the rate reward_rate_from_apr_bps returns may pay from it (1%)
+ pub const APR_TOLERANCE_BPS: u64 = 100;
+
+ // Most stakes FreezeIdleAccounts takes in one call
+ pub const MAX_IDLE_FREEZE_BATCH: u8 = 10;
+ // Log line QueryPendingReward reports through, followed by the amount
+ pub const PENDING_REWARD_LOG_PREFIX: &str = "PENDING_REWARD:";
+ // Most positions ClaimMany settles in one call, each a token CPI
+ pub const MAX_CLAIM_MANY_POSITIONS: u8 = 6;
+ // Inactivity after which FreezeStalePDA may freeze a stake, for newly created pools
+ pub const DEFAULT_IDLE_THRESHOLD_SECONDS: i64 = 365 * 24 * 60 * 60;
+ // Virtual shares and tokens on each side of the shares-mode exchange rate; a donation meant to round the
+ // next deposit down to nothing has to outweigh them, and then mostly goes to them
+ pub const SHARES_VIRTUAL_OFFSET: u64 = 1_000_000;
+ // Metaplex name and symbol of the tokens MintStakePositionNFT mints
+ pub const POSITION_TOKEN_NAME: &str = "Stake Position";
+ pub const POSITION_TOKEN_SYMBOL: &str = "STAKE";
+
+
+
+ #[derive(thiserror::Error, Debug, Copy, Clone, PartialEq, Eq)]
  pub enum StakingError {
      #[error("Unauthorized")] Unauthorized,
      #[error("NotRentExempt")] NotRentExempt,
@@ -41,6 +155,43 @@
      #[error("VaultInsufficient")] VaultInsufficient,
      #[error("ATAMissing")] ATAMissing,
      #[error("TimeWentBackwards")] TimeWentBackwards,
+     #[error("InvalidMultiplier")] InvalidMultiplier,
+     #[error("InvalidCompoundInterval")] InvalidCompoundInterval,
+     #[error("AutoCompoundDisabled")] AutoCompoundDisabled,
+     #[error("CompoundTooEarly")] CompoundTooEarly,
+     #[error("InvalidConfig")] InvalidConfig,
+     #[error("InvalidVault")] InvalidVault,
+     #[error("StillStaked")] StillStaked,
+     #[error("NotOrphaned")] NotOrphaned,
+     #[error("PoolPaused")] PoolPaused,
+     #[error("NoActiveStake")] NoActiveStake,
+     #[error("NothingToClaim")] NothingToClaim,
+     #[error("InvalidMetadata")] InvalidMetadata,
+     #[error("TooManyAccounts")] TooManyAccounts,
+     #[error("AccountFrozen")] AccountFrozen,
+     #[error("AccountNotIdle")] AccountNotIdle,
+     #[error("MintAuthorityModeDisabled")] MintAuthorityModeDisabled,
+     #[error("StakersStillActive")] StakersStillActive,
+     #[error("OutsideStakingWindow")] OutsideStakingWindow,
+     #[error("ClaimBelowMinimum")] ClaimBelowMinimum,
+     #[error("CampaignStillActive")] CampaignStillActive,
+     #[error("ClaimTooSoon")] ClaimTooSoon,
+     #[error("InvalidTreasury")] InvalidTreasury,
+     #[error("EarlyUnstakeDisabled")] EarlyUnstakeDisabled,
+     #[error("InvalidPenaltyRecipient")] InvalidPenaltyRecipient,
+     #[error("PositionTokenized")] PositionTokenized,
+     #[error("InvalidPermit")] InvalidPermit,
+     #[error("PermitNonceUsed")] PermitNonceUsed,
+     #[error("PoolNotInactive")] PoolNotInactive,
+     #[error("MintAuthorityMismatch")] MintAuthorityMismatch,
+     #[error("NonCanonicalTokenAccount")] NonCanonicalTokenAccount,
+     #[error("SponsoredStake")] SponsoredStake,
+     #[error("FreezableMintNotAllowed")] FreezableMintNotAllowed,
+     #[error("MaxStakeExceeded")] MaxStakeExceeded,
+     #[error("AccessPermitRequired")] AccessPermitRequired,
+     #[error("PermitExpired")] PermitExpired,
+     #[error("PermitMismatch")] PermitMismatch,
+     #[error("AprNotRepresentable")] AprNotRepresentable,
  }
 
  impl From<StakingError> for ProgramError {
@@ -50,693 +201,7350 @@
  }
 
 
- /// StakingPool: One per mint. Holds authority, config and totals.
- #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+ /// Little-endian u64 with alignment 1, so state structs can be cast straight from account data
+ #[repr(transparent)]
+ #[derive(Pod, Zeroable, Copy, Clone, Default, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct PodU64(pub [u8; 8]);
+
+ impl PodU64 {
+     pub const fn get(self) -> u64 {
+         u64::from_le_bytes(self.0)
+     }
+ }
+
+ impl From<u64> for PodU64 {
+     fn from(v: u64) -> Self {
+         Self(v.to_le_bytes())
+     }
+ }
+
+ /// Little-endian u16 with alignment 1
+ #[repr(transparent)]
+ #[derive(Pod, Zeroable, Copy, Clone, Default, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct PodU16(pub [u8; 2]);
+
+ impl PodU16 {
+     pub const fn get(self) -> u16 {
+         u16::from_le_bytes(self.0)
+     }
+ }
+
+ impl From<u16> for PodU16 {
+     fn from(v: u16) -> Self {
+         Self(v.to_le_bytes())
+     }
+ }
+
+ /// Little-endian u32 with alignment 1
+ #[repr(transparent)]
+ #[derive(Pod, Zeroable, Copy, Clone, Default, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct PodU32(pub [u8; 4]);
+
+ impl PodU32 {
+     pub const fn get(self) -> u32 {
+         u32::from_le_bytes(self.0)
+     }
+ }
+
+ impl From<u32> for PodU32 {
+     fn from(v: u32) -> Self {
+         Self(v.to_le_bytes())
+     }
+ }
+
+ /// Little-endian i64 with alignment 1
+ #[repr(transparent)]
+ #[derive(Pod, Zeroable, Copy, Clone, Default, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct PodI64(pub [u8; 8]);
+
+ impl PodI64 {
+     pub const fn get(self) -> i64 {
+         i64::from_le_bytes(self.0)
+     }
+ }
+
+ impl From<i64> for PodI64 {
+     fn from(v: i64) -> Self {
+         Self(v.to_le_bytes())
+     }
+ }
+
+ /// A scheduled change of a pool's reward rate: `reward_rate` applies from `start_time` (0 = unused entry)
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Default, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct RewardEpoch {
+     pub start_time: PodI64,
+     pub reward_rate: PodU64,
+ }
+
+ /// StakingPool: One per (mint, pool_id). Holds authority, config and totals.
+ /// Every field has alignment 1, so the in-memory layout is exactly the Borsh encoding.
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
  pub struct StakingPool {
      /// Admin authority that can update config
      pub authority: Pubkey, // 32
-     /// Vault ATA (owner = pool PDA) for the staking mint
+     /// The pool's vault token account (owner = pool PDA) for the staking mint: its ATA, the seeded vault PDA or,
+     /// after RotateVault, any token account the pool PDA owns
      pub vault: Pubkey,     // 32
      /// Reward rate per second per token staked (scaled by 1e9)
-     pub reward_rate: u64,  // 8
+     pub reward_rate: PodU64,  // 8
      /// Minimum lock period in seconds
-     pub min_lock_period: i64, // 8
+     pub min_lock_period: PodI64, // 8
      /// Total staked across all users
-     pub total_staked: u64, // 8
+     pub total_staked: PodU64, // 8
      /// Bump for pool PDA
      pub bump: u8,          // 1
+     /// Proposed new authority awaiting acceptance (default = none)
+     pub pending_authority: Pubkey, // 32
+     /// Upper bound on lock periods in seconds (0 = no limit)
+     pub max_lock_period: PodI64, // 8
+     /// Staking (and reward) mint this pool serves
+     pub mint: Pubkey,      // 32
+     /// Ceiling for reward_rate; can only be lowered (u64::MAX = not yet capped)
+     pub max_reward_rate: PodU64, // 8
+     /// Protocol mechanics version clients key their reward formula off; only ever increases
+     pub protocol_version: u8, // 1
+     /// Seconds without a claim after which a stake may be frozen by FreezeStalePDA
+     pub idle_threshold_seconds: PodI64, // 8
+     /// Non-zero when rewards are funded by MintRewardsToVault, with the pool PDA as mint authority
+     pub mint_authority_mode: u8, // 1
+     /// Non-zero when rewards accrue per slot instead of per second (reward_rate is then per slot)
+     pub use_slot_accrual: u8, // 1
+     /// First UTC hour (0-23) in which Stake is accepted; equal start and end = unrestricted
+     pub stake_window_start_hour: u8, // 1
+     /// UTC hour (0-23) at which the staking window closes; may wrap past midnight
+     pub stake_window_end_hour: u8, // 1
+     /// ClaimRewards fails below this many pending reward tokens (0 = no minimum)
+     pub min_reward_per_claim: PodU64, // 8
+     /// Seconds a user must wait after their last claim before claiming again (0 = no cooldown)
+     pub claim_cooldown: PodI64, // 8
+     /// Stake the pool is sized for; Rebalance steers total_staked toward a share of it (0 = no rebalancing)
+     pub pool_capacity: PodU64, // 8
+     /// Share of pool_capacity Rebalance aims for, in basis points
+     pub target_utilization_bps: PodU16, // 2
+     /// reward_rate when the rebalance params were set; Rebalance keeps the rate within 1x-2x of it
+     pub base_reward_rate: PodU64, // 8
+     /// Seconds after start_time before a stake starts accruing rewards (0 = from the start)
+     pub min_accrual_delay: PodI64, // 8
+     /// Token account (pool mint) that receives withdrawal fees; default = none set
+     pub protocol_treasury_ata: Pubkey, // 32
+     /// Fee on principal returned by Unstake, in basis points (0 = none)
+     pub withdraw_fee_bps: PodU16, // 2
+     /// Wallet whose ATA receives early-unstake penalties; default = penalties are burned
+     pub penalty_recipient: Pubkey, // 32
+     /// Share of principal EarlyUnstake forfeits while the lock is active, in basis points (0 = disabled)
+     pub early_unstake_penalty_bps: PodU16, // 2
+     /// Co-signer required for large reward rate increases; default = no governance
+     pub governance_address: Pubkey, // 32
+     /// UpdateConfig may raise reward_rate by up to this many basis points without governance
+     pub governance_rate_change_threshold_bps: PodU16, // 2
+     /// `LockResetPolicy` as u8: how IncreaseStake moves the lock
+     pub lock_reset_policy: u8, // 1
+     /// Distinguishes pools of the same mint; part of the pool PDA seeds unless 0
+     pub pool_id: PodU64, // 8
+     /// Unstake pays at most this many seconds of unclaimed rewards (0 = no limit)
+     pub max_claim_interval_seconds: PodI64, // 8
+     /// Epochs after reward_rate (epoch 0), by increasing start_time; time accrual only
+     pub reward_schedule: [RewardEpoch; MAX_REWARD_EPOCHS - 1], // 48
+     /// Non-zero once SetLockPeriodTier has run; Stake and IncreaseStake then need the LockTierConfig PDA
+     pub has_lock_tiers: u8, // 1
+     /// Most rewards the pool ever pays out; payouts are clamped to what is left (0 = uncapped)
+     pub max_total_rewards: PodU64, // 8
+     /// Rewards paid out or compounded so far
+     pub total_rewards_distributed: PodU64, // 8
+     /// Non-zero while Stake, ClaimRewards and Unstake update a PoolStats PDA passed to them
+     pub analytics_enabled: u8, // 1
+     /// Second role allowed to manage referrals alongside the authority (default = none)
+     pub referral_authority: Pubkey, // 32
+     /// Tokens the vault must keep above total_staked after a reward payout (0 = none)
+     pub minimum_vault_buffer: PodU64, // 8
+     /// Non-zero to keep early-unstake penalties in the vault as reward surplus instead of paying or burning them
+     pub redistribute_penalties: u8, // 1
+     /// Unix timestamp InitializePool ran at (0 = pools from before it was recorded)
+     pub created_at: PodI64, // 8
+     /// Non-zero once HardPause (or SetPoolState FullyPaused) stopped stakes, claims and unstakes alike.
+     /// This byte held the whole `PoolState` before soft_paused, so a pool left DepositsPaused then reads
+     /// as hard-paused until its pauser resumes it
+     pub hard_paused: u8, // 1
+     /// Non-zero in shares mode: stake amounts and total_staked count shares of the vault's whole balance
+     /// instead of tokens, and the pool accrues no rate-based rewards
+     pub shares_mode: u8, // 1
+     /// Non-zero once SetFunder or SetPauser created the PoolRoles PDA, which MintRewardsToVault and
+     /// SetPoolState must then pass
+     pub has_pool_roles: u8, // 1
+     /// Non-zero if claims mint rewards to the staker, signed by the pool PDA as mint authority,
+     /// rather than paying them from the vault; max_total_rewards caps the emission
+     pub mint_on_claim: u8, // 1
+     /// Non-zero if user token accounts must be the owner's canonical ATA rather than any token account of theirs
+     pub require_canonical_ata: u8, // 1
+     /// Non-zero if the mint had a freeze authority at InitializePool and the creator accepted it
+     pub freezable_mint: u8, // 1
+     /// Key whose ed25519 permits StakeWithPermit requires to open a position (default = anyone may stake)
+     pub access_signer: Pubkey, // 32
+     /// Non-zero once SetStakePositionTransferable turned position transfers off; zero, as in pools from before
+     /// the flag, keeps positions transferable (see `positions_transferable`)
+     pub position_transfers_disabled: u8, // 1
+     /// Vault, owned by a sibling pool PDA of the same mint and authority, that pays this pool's rewards
+     /// (default = the pool's own vault; see SetPoolShared)
+     pub shared_reward_vault: Pubkey, // 32
+     /// Non-zero once SoftPause (or SetPoolState DepositsPaused) stopped new deposits; claims and unstakes go on
+     pub soft_paused: u8, // 1
      /// Reserved padding to reach STAKING_POOL_SIZE
-     pub _reserved: [u8; 23], // 23 => 32+32+8+8+8+1+23 = 112
+     pub _reserved: [u8; 494], // 494 => 32+32+8+8+8+1+32+8+32+8+1+8+1+1+1+1+8+8+8+2+8+8+32+2+32+2+32+2+1+8+8+48+1+8+8+1+32+8+1+8+1+1+1+1+1+1+32+1+32+1+494 = 1024
  }
 
  impl StakingPool {
      pub fn new(
          authority: Pubkey,
          vault: Pubkey,
+         mint: Pubkey,
          reward_rate: u64,
          min_lock_period: i64,
          bump: u8,
+         pool_id: u64,
      ) -> Self {
          Self {
              authority,
              vault,
-             reward_rate,
-             min_lock_period,
-             total_staked: 0,
+             reward_rate: reward_rate.into(),
+             min_lock_period: min_lock_period.into(),
+             total_staked: 0.into(),
              bump,
-             _reserved: [0u8; 23],
+             pending_authority: Pubkey::default(),
+             max_lock_period: 0.into(),
+             mint,
+             max_reward_rate: u64::MAX.into(),
+             protocol_version: INITIAL_PROTOCOL_VERSION,
+             idle_threshold_seconds: DEFAULT_IDLE_THRESHOLD_SECONDS.into(),
+             mint_authority_mode: 0,
+             use_slot_accrual: 0,
+             stake_window_start_hour: 0,
+             stake_window_end_hour: 0,
+             min_reward_per_claim: 0.into(),
+             claim_cooldown: 0.into(),
+             pool_capacity: 0.into(),
+             target_utilization_bps: 0.into(),
+             base_reward_rate: 0.into(),
+             min_accrual_delay: 0.into(),
+             protocol_treasury_ata: Pubkey::default(),
+             withdraw_fee_bps: 0.into(),
+             penalty_recipient: Pubkey::default(),
+             early_unstake_penalty_bps: 0.into(),
+             governance_address: Pubkey::default(),
+             governance_rate_change_threshold_bps: 0.into(),
+             lock_reset_policy: LockResetPolicy::None as u8,
+             pool_id: pool_id.into(),
+             max_claim_interval_seconds: 0.into(),
+             reward_schedule: [RewardEpoch::default(); MAX_REWARD_EPOCHS - 1],
+             has_lock_tiers: 0,
+             max_total_rewards: 0.into(),
+             total_rewards_distributed: 0.into(),
+             analytics_enabled: 0,
+             referral_authority: Pubkey::default(),
+             minimum_vault_buffer: 0.into(),
+             redistribute_penalties: 0,
+             created_at: 0.into(),
+             hard_paused: 0,
+             shares_mode: 0,
+             has_pool_roles: 0,
+             mint_on_claim: 0,
+             require_canonical_ata: 0,
+             freezable_mint: 0,
+             access_signer: Pubkey::default(),
+             position_transfers_disabled: 0,
+             shared_reward_vault: Pubkey::default(),
+             soft_paused: 0,
+             _reserved: [0u8; 494],
+         }
+     }
+
+     /// Borrow pool state in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..STAKING_POOL_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow pool state in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..STAKING_POOL_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Seeds the pool PDA signs with; pool_id 0 keeps the original `[SEED_POOL, mint]` derivation
+     pub fn signer_seeds(&self) -> Vec<&[u8]> {
+         let mut seeds: Vec<&[u8]> = vec![SEED_POOL, self.mint.as_ref()];
+         if self.pool_id.get() != 0 {
+             seeds.push(&self.pool_id.0);
+         }
+         seeds.push(core::slice::from_ref(&self.bump));
+         seeds
+     }
+
+     /// Where early-unstake penalties go, from redistribute_penalties and penalty_recipient
+     pub fn penalty_disposition(&self) -> PenaltyDisposition {
+         if self.redistribute_penalties != 0 {
+             PenaltyDisposition::Redistribute
+         } else if self.penalty_recipient != Pubkey::default() {
+             PenaltyDisposition::Treasury
+         } else {
+             PenaltyDisposition::Burn
+         }
+     }
+
+     /// Whether stakers may hand positions to another wallet, with TransferStakePosition or MintStakePositionNFT
+     pub fn positions_transferable(&self) -> bool {
+         self.position_transfers_disabled == 0
+     }
+
+     /// Whether rewards are paid from a sibling pool's vault (SetPoolShared) rather than this pool's
+     pub fn shares_reward_vault(&self) -> bool {
+         self.shared_reward_vault != Pubkey::default()
+     }
+
+     /// Rewards still payable under max_total_rewards (u64::MAX while uncapped)
+     pub fn remaining_reward_budget(&self) -> u64 {
+         match self.max_total_rewards.get() {
+             0 => u64::MAX,
+             max => max.saturating_sub(self.total_rewards_distributed.get()),
+         }
+     }
+
+     /// Least the vault may hold once rewards leave it: every staker's principal plus minimum_vault_buffer,
+     /// so rewards are only ever paid from the surplus above principal
+     pub fn vault_floor(&self) -> Result<u64, StakingError> {
+         self.total_staked.get().checked_add(self.minimum_vault_buffer.get()).ok_or(StakingError::Overflow)
+     }
+
+     /// Shares minted for depositing `amount` into a shares-mode vault holding `vault_balance`, rounded down.
+     /// Both sides carry SHARES_VIRTUAL_OFFSET, so an empty vault mints 1:1 and tokens that reach the vault
+     /// before a stake go mostly to the virtual shares rather than to whoever stakes first.
+     pub fn shares_for_deposit(&self, vault_balance: u64, amount: u64) -> Result<u64, StakingError> {
+         let total_shares = self.total_staked.get() as u128 + SHARES_VIRTUAL_OFFSET as u128;
+         let shares = amount as u128 * total_shares / (vault_balance as u128 + SHARES_VIRTUAL_OFFSET as u128);
+         u64::try_from(shares).map_err(|_| StakingError::Overflow)
+     }
+
+     /// Tokens `shares` of a shares-mode vault holding `vault_balance` redeem for, rounded down, at the
+     /// same offset exchange rate as `shares_for_deposit`
+     pub fn tokens_for_shares(&self, vault_balance: u64, shares: u64) -> Result<u64, StakingError> {
+         let total_shares = self.total_staked.get() as u128 + SHARES_VIRTUAL_OFFSET as u128;
+         let tokens = shares as u128 * (vault_balance as u128 + SHARES_VIRTUAL_OFFSET as u128) / total_shares;
+         u64::try_from(tokens).map_err(|_| StakingError::Overflow)
+     }
+
+     /// Whether a lock of `seconds` fits under `max_lock_period`
+     pub fn lock_within_max(&self, seconds: i64) -> bool {
+         let max = self.max_lock_period.get();
+         max == 0 || seconds <= max
+     }
+
+     /// Rewards for `amount` staked from `from` to `to`, each stretch at the rate of the epoch it falls in
+     pub fn scheduled_rewards(&self, from: i64, to: i64, amount: u64) -> Result<u64, StakingError> {
+         let mut total = 0u64;
+         let mut rate = self.reward_rate.get();
+         let mut stretch_start = from;
+         for epoch in self.reward_schedule.iter().take_while(|e| e.start_time.get() != 0) {
+             let boundary = epoch.start_time.get();
+             if boundary >= to {
+                 break;
+             }
+             if boundary > stretch_start {
+                 let stretch = calculate_pending_rewards((boundary - stretch_start) as u64, amount, rate)?;
+                 total = total.checked_add(stretch).ok_or(StakingError::Overflow)?;
+                 stretch_start = boundary;
+             }
+             rate = epoch.reward_rate.get();
+         }
+         let last = calculate_pending_rewards(to.saturating_sub(stretch_start).max(0) as u64, amount, rate)?;
+         total.checked_add(last).ok_or(StakingError::Overflow)
+     }
+
+     /// Whether raising reward_rate to `new_rate` goes past the governance threshold
+     pub fn needs_governance_for_rate(&self, new_rate: u64) -> bool {
+         let threshold_bps = MULTIPLIER_BPS_DENOMINATOR as u128 + self.governance_rate_change_threshold_bps.get() as u128;
+         let limit = self.reward_rate.get() as u128 * threshold_bps / MULTIPLIER_BPS_DENOMINATOR as u128;
+         self.governance_address != Pubkey::default() && new_rate as u128 > limit
+     }
+
+     /// Whether Stake is accepted at `unix_timestamp`: its UTC hour lies in
+     /// `[stake_window_start_hour, stake_window_end_hour)`, wrapping past midnight when start > end
+     pub fn stake_window_open(&self, unix_timestamp: i64) -> bool {
+         let hour = (unix_timestamp.rem_euclid(24 * 60 * 60) / (60 * 60)) as u8;
+         let (start, end) = (self.stake_window_start_hour, self.stake_window_end_hour);
+         match start.cmp(&end) {
+             core::cmp::Ordering::Equal => true,
+             core::cmp::Ordering::Less => start <= hour && hour < end,
+             core::cmp::Ordering::Greater => hour >= start || hour < end,
+         }
+     }
+ }
+
+ /// What happens to a user's rewards when they would otherwise be paid out
+ #[repr(u8)]
+ #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+ #[borsh(use_discriminant = true)]
+ pub enum CompoundMode {
+     /// Rewards are paid to the user's ATA on claim
+     Manual = 0,
+     /// ClaimRewards compounds into the stake instead of paying out
+     OnClaim = 1,
+     /// Settle and compound before a stake increase
+     OnIncrease = 2,
+ }
+
+ impl TryFrom<u8> for CompoundMode {
+     type Error = ProgramError;
+
+     fn try_from(v: u8) -> Result<Self, Self::Error> {
+         match v {
+             0 => Ok(Self::Manual),
+             1 => Ok(Self::OnClaim),
+             2 => Ok(Self::OnIncrease),
+             _ => Err(ProgramError::InvalidAccountData),
+         }
+     }
+ }
+
+ /// How IncreaseStake moves the lock of the position it tops up
+ #[repr(u8)]
+ #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+ #[borsh(use_discriminant = true)]
+ pub enum LockResetPolicy {
+     /// The lock still runs from the first stake
+     None = 0,
+     /// The whole position locks for min_lock_period again from the last top-up
+     ResetAll = 1,
+     /// The unlock time becomes the amount-weighted mean of the old unlock and the top-up's own
+     WeightedAverage = 2,
+ }
+
+ impl TryFrom<u8> for LockResetPolicy {
+     type Error = ProgramError;
+
+     fn try_from(v: u8) -> Result<Self, Self::Error> {
+         match v {
+             0 => Ok(Self::None),
+             1 => Ok(Self::ResetAll),
+             2 => Ok(Self::WeightedAverage),
+             _ => Err(ProgramError::InvalidAccountData),
+         }
+     }
+ }
+
+ /// Which user instructions a pool takes, set by its authority
+ #[repr(u8)]
+ #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+ #[borsh(use_discriminant = true)]
+ pub enum PoolState {
+     /// Everything is open
+     Active = 0,
+     /// No new tokens come in, but claims and unstakes go on, e.g. during a migration
+     DepositsPaused = 1,
+     /// Stakes, claims and unstakes all stop, e.g. during an exploit
+     FullyPaused = 2,
+ }
+
+ impl TryFrom<u8> for PoolState {
+     type Error = ProgramError;
+
+     fn try_from(v: u8) -> Result<Self, Self::Error> {
+         match v {
+             0 => Ok(Self::Active),
+             1 => Ok(Self::DepositsPaused),
+             2 => Ok(Self::FullyPaused),
+             _ => Err(ProgramError::InvalidAccountData),
          }
      }
  }
 
+ /// What EarlyUnstake does with the penalty it forfeits, see StakingPool::penalty_disposition
+ #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+ pub enum PenaltyDisposition {
+     /// Paid to penalty_recipient's ATA
+     Treasury,
+     /// Left in the vault as reward surplus for the remaining stakers
+     Redistribute,
+     /// Burned from the vault, reducing supply
+     Burn,
+ }
+
  /// UserStake: Tracks a user's single active stake in a given pool
- #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
  pub struct UserStake {
      /// User wallet owner
      pub owner: Pubkey, // 32
      /// Pool this user is staked in
      pub pool: Pubkey,  // 32
      /// Current staked amount (0 means not staked)
-     pub amount: u64,   // 8
+     pub amount: PodU64,   // 8
      /// Stake start unix timestamp
-     pub start_time: i64, // 8
+     pub start_time: PodI64, // 8
      /// Last timestamp rewards were claimed
-     pub last_claim_time: i64, // 8
+     pub last_claim_time: PodI64, // 8
      /// Cumulative rewards claimed (informational)
-     pub rewards_claimed: u64, // 8
+     pub rewards_claimed: PodU64, // 8
+     /// Bump for user stake PDA
+     pub bump: u8,              // 1
+     /// Reward multiplier in bps set by the authority (10_000 = 1x; 0 = unset, treated as 1x)
+     pub individual_multiplier_bps: PodU16, // 2
+     /// Non-zero when the user opted in to permissionless AutoCompound
+     pub auto_compound_enabled: u8, // 1
+     /// Minimum seconds between auto-compounds
+     pub auto_compound_interval_seconds: PodI64, // 8
+     /// `CompoundMode` as u8
+     pub compound_mode: u8, // 1
+     /// Unix timestamp the stake unlocks at, fixed when it is made so later min_lock_period changes
+     /// do not move it (0 = positions from before it was recorded, which fall back to the pool rule)
+     pub unlock_at: PodI64, // 8
+     /// Non-zero while frozen for inactivity; every stake, claim or unstake on it fails until unfrozen
+     pub frozen: u8, // 1
+     /// Slot of the stake, alongside start_time
+     pub start_slot: PodU64, // 8
+     /// Slot of the last claim, alongside last_claim_time; the accrual base in slot mode
+     pub last_claim_slot: PodU64, // 8
+     /// Unix timestamp of the latest Stake or IncreaseStake; start_time stays at the first
+     pub last_stake_time: PodI64, // 8
+     /// Principal already paid out by ClaimVestedPrincipal; `amount` is what is left of the grant
+     pub principal_claimed: PodU64, // 8
+     /// Non-zero while a position token minted before `position_nft_mint` existed is outstanding, at the
+     /// `find_position_mint_pda` mint; MigrateAccount moves it there, so it is zero on every current account
+     pub legacy_position_nft: u8, // 1
+     /// Reward rate bonus in bps from the lock tier the position reached at its latest stake (0 = none)
+     pub effective_rate_bps: PodU16, // 2
+     /// Non-zero while unlock_at was set by SetUserLockOverride: top-ups leave it alone and
+     /// EarlyUnstake cannot buy out of it
+     pub lock_override: u8, // 1
+     /// Non-zero if a sponsor paid the rent and a RentPayer record names them; UnstakeAndClose refunds them
+     pub rent_sponsored: u8, // 1
+     /// Non-zero while `amount` is a stake credited by RecordStakeOnBehalf: its principal is the protocol's
+     /// and stays in the vault on unstake, until ConvertSponsoredStake pays it in
+     pub sponsored: u8, // 1
+     /// Mint of the position token MintStakePositionNFT minted, while it is outstanding (default = none)
+     pub position_nft_mint: Pubkey, // 32
      /// Reserved padding to reach USER_STAKE_SIZE
-     pub _reserved: [u8; 8], // 8 => 32+32+8+8+8+8+8 = 104
+     pub _reserved: [u8; 4], // 4 => 32+32+8+8+8+8+1+2+1+8+1+8+1+8+8+8+8+1+2+1+1+1+32+4 = 192
  }
 
+ // Arrays over 32 elements have no Default impl
  impl Default for UserStake {
      fn default() -> Self {
-         Self {
-             owner: Pubkey::default(),
-             pool: Pubkey::default(),
-             amount: 0,
-             start_time: 0,
-             last_claim_time: 0,
-             rewards_claimed: 0,
-             _reserved: [0u8; 8],
-         }
+         Self::zeroed()
      }
  }
 
- #[derive(BorshSerialize, BorshDeserialize, Debug)]
- pub enum StakingInstruction {
-     /// Initialize a pool for a given mint
-     /// Accounts:
-     /// - [signer, writable] payer
-     /// - [signer] authority
-     /// - [writable] pool_pda
-     /// - [] mint
-     /// - [writable] vault_ata (ATA owned by pool_pda)
-     /// - [] token_program
-     /// - [] associated_token_program
-     /// - [] system_program
-     /// - [] rent
-     InitializePool { reward_rate: u64, min_lock_period: i64 },
-
-     /// Update config fields (only authority)
-     /// Accounts:
-     /// - [signer] authority
-     /// - [writable] pool_pda
-     UpdateConfig { new_reward_rate: Option<u64>, new_min_lock_period: Option<i64> },
+ impl UserStake {
+     /// Borrow user stake state in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..USER_STAKE_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
 
-     /// Initialize user stake account
-     /// Accounts:
-     /// - [signer, writable] payer
-     /// - [signer] user
-     /// - [] pool_pda
-     /// - [writable] user_stake_pda
-     /// - [] system_program
-     /// - [] rent
-     InitializeUser,
+     /// Mutably borrow user stake state in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..USER_STAKE_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
 
-     /// Stake a specific amount from user's ATA to pool vault
-     /// Accounts:
-     /// - [signer] user
-     /// - [writable] user_ata
-     /// - [] mint
-     /// - [] pool_pda
-     /// - [writable] user_stake_pda
-     /// - [writable] vault_ata
-     /// - [] token_program
-     Stake { amount: u64 },
+     /// Effective reward multiplier; accounts created before the field existed read as 1x
+     pub fn reward_multiplier_bps(&self) -> u16 {
+         match self.individual_multiplier_bps.get() {
+             0 => MULTIPLIER_BPS_DENOMINATOR,
+             bps => bps,
+         }
+     }
 
-     /// Claim rewards from pool vault to user's ATA
-     /// Accounts:
-     /// - [signer] user
-     /// - [writable] user_ata
-     /// - [] mint
-     /// - [writable] user_stake_pda
-     /// - [writable] pool_pda
-     /// - [writable] vault_ata
-     /// - [] token_program
-     ClaimRewards,
+     /// Earliest unix timestamp Unstake is allowed at: `unlock_at`, or for positions that predate it,
+     /// min_lock_period after start_time
+     pub fn unlock_time(&self, pool: &StakingPool) -> i64 {
+         match self.unlock_at.get() {
+             0 => self.start_time.get().saturating_add(pool.min_lock_period.get()),
+             unlock_at => unlock_at,
+         }
+     }
 
-     /// Unstake principal back to user after lock period
-     /// Accounts:
-     /// - [signer] user
-     /// - [writable] user_ata
-     /// - [] mint
-     /// - [writable] user_stake_pda
-     /// - [writable] pool_pda
-     /// - [writable] vault_ata
-     /// - [] token_program
-     Unstake,
+     /// Whether a position token is outstanding: only burning it releases the principal and rewards
+     pub fn tokenized(&self) -> bool {
+         self.position_nft_mint != Pubkey::default()
+     }
  }
 
- entrypoint!(process_instruction);
+ impl Sealed for StakingPool {}
 
- pub fn process_instruction(
-     program_id: &Pubkey,
-     accounts: &[AccountInfo],
-     instruction_data: &[u8],
- ) -> ProgramResult {
-     let ix = StakingInstruction::try_from_slice(instruction_data)
-         .map_err(|_| ProgramError::InvalidInstructionData)?;
-     match ix {
-         StakingInstruction::InitializePool { reward_rate, min_lock_period } => {
-             process_initialize_pool(program_id, accounts, reward_rate, min_lock_period)
-         }
-         StakingInstruction::UpdateConfig { new_reward_rate, new_min_lock_period } => {
-             process_update_config(program_id, accounts, new_reward_rate, new_min_lock_period)
-         }
-         StakingInstruction::InitializeUser => process_initialize_user(program_id, accounts),
-         StakingInstruction::Stake { amount } => process_stake(program_id, accounts, amount),
-         StakingInstruction::ClaimRewards => process_claim(program_id, accounts),
-         StakingInstruction::Unstake => process_unstake(program_id, accounts),
+ // An initialized pool always has an authority; a zeroed account has none
+ impl IsInitialized for StakingPool {
+     fn is_initialized(&self) -> bool {
+         self.authority != Pubkey::default()
      }
  }
 
- fn find_pool_pda(program_id: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
-     Pubkey::find_program_address(&[SEED_POOL, mint.as_ref()], program_id)
- }
+ impl Pack for StakingPool {
+     const LEN: usize = STAKING_POOL_SIZE;
 
- fn find_user_pda(program_id: &Pubkey, pool: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
-     Pubkey::find_program_address(&[SEED_USER, pool.as_ref(), owner.as_ref()], program_id)
+     fn pack_into_slice(&self, dst: &mut [u8]) {
+         dst.copy_from_slice(bytemuck::bytes_of(self));
+     }
+
+     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+         Self::load(src).copied()
+     }
  }
 
- // -------------------------------------------------------------------------------------
- // Instruction processors
- // -------------------------------------------------------------------------------------
+ impl Sealed for UserStake {}
 
- fn process_initialize_pool(
-     program_id: &Pubkey,
-     accounts: &[AccountInfo],
-     reward_rate: u64,
-     min_lock_period: i64,
- ) -> ProgramResult {
-     let account_info_iter = &mut accounts.iter();
-     let payer = next_account_info(account_info_iter)?; // signer, writable
-     let authority = next_account_info(account_info_iter)?; // signer
-     let pool_ai = next_account_info(account_info_iter)?; // writable
-     let mint_ai = next_account_info(account_info_iter)?; // mint
-     let vault_ai = next_account_info(account_info_iter)?; // writable ATA
-     let token_program_ai = next_account_info(account_info_iter)?;
-     let ata_program_ai = next_account_info(account_info_iter)?;
-     let system_program_ai = next_account_info(account_info_iter)?;
-     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+ // An initialized stake always records the wallet it belongs to
+ impl IsInitialized for UserStake {
+     fn is_initialized(&self) -> bool {
+         self.owner != Pubkey::default()
+     }
+ }
 
-     // Signer checks
-     if !payer.is_signer || !authority.is_signer {
-         return Err(StakingError::Unauthorized.into());
+ impl Pack for UserStake {
+     const LEN: usize = USER_STAKE_SIZE;
+
+     fn pack_into_slice(&self, dst: &mut [u8]) {
+         dst.copy_from_slice(bytemuck::bytes_of(self));
      }
 
-     // Derive expected pool PDA
-     let (expected_pool, bump) = find_pool_pda(program_id, mint_ai.key);
-     if *pool_ai.key != expected_pool {
-         return Err(ProgramError::InvalidArgument);
+     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+         Self::load(src).copied()
      }
+ }
 
-    // Create pool PDA account with program-derived signature if not already allocated
-    if pool_ai.data_is_empty() {
-         let rent = Rent::from_account_info(rent_sysvar_ai)?;
-         let required_lamports = rent.minimum_balance(STAKING_POOL_SIZE);
-         // Create the account
-        let create_ix = solana_program::system_instruction::create_account(
-             payer.key,
-             pool_ai.key,
-             required_lamports,
-             STAKING_POOL_SIZE as u64,
-             program_id,
-         );
-        let seeds: &[&[u8]] = &[SEED_POOL, mint_ai.key.as_ref(), &[bump]];
-        invoke_signed(
-            &create_ix,
-            &[payer.clone(), pool_ai.clone(), system_program_ai.clone()],
-            &[seeds],
-        )?;
+ /// GlobalPause: program-wide kill switch, a singleton PDA at [b"global_pause"]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct GlobalPause {
+     /// Who may flip the pause
+     pub authority: Pubkey, // 32
+     /// Non-zero while paused
+     pub paused: u8,        // 1
+     /// Bump for the global pause PDA
+     pub bump: u8,          // 1
+     /// Reserved padding to reach GLOBAL_PAUSE_SIZE
+     pub _reserved: [u8; 30], // 30 => 32+1+1+30 = 64
+ }
 
-         // Sanity: rent exempt
-         if !rent.is_exempt(pool_ai.lamports(), pool_ai.data_len()) {
-             return Err(StakingError::NotRentExempt.into());
-         }
+ impl GlobalPause {
+     /// Borrow global pause state in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..GLOBAL_PAUSE_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
      }
 
-    // Create the vault ATA owned by pool PDA if not exists
-     if vault_ai.data_is_empty() {
-         let create_ata_ix = ata_ix::create_associated_token_account(
-             payer.key,
-             pool_ai.key,
-             mint_ai.key,
-             token_program_ai.key,
-         );
-        invoke(
-             &create_ata_ix,
-             &[
-                 payer.clone(),
-                 vault_ai.clone(),
-                 pool_ai.clone(),
-                 mint_ai.clone(),
-                 system_program_ai.clone(),
-                 token_program_ai.clone(),
-                 ata_program_ai.clone(),
-                 rent_sysvar_ai.clone(),
-             ],
-         )?;
+     /// Mutably borrow global pause state in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..GLOBAL_PAUSE_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
      }
+ }
 
-     // Persist pool state
-     {
-         // Verify vault ATA is indeed owned by pool PDA and for the given mint
-         let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
-             .map_err(|_| ProgramError::InvalidAccountData)?;
-         if vault_data.owner != *pool_ai.key {
-             return Err(StakingError::InvalidOwner.into());
-         }
-         if vault_data.mint != *mint_ai.key {
-             return Err(StakingError::InvalidMint.into());
-         }
+ /// PoolMetadata: display name and URI for a pool, PDA at [b"meta", pool]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct PoolMetadata {
+     /// Pool this metadata describes
+     pub pool: Pubkey,      // 32
+     /// Zero-padded UTF-8 name
+     pub name: [u8; 32],    // 32
+     /// Zero-padded URI (e.g. off-chain JSON)
+     pub uri: [u8; 128],    // 128
+     /// Bump for metadata PDA
+     pub bump: u8,          // 1
+     /// Reserved padding to reach POOL_METADATA_SIZE
+     pub _reserved: [u8; 31], // 31 => 32+32+128+1+31 = 224
+ }
 
-         let mut pool_data = StakingPool::new(*authority.key, *vault_ai.key, reward_rate, min_lock_period, bump);
-         pool_data
-             .serialize(&mut &mut pool_ai.data.borrow_mut()[..])
-             .map_err(|_| ProgramError::AccountDataTooSmall)?;
+ impl PoolMetadata {
+     /// Borrow pool metadata in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..POOL_METADATA_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
      }
 
-     msg!("Pool initialized. Authority={}, Rate={}, Lock={}s", authority.key, reward_rate, min_lock_period);
-     Ok(())
+     /// Mutably borrow pool metadata in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..POOL_METADATA_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
  }
 
- fn process_update_config(
-     program_id: &Pubkey,
-     accounts: &[AccountInfo],
-     new_reward_rate: Option<u64>,
-     new_min_lock_period: Option<i64>,
- ) -> ProgramResult {
-     let account_info_iter = &mut accounts.iter();
-     let authority = next_account_info(account_info_iter)?; // signer
-     let pool_ai = next_account_info(account_info_iter)?;   // writable
+ /// PoolDescription: free-form text for UIs, kept out of the pool account, PDA at [b"desc", pool]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct PoolDescription {
+     /// Pool this description belongs to
+     pub pool: Pubkey,             // 32
+     /// Zero-padded UTF-8 text or JSON, as the authority wrote it
+     pub description: [u8; 256],   // 256
+     /// Unix timestamp of the latest SetPoolDescription
+     pub updated_at: PodI64,       // 8
+     /// Bump for the description PDA
+     pub bump: u8,                 // 1
+     /// Reserved padding to reach POOL_DESCRIPTION_SIZE
+     pub _reserved: [u8; 23], // 23 => 32+256+8+1+23 = 320
+ }
 
-     if !authority.is_signer {
-         return Err(StakingError::Unauthorized.into());
+ impl PoolDescription {
+     /// Borrow the pool description in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..POOL_DESCRIPTION_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
      }
 
-     // Validate PDA data exists
-     {
-         let pool_data = pool_ai.try_borrow_data()?;
-         if pool_data.is_empty() {
-             return Err(ProgramError::UninitializedAccount);
-         }
+     /// Mutably borrow the pool description in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..POOL_DESCRIPTION_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
      }
+ }
 
-     // We can't derive mint from pool directly; we trust PDA derivation by caller context.
-     // Only enforce authority ownership here.
-     let mut pool: StakingPool = StakingPool::try_from_slice(&pool_ai.try_borrow_data()?)
-         .map_err(|_| ProgramError::InvalidAccountData)?;
+ /// IncentiveCampaign: a time-limited reward boost for one pool, PDA at [b"campaign", pool, campaign_id]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct IncentiveCampaign {
+     /// Pool whose claims this campaign boosts
+     pub pool: Pubkey,      // 32
+     /// Authority-chosen id, part of the PDA seeds
+     pub campaign_id: PodU64, // 8
+     /// Boost applied on top of the user's own multiplier, in basis points (10_000 = 1x)
+     pub multiplier_bps: PodU16, // 2
+     /// First unix timestamp at which claims are boosted
+     pub start_time: PodI64, // 8
+     /// Last unix timestamp at which claims are boosted
+     pub end_time: PodI64,  // 8
+     /// Bump for campaign PDA
+     pub bump: u8,          // 1
+     /// Reserved padding to reach INCENTIVE_CAMPAIGN_SIZE
+     pub _reserved: [u8; 5], // 5 => 32+8+2+8+8+1+5 = 64
+ }
 
-     if pool.authority != *authority.key {
-         return Err(StakingError::Unauthorized.into());
+ impl IncentiveCampaign {
+     /// Borrow campaign state in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..INCENTIVE_CAMPAIGN_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
      }
 
-     if let Some(rr) = new_reward_rate {
-         pool.reward_rate = rr;
+     /// Mutably borrow campaign state in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..INCENTIVE_CAMPAIGN_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
      }
-     if let Some(lp) = new_min_lock_period {
-         pool.min_lock_period = lp;
+
+     /// Whether claims at `now` are boosted (both ends inclusive)
+     pub fn is_live(&self, now: i64) -> bool {
+         self.start_time.get() <= now && now <= self.end_time.get()
+     }
+ }
+
+ /// VestingConfig: linear release of staked principal after a cliff, PDA at [b"vest", pool]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct VestingConfig {
+     /// Pool whose stakes vest on this schedule
+     pub pool: Pubkey,      // 32
+     /// Seconds over which principal vests once the cliff has passed
+     pub vesting_duration: PodI64, // 8
+     /// Seconds after a stake's start_time before anything vests
+     pub cliff_seconds: PodI64, // 8
+     /// Bump for vesting PDA
+     pub bump: u8,          // 1
+     /// Reserved padding to reach VESTING_CONFIG_SIZE
+     pub _reserved: [u8; 15], // 15 => 32+8+8+1+15 = 64
+ }
+
+ impl VestingConfig {
+     /// Borrow vesting config in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..VESTING_CONFIG_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow vesting config in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..VESTING_CONFIG_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Vested part of `grant` for a stake started at `start_time`, linear over vesting_duration
+     /// from the cliff and rounded down; `None` while the cliff has not passed
+     pub fn vested(&self, grant: u64, start_time: i64, now: i64) -> Option<u64> {
+         let cliff_end = start_time.saturating_add(self.cliff_seconds.get());
+         if now < cliff_end {
+             return None;
+         }
+         let duration = self.vesting_duration.get().max(1) as u128;
+         let elapsed = (now.saturating_sub(cliff_end) as u128).min(duration);
+         // grant * elapsed / duration <= grant, so it fits back into u64
+         Some((grant as u128 * elapsed / duration) as u64)
+     }
+ }
+
+ /// RegistryEntry: one pool in creation order, PDA at [b"registry", index]; clients walk the indices
+ /// below ProtocolConfig.pool_count to enumerate every registered pool
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct RegistryEntry {
+     /// Registered pool PDA
+     pub pool: Pubkey,      // 32
+     /// The pool's mint
+     pub mint: Pubkey,      // 32
+     /// Position in the registry, part of the PDA seeds
+     pub index: PodU32,     // 4
+     /// Bump for registry entry PDA
+     pub bump: u8,          // 1
+     /// Reserved padding to reach REGISTRY_ENTRY_SIZE
+     pub _reserved: [u8; 27], // 27 => 32+32+4+1+27 = 96
+ }
+
+ impl RegistryEntry {
+     /// Borrow a registry entry in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..REGISTRY_ENTRY_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow a registry entry in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..REGISTRY_ENTRY_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+ }
+
+ /// PermitNonce: replay protection for one wallet's stake permits in one pool, PDA at [b"permit", pool, owner]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct PermitNonce {
+     /// Pool the permits stake into
+     pub pool: Pubkey,      // 32
+     /// Wallet that signs the permits
+     pub owner: Pubkey,     // 32
+     /// Lowest nonce a new permit may use; each permit moves it past its own nonce
+     pub next_nonce: PodU64, // 8
+     /// Bump for permit nonce PDA
+     pub bump: u8,          // 1
+     /// Reserved padding to reach PERMIT_NONCE_SIZE
+     pub _reserved: [u8; 23], // 23 => 32+32+8+1+23 = 96
+ }
+
+ impl PermitNonce {
+     /// Borrow a permit nonce in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..PERMIT_NONCE_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow a permit nonce in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..PERMIT_NONCE_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+ }
+
+ /// One amount tier of a LockTierConfig (all zero = unused entry)
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Default, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct LockTier {
+     /// Smallest position the tier applies to
+     pub min_amount: PodU64,
+     /// Seconds locked on top of the pool's min_lock_period
+     pub min_lock_seconds: PodI64,
+     /// Added to the reward rate, in basis points of it
+     pub bonus_rate_bps: PodU16,
+ }
+
+ impl LockTier {
+     fn is_used(&self) -> bool {
+         *self != Self::default()
+     }
+ }
+
+ /// LockTierConfig: amount-dependent locks and reward bonuses for a pool, PDA at [b"ltier", pool]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct LockTierConfig {
+     /// Pool whose stakes the tiers apply to
+     pub pool: Pubkey,      // 32
+     /// Tiers in no particular order; a position gets the one with the highest min_amount it reaches
+     pub lock_tiers: [LockTier; MAX_LOCK_TIERS], // 72
+     /// Bump for lock tier PDA
+     pub bump: u8,          // 1
+     /// Reserved padding to reach LOCK_TIER_CONFIG_SIZE
+     pub _reserved: [u8; 23], // 23 => 32+72+1+23 = 128
+ }
+
+ impl LockTierConfig {
+     /// Borrow a lock tier config in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..LOCK_TIER_CONFIG_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow a lock tier config in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..LOCK_TIER_CONFIG_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Tier for a position of `amount`: the used tier with the highest min_amount at or below it
+     /// (an all-zero tier, i.e. no extra lock or bonus, when none applies)
+     pub fn tier_for(&self, amount: u64) -> LockTier {
+         self.lock_tiers
+             .iter()
+             .filter(|t| t.is_used() && t.min_amount.get() <= amount)
+             .max_by_key(|t| t.min_amount.get())
+             .copied()
+             .unwrap_or_default()
+     }
+ }
+
+ /// PoolStats: analytics counters for a pool, PDA at [b"stats", pool]; only written while analytics is enabled
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct PoolStats {
+     /// Pool the counters describe
+     pub pool: Pubkey,      // 32
+     /// Highest total_staked seen after a recorded instruction
+     pub peak_tvl: PodU64,  // 8
+     /// Tokens staked through Stake, IncreaseStake, StakeWithPermitSignature and StakeFor
+     pub stake_volume: PodU64, // 8
+     /// Principal withdrawn through the Unstake variants, fees and penalties included
+     pub unstake_volume: PodU64, // 8
+     /// Rewards paid out or compounded by ClaimRewards and the Unstake variants
+     pub rewards_paid: PodU64, // 8
+     /// Recorded stakes and top-ups
+     pub stake_count: PodU64, // 8
+     /// Recorded ClaimRewards calls
+     pub claim_count: PodU64, // 8
+     /// Recorded Unstake calls of any variant
+     pub unstake_count: PodU64, // 8
+     /// Bump for pool stats PDA
+     pub bump: u8,          // 1
+     /// Reserved padding to reach POOL_STATS_SIZE
+     pub _reserved: [u8; 23], // 23 => 32+8+8+8+8+8+8+8+1+23 = 112
+ }
+
+ impl PoolStats {
+     fn record_claim(&mut self, rewards: u64) {
+         self.claim_count = self.claim_count.get().saturating_add(1).into();
+         self.rewards_paid = self.rewards_paid.get().saturating_add(rewards).into();
+     }
+
+     /// Borrow pool stats in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..POOL_STATS_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow pool stats in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..POOL_STATS_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+ }
+
+ /// CleanupAuthority: who may close abandoned pools, a singleton PDA at [b"cleanup_auth"]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct CleanupAuthority {
+     /// Who may run CloseInactivePool
+     pub authority: Pubkey, // 32
+     /// Bump for the cleanup authority PDA
+     pub bump: u8,          // 1
+     /// Reserved padding to reach CLEANUP_AUTHORITY_SIZE
+     pub _reserved: [u8; 47], // 47 => 32+1+47 = 80
+ }
+
+ impl CleanupAuthority {
+     /// Borrow cleanup authority state in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..CLEANUP_AUTHORITY_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow cleanup authority state in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..CLEANUP_AUTHORITY_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+ }
+
+ /// RentPayer: who funded a user stake PDA on its owner's behalf, at [b"rent_payer", user_stake]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct RentPayer {
+     /// Wallet that paid the rent, and gets it back when the account closes
+     pub payer: Pubkey, // 32
+     /// Bump for the rent payer PDA
+     pub bump: u8,      // 1
+     /// Reserved padding to reach RENT_PAYER_SIZE
+     pub _reserved: [u8; 31], // 31 => 32+1+31 = 64
+ }
+
+ impl RentPayer {
+     /// Borrow the rent payer record in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..RENT_PAYER_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow the rent payer record in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..RENT_PAYER_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+ }
+
+ /// PoolRoles: keys the pool authority has delegated routine work to, at [b"roles", pool]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct PoolRoles {
+     /// Pool these roles belong to
+     pub pool: Pubkey,   // 32
+     /// Signs MintRewardsToVault
+     pub funder: Pubkey, // 32
+     /// Signs SetPoolState
+     pub pauser: Pubkey, // 32
+     /// Bump for the pool roles PDA
+     pub bump: u8,       // 1
+     /// Reserved padding to reach POOL_ROLES_SIZE
+     pub _reserved: [u8; 31], // 31 => 32+32+32+1+31 = 128
+ }
+
+ impl PoolRoles {
+     /// Borrow the pool roles in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..POOL_ROLES_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow the pool roles in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..POOL_ROLES_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+ }
+
+ /// ClaimCooldownBypass: until when a position's claims skip the pool's claim_cooldown, at
+ /// [b"cooldown_bypass", user_stake]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct ClaimCooldownBypass {
+     /// User stake PDA the bypass applies to
+     pub user_stake: Pubkey, // 32
+     /// Unix timestamp the bypass lasts through (0 = none)
+     pub bypass_until: PodI64, // 8
+     /// Bump for the claim cooldown bypass PDA
+     pub bump: u8,           // 1
+     /// Reserved padding to reach CLAIM_COOLDOWN_BYPASS_SIZE
+     pub _reserved: [u8; 7], // 7 => 32+8+1+7 = 48
+ }
+
+ impl ClaimCooldownBypass {
+     /// Borrow the claim cooldown bypass in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..CLAIM_COOLDOWN_BYPASS_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow the claim cooldown bypass in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..CLAIM_COOLDOWN_BYPASS_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+ }
+
+ /// GlobalTVLTracker: tokens staked across every pool and the cap on them, a singleton PDA at [b"tvl"].
+ /// Amounts of different mints are added as raw base units.
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct GlobalTVLTracker {
+     /// Who may change the cap
+     pub authority: Pubkey, // 32
+     /// Tokens staked through instructions that passed the tracker, less those unstaked likewise
+     pub total_staked: PodU64, // 8
+     /// Stakes that would take total_staked above this fail with MaxStakeExceeded
+     pub total_tvl_cap: PodU64, // 8
+     /// Bump for the global TVL tracker PDA
+     pub bump: u8,            // 1
+     /// Reserved padding to reach GLOBAL_TVL_TRACKER_SIZE
+     pub _reserved: [u8; 23], // 23 => 32+8+8+1+23 = 72
+ }
+
+ impl GlobalTVLTracker {
+     /// Borrow the global TVL tracker in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..GLOBAL_TVL_TRACKER_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow the global TVL tracker in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..GLOBAL_TVL_TRACKER_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+ }
+
+ /// BoostCampaign: a fixed-lifetime boost on top of the base reward for one pool, PDA at [b"bcmp", pool, campaign_id].
+ /// Unlike an IncentiveCampaign it cannot be edited once created, only closed after it ends.
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct BoostCampaign {
+     /// Pool whose claims this campaign boosts
+     pub pool: Pubkey,      // 32
+     /// Authority-chosen id, part of the PDA seeds
+     pub campaign_id: PodU64, // 8
+     /// Extra reward in basis points of the base reward (2_000 pays 1.2x)
+     pub boost_bps: PodU16, // 2
+     /// First unix timestamp at which claims are boosted
+     pub start_time: PodI64, // 8
+     /// Last unix timestamp at which claims are boosted
+     pub end_time: PodI64,  // 8
+     /// Bump for boost campaign PDA
+     pub bump: u8,          // 1
+     /// Reserved padding to reach BOOST_CAMPAIGN_SIZE
+     pub _reserved: [u8; 29], // 29 => 32+8+2+8+8+1+29 = 88
+ }
+
+ impl BoostCampaign {
+     /// Borrow boost campaign state in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..BOOST_CAMPAIGN_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow boost campaign state in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..BOOST_CAMPAIGN_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Whether claims at `now` are boosted (both ends inclusive)
+     pub fn is_live(&self, now: i64) -> bool {
+         self.start_time.get() <= now && now <= self.end_time.get()
+     }
+
+     /// The boost as a claim multiplier (10_000 = 1x)
+     pub fn multiplier_bps(&self) -> u16 {
+         MULTIPLIER_BPS_DENOMINATOR.saturating_add(self.boost_bps.get())
+     }
+ }
+
+ /// ProtocolConfig: program-wide settings owned by a super admin, a singleton PDA at [b"config"]
+ #[repr(C)]
+ #[derive(Pod, Zeroable, Copy, Clone, Debug, PartialEq, Eq)]
+ #[cfg_attr(feature = "client", derive(BorshSerialize, BorshDeserialize))]
+ pub struct ProtocolConfig {
+     /// Who may change this config
+     pub super_admin: Pubkey, // 32
+     /// Non-zero if anyone may create a pool; otherwise only the super admin and `pool_creators`
+     pub pool_creation_open: u8, // 1
+     /// Withdrawal fee new pools start with, when created with a treasury
+     pub default_fee_bps: PodU16, // 2
+     /// Non-zero while user instructions are paused across every pool
+     pub global_pause: u8,  // 1
+     /// Bump for the protocol config PDA
+     pub bump: u8,          // 1
+     /// Allowlisted pool creators; unused slots are the default pubkey
+     pub pool_creators: [Pubkey; MAX_POOL_CREATORS], // 128
+     /// Pools registered so far; the next pool gets registry entry `pool_count`
+     pub pool_count: PodU32, // 4
+     /// Reserved padding to reach PROTOCOL_CONFIG_SIZE
+     pub _reserved: [u8; 87], // 87 => 32+1+2+1+1+128+4+87 = 256
+ }
+
+ impl ProtocolConfig {
+     /// Borrow protocol config in place from account data
+     pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+         data.get(..PROTOCOL_CONFIG_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Mutably borrow protocol config in place from account data
+     pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+         data.get_mut(..PROTOCOL_CONFIG_SIZE)
+             .and_then(|bytes| bytemuck::try_from_bytes_mut(bytes).ok())
+             .ok_or(ProgramError::InvalidAccountData)
+     }
+
+     /// Whether `creator` may create a pool under this config
+     pub fn may_create_pool(&self, creator: &Pubkey) -> bool {
+         self.pool_creation_open != 0
+             || *creator == self.super_admin
+             || (*creator != Pubkey::default() && self.pool_creators.contains(creator))
+     }
+ }
+
+ // Pin the on-chain layout: account sizes and field offsets must only change deliberately
+ const _: () = {
+     use core::mem::{align_of, offset_of};
+     assert!(STAKING_POOL_SIZE == 1024);
+     assert!(align_of::<StakingPool>() == 1);
+     assert!(offset_of!(StakingPool, vault) == 32);
+     assert!(offset_of!(StakingPool, reward_rate) == 64);
+     assert!(offset_of!(StakingPool, min_lock_period) == 72);
+     assert!(offset_of!(StakingPool, total_staked) == 80);
+     assert!(offset_of!(StakingPool, bump) == 88);
+     assert!(offset_of!(StakingPool, pending_authority) == 89);
+     assert!(offset_of!(StakingPool, max_lock_period) == 121);
+     assert!(offset_of!(StakingPool, mint) == 129);
+     assert!(offset_of!(StakingPool, max_reward_rate) == 161);
+     assert!(offset_of!(StakingPool, protocol_version) == 169);
+     assert!(offset_of!(StakingPool, idle_threshold_seconds) == 170);
+     assert!(offset_of!(StakingPool, mint_authority_mode) == 178);
+     assert!(offset_of!(StakingPool, use_slot_accrual) == 179);
+     assert!(offset_of!(StakingPool, stake_window_start_hour) == 180);
+     assert!(offset_of!(StakingPool, stake_window_end_hour) == 181);
+     assert!(offset_of!(StakingPool, min_reward_per_claim) == 182);
+     assert!(offset_of!(StakingPool, claim_cooldown) == 190);
+     assert!(offset_of!(StakingPool, pool_capacity) == 198);
+     assert!(offset_of!(StakingPool, target_utilization_bps) == 206);
+     assert!(offset_of!(StakingPool, base_reward_rate) == 208);
+     assert!(offset_of!(StakingPool, min_accrual_delay) == 216);
+     assert!(offset_of!(StakingPool, protocol_treasury_ata) == 224);
+     assert!(offset_of!(StakingPool, withdraw_fee_bps) == 256);
+     assert!(offset_of!(StakingPool, penalty_recipient) == 258);
+     assert!(offset_of!(StakingPool, early_unstake_penalty_bps) == 290);
+     assert!(offset_of!(StakingPool, governance_address) == 292);
+     assert!(offset_of!(StakingPool, governance_rate_change_threshold_bps) == 324);
+     assert!(offset_of!(StakingPool, lock_reset_policy) == 326);
+     assert!(offset_of!(StakingPool, pool_id) == 327);
+     assert!(offset_of!(StakingPool, max_claim_interval_seconds) == 335);
+     assert!(offset_of!(StakingPool, reward_schedule) == 343);
+     assert!(offset_of!(StakingPool, has_lock_tiers) == 391);
+     assert!(offset_of!(StakingPool, max_total_rewards) == 392);
+     assert!(offset_of!(StakingPool, total_rewards_distributed) == 400);
+     assert!(offset_of!(StakingPool, analytics_enabled) == 408);
+     assert!(offset_of!(StakingPool, referral_authority) == 409);
+     assert!(offset_of!(StakingPool, minimum_vault_buffer) == 441);
+     assert!(offset_of!(StakingPool, redistribute_penalties) == 449);
+     assert!(offset_of!(StakingPool, created_at) == 450);
+     assert!(offset_of!(StakingPool, hard_paused) == 458);
+     assert!(offset_of!(StakingPool, shares_mode) == 459);
+     assert!(offset_of!(StakingPool, has_pool_roles) == 460);
+     assert!(offset_of!(StakingPool, mint_on_claim) == 461);
+     assert!(offset_of!(StakingPool, require_canonical_ata) == 462);
+     assert!(offset_of!(StakingPool, freezable_mint) == 463);
+     assert!(offset_of!(StakingPool, access_signer) == 464);
+     assert!(offset_of!(StakingPool, position_transfers_disabled) == 496);
+     assert!(offset_of!(StakingPool, shared_reward_vault) == 497);
+     assert!(offset_of!(StakingPool, soft_paused) == 529);
+     assert!(USER_STAKE_SIZE == 192);
+     assert!(align_of::<UserStake>() == 1);
+     assert!(offset_of!(UserStake, pool) == 32);
+     assert!(offset_of!(UserStake, amount) == 64);
+     assert!(offset_of!(UserStake, start_time) == 72);
+     assert!(offset_of!(UserStake, last_claim_time) == 80);
+     assert!(offset_of!(UserStake, rewards_claimed) == 88);
+     assert!(offset_of!(UserStake, bump) == 96);
+     assert!(offset_of!(UserStake, individual_multiplier_bps) == 97);
+     assert!(offset_of!(UserStake, auto_compound_enabled) == 99);
+     assert!(offset_of!(UserStake, auto_compound_interval_seconds) == 100);
+     assert!(offset_of!(UserStake, compound_mode) == 108);
+     assert!(offset_of!(UserStake, unlock_at) == 109);
+     assert!(offset_of!(UserStake, frozen) == 117);
+     assert!(offset_of!(UserStake, start_slot) == 118);
+     assert!(offset_of!(UserStake, last_claim_slot) == 126);
+     assert!(offset_of!(UserStake, last_stake_time) == 134);
+     assert!(offset_of!(UserStake, principal_claimed) == 142);
+     assert!(offset_of!(UserStake, legacy_position_nft) == 150);
+     assert!(offset_of!(UserStake, effective_rate_bps) == 151);
+     assert!(offset_of!(UserStake, lock_override) == 153);
+     assert!(offset_of!(UserStake, rent_sponsored) == 154);
+     assert!(offset_of!(UserStake, sponsored) == 155);
+     assert!(offset_of!(UserStake, position_nft_mint) == 156);
+     assert!(GLOBAL_PAUSE_SIZE == 64);
+     assert!(align_of::<GlobalPause>() == 1);
+     assert!(offset_of!(GlobalPause, paused) == 32);
+     assert!(offset_of!(GlobalPause, bump) == 33);
+     assert!(POOL_METADATA_SIZE == 224);
+     assert!(align_of::<PoolMetadata>() == 1);
+     assert!(offset_of!(PoolMetadata, name) == 32);
+     assert!(offset_of!(PoolMetadata, uri) == 64);
+     assert!(offset_of!(PoolMetadata, bump) == 192);
+     assert!(POOL_DESCRIPTION_SIZE == 320);
+     assert!(align_of::<PoolDescription>() == 1);
+     assert!(offset_of!(PoolDescription, description) == 32);
+     assert!(offset_of!(PoolDescription, updated_at) == 288);
+     assert!(offset_of!(PoolDescription, bump) == 296);
+     assert!(INCENTIVE_CAMPAIGN_SIZE == 64);
+     assert!(align_of::<IncentiveCampaign>() == 1);
+     assert!(offset_of!(IncentiveCampaign, campaign_id) == 32);
+     assert!(offset_of!(IncentiveCampaign, multiplier_bps) == 40);
+     assert!(offset_of!(IncentiveCampaign, start_time) == 42);
+     assert!(offset_of!(IncentiveCampaign, end_time) == 50);
+     assert!(offset_of!(IncentiveCampaign, bump) == 58);
+     assert!(PROTOCOL_CONFIG_SIZE == 256);
+     assert!(align_of::<ProtocolConfig>() == 1);
+     assert!(offset_of!(ProtocolConfig, pool_creation_open) == 32);
+     assert!(offset_of!(ProtocolConfig, default_fee_bps) == 33);
+     assert!(offset_of!(ProtocolConfig, global_pause) == 35);
+     assert!(offset_of!(ProtocolConfig, bump) == 36);
+     assert!(offset_of!(ProtocolConfig, pool_creators) == 37);
+     assert!(offset_of!(ProtocolConfig, pool_count) == 165);
+     assert!(REGISTRY_ENTRY_SIZE == 96);
+     assert!(align_of::<RegistryEntry>() == 1);
+     assert!(offset_of!(RegistryEntry, mint) == 32);
+     assert!(offset_of!(RegistryEntry, index) == 64);
+     assert!(offset_of!(RegistryEntry, bump) == 68);
+     assert!(PERMIT_NONCE_SIZE == 96);
+     assert!(align_of::<PermitNonce>() == 1);
+     assert!(offset_of!(PermitNonce, owner) == 32);
+     assert!(offset_of!(PermitNonce, next_nonce) == 64);
+     assert!(offset_of!(PermitNonce, bump) == 72);
+     assert!(LOCK_TIER_CONFIG_SIZE == 128);
+     assert!(align_of::<LockTierConfig>() == 1);
+     assert!(offset_of!(LockTierConfig, lock_tiers) == 32);
+     assert!(offset_of!(LockTierConfig, bump) == 104);
+     assert!(POOL_STATS_SIZE == 112);
+     assert!(align_of::<PoolStats>() == 1);
+     assert!(offset_of!(PoolStats, peak_tvl) == 32);
+     assert!(offset_of!(PoolStats, unstake_count) == 80);
+     assert!(offset_of!(PoolStats, bump) == 88);
+     assert!(CLEANUP_AUTHORITY_SIZE == 80);
+     assert!(align_of::<CleanupAuthority>() == 1);
+     assert!(offset_of!(CleanupAuthority, bump) == 32);
+     assert!(RENT_PAYER_SIZE == 64);
+     assert!(align_of::<RentPayer>() == 1);
+     assert!(offset_of!(RentPayer, bump) == 32);
+     assert!(POOL_ROLES_SIZE == 128);
+     assert!(align_of::<PoolRoles>() == 1);
+     assert!(offset_of!(PoolRoles, funder) == 32);
+     assert!(offset_of!(PoolRoles, pauser) == 64);
+     assert!(offset_of!(PoolRoles, bump) == 96);
+     assert!(CLAIM_COOLDOWN_BYPASS_SIZE == 48);
+     assert!(align_of::<ClaimCooldownBypass>() == 1);
+     assert!(offset_of!(ClaimCooldownBypass, bypass_until) == 32);
+     assert!(offset_of!(ClaimCooldownBypass, bump) == 40);
+     assert!(GLOBAL_TVL_TRACKER_SIZE == 72);
+     assert!(align_of::<GlobalTVLTracker>() == 1);
+     assert!(offset_of!(GlobalTVLTracker, total_staked) == 32);
+     assert!(offset_of!(GlobalTVLTracker, total_tvl_cap) == 40);
+     assert!(offset_of!(GlobalTVLTracker, bump) == 48);
+     assert!(VESTING_CONFIG_SIZE == 64);
+     assert!(align_of::<VestingConfig>() == 1);
+     assert!(offset_of!(VestingConfig, vesting_duration) == 32);
+     assert!(offset_of!(VestingConfig, cliff_seconds) == 40);
+     assert!(offset_of!(VestingConfig, bump) == 48);
+     assert!(BOOST_CAMPAIGN_SIZE == 88);
+     assert!(align_of::<BoostCampaign>() == 1);
+     assert!(offset_of!(BoostCampaign, campaign_id) == 32);
+     assert!(offset_of!(BoostCampaign, boost_bps) == 40);
+     assert!(offset_of!(BoostCampaign, start_time) == 42);
+     assert!(offset_of!(BoostCampaign, end_time) == 50);
+     assert!(offset_of!(BoostCampaign, bump) == 58);
+ };
+
+ // Decoded once per call, so the fixed-size AirdropStake payload is not worth boxing
+ #[allow(clippy::large_enum_variant)]
+ #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+ pub enum StakingInstruction {
+     /// Initialize a pool for a given mint; `pool_id` tells apart pools of the same mint (0 = the original pool PDA).
+     /// Re-sending it for an existing pool with the same authority and mint is a no-op. A mint with a freeze
+     /// authority, which could freeze the vault, needs `allow_freezable_mint` (FreezableMintNotAllowed otherwise)
+     /// Accounts:
+     /// - [signer, writable] payer
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     /// - [] mint
+     /// - [writable] vault: pool_pda's ATA, or the seeded vault PDA `find_vault_pda(pool_pda)`
+     /// - [] token_program
+     /// - [] associated_token_program (only checked when creating an ATA vault)
+     /// - [] system_program
+     /// - [] rent
+     /// - [writable] protocol_config_pda (optional; may be uninitialized, in which case pool creation is open)
+     /// - [writable] registry_entry_pda for index `pool_count` (required with the config; 0 and left uncreated
+     ///   while the config is uninitialized)
+     /// - [] protocol_treasury_ata (optional; applies the config's default_fee_bps)
+     InitializePool { reward_rate: u64, min_lock_period: i64, pool_id: u64, allow_freezable_mint: bool },
+
+     /// Update config fields (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     /// - [signer] governance (only to raise reward_rate past the governance threshold)
+     UpdateConfig { new_reward_rate: Option<u64>, new_min_lock_period: Option<i64> },
+
+     /// Initialize user stake account; succeeds without writing if it already exists for this user and pool
+     /// A payer other than the user may pass the rent payer PDA to record themselves, so that
+     /// UnstakeAndClose refunds the rent to them rather than to the user.
+     /// Accounts:
+     /// - [signer, writable] payer
+     /// - [signer] user
+     /// - [] pool_pda
+     /// - [writable] user_stake_pda
+     /// - [] system_program
+     /// - [] rent
+     /// - [writable] rent_payer_pda (optional; only while creating the stake PDA for someone else)
+     InitializeUser,
+
+     /// Stake a specific amount from user's ATA to pool vault
+     /// Creates the user stake PDA first if it does not exist yet (user pays rent).
+     /// Accounts:
+     /// - [signer] user (writable when creating the stake PDA)
+     /// - [writable] user_ata
+     /// - [] mint
+     /// - [writable] pool_pda
+     /// - [writable] user_stake_pda
+     /// - [writable] vault_ata
+     /// - [] token_program
+     /// - [] system_program (only needed when creating the stake PDA)
+     /// - [] rent (only needed when creating the stake PDA)
+     /// - [] global_pause_pda (optional)
+     /// - [] lock_tier_pda (required once the pool has lock tiers)
+     /// - [writable] pool_stats_pda (optional; updated while analytics is enabled)
+     /// - [writable] global_tvl_pda (optional; counts the stake against the global cap)
+     Stake { amount: u64 },
+
+     /// Claim rewards from pool vault to user's ATA
+     /// Accounts (optionally followed by the GlobalPause PDA, campaign PDAs, the PoolStats PDA and the
+     /// position's ClaimCooldownBypass PDA):
+     /// - [signer] user
+     /// - [writable] user_ata
+     /// - [] mint
+     /// - [writable] user_stake_pda
+     /// - [writable] pool_pda
+     /// - [writable] vault_ata
+     /// - [] token_program
+     ClaimRewards,
+
+     /// Unstake principal back to user after lock period, less the pool's withdrawal fee
+     /// Accounts:
+     /// - [signer] user
+     /// - [writable] user_ata
+     /// - [] mint
+     /// - [writable] user_stake_pda
+     /// - [writable] pool_pda
+     /// - [writable] vault_ata
+     /// - [] token_program
+     /// - [writable] protocol_treasury_ata (required while withdraw_fee_bps > 0)
+     /// - [writable] pool_stats_pda (optional; updated while analytics is enabled)
+     /// - [writable] global_tvl_pda (optional; takes the returned principal off the global total)
+     Unstake,
+
+     /// Settle a user's pending rewards to their ATA (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] user_ata (ATA of user_wallet; must exist)
+     /// - [] mint
+     /// - [writable] user_stake_pda
+     /// - [writable] pool_pda
+     /// - [writable] vault_ata
+     /// - [] token_program
+     ForceClaimForUser { user_wallet: Pubkey },
+
+     /// Nominate a new authority; takes effect once they accept (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     ProposeAuthority { proposed: Pubkey },
+
+     /// Accept a pending authority nomination
+     /// Accounts:
+     /// - [signer] pending_authority
+     /// - [writable] pool_pda
+     AcceptAuthority,
+
+     /// Withdraw a pending authority nomination (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     CancelAuthorityTransfer,
+
+     /// Override one user's reward rate, in bps of the base rate (only authority)
+     /// Applies to everything not yet claimed, so settle first if that matters.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [] pool_pda
+     /// - [writable] user_stake_pda
+     SetUserRewardMultiplier { user_wallet: Pubkey, multiplier_bps: u16 },
+
+     /// Opt in to bot-triggered compounding every `interval_seconds` (0 = opt out)
+     /// Fund the stake PDA above rent to cover AUTO_COMPOUND_TIP_LAMPORTS per compound.
+     /// Accounts:
+     /// - [signer] user
+     /// - [] pool_pda
+     /// - [writable] user_stake_pda
+     SetupAutoCompound { interval_seconds: i64 },
+
+     /// Compound a user's pending rewards into their stake (permissionless)
+     /// Accounts:
+     /// - [signer, writable] caller (receives the tip, if the PDA can afford it)
+     /// - [writable] user_stake_pda
+     /// - [writable] pool_pda
+     /// - [] vault_ata
+     /// - [] global_pause_pda (optional)
+     AutoCompound { user_wallet: Pubkey },
+
+     /// Cap lock periods at `max_seconds` (0 = no limit; only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetMaxLockPeriod { max_seconds: i64 },
+
+     /// Close an empty user stake account whose recorded pool is not a live pool,
+     /// sending its rent to the authority (only authority)
+     /// Accounts:
+     /// - [signer, writable] authority
+     /// - [] pool_pda
+     /// - [writable] target_pda
+     /// - [] recorded_pool (the target's `pool` field; omit if it is unset)
+     RecoverOrphanedAccount { target_pda: Pubkey },
+
+     /// Read-only health check; logs `INTEGRITY_FAIL: ...` per violation and never fails on them
+     /// Accounts:
+     /// - [] pool_pda
+     /// - [] vault_ata
+     /// - [] user_stake_pda (zero or more; when given, their amounts must sum to total_staked)
+     VerifyPoolIntegrity,
+
+     /// Create the program-wide GlobalPause PDA (once, at deploy)
+     /// Accounts:
+     /// - [signer, writable] payer
+     /// - [writable] global_pause_pda
+     /// - [] system_program
+     /// - [] rent
+     InitializeGlobalPause { authority: Pubkey },
+
+     /// Pause or resume Stake, ClaimRewards and AutoCompound across every pool (only global authority)
+     /// Those instructions honour the pause when the GlobalPause PDA is passed as a trailing account.
+     /// Accounts:
+     /// - [signer] global_authority
+     /// - [writable] global_pause_pda
+     SetGlobalPause { paused: bool },
+
+     /// InitializePool plus a PoolMetadata PDA, in one instruction; a mint with a freeze authority is refused
+     /// Accounts:
+     /// - the InitializePool accounts up to the optional config and registry entry, in the same order
+     /// - [writable] metadata_pda
+     /// - [] protocol_treasury_ata (optional, as for InitializePool)
+     InitializePoolWithMetadata { reward_rate: u64, min_lock_period: i64, pool_id: u64, name: [u8; 32], uri: [u8; 128] },
+
+     /// Choose what ClaimRewards does with rewards (see `CompoundMode`)
+     /// Accounts:
+     /// - [signer] user
+     /// - [] pool_pda
+     /// - [writable] user_stake_pda
+     SetCompoundMode { mode: CompoundMode },
+
+     /// Lower the pool's reward rate ceiling to `max_rate` (only authority).
+     /// The ceiling never rises and never drops below the current reward_rate.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetMaxRewardRate { max_rate: u64 },
+
+     /// Stake on behalf of up to `MAX_AIRDROP_RECIPIENTS` users from the authority's token account
+     /// (only authority). Each `(user_wallet, amount, unlock_at)` entry with a non-default wallet
+     /// needs an initialized, unstaked user stake PDA; unused entries are `Pubkey::default()`.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] authority_ata
+     /// - [] mint
+     /// - [writable] pool_pda
+     /// - [writable] vault_ata
+     /// - [] token_program
+     /// - [writable] user_stake_pda, one per used entry, in order
+     AirdropStake { recipients: [(Pubkey, u64, i64); MAX_AIRDROP_RECIPIENTS] },
+
+     /// Bump the pool's protocol_version to `version`, which must be higher (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetProtocolVersion { version: u8 },
+
+     /// Freeze a stake with no claim for longer than the pool's idle_threshold_seconds (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [] pool_pda
+     /// - [writable] user_stake_pda
+     FreezeStalePDA { user_wallet: Pubkey },
+
+     /// Lift a freeze set by FreezeStalePDA (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [] pool_pda
+     /// - [writable] user_stake_pda
+     UnfreezePDA { user_wallet: Pubkey },
+
+     /// Allow or forbid MintRewardsToVault on this pool (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetMintAuthorityMode { enabled: bool },
+
+     /// Mint `amount` reward tokens into the vault, signed by the pool PDA (only the funder, which is the
+     /// authority until SetFunder). The pool PDA must be the mint authority and mint_authority_mode must be enabled.
+     /// Accounts:
+     /// - [signer] funder
+     /// - [writable] mint
+     /// - [] pool_pda
+     /// - [writable] vault_ata
+     /// - [] token_program
+     /// - [] pool_roles_pda (required once SetFunder or SetPauser has run)
+     MintRewardsToVault { amount: u64 },
+
+     /// Accrue rewards per slot instead of per second (only authority, only while nothing is staked).
+     /// reward_rate is then read per slot.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetAccrualMode { use_slots: bool },
+
+     /// Unstake (lock enforced, rewards settled, principal returned), then close the user stake PDA,
+     /// refunding its lamports to the user, or to the sponsor who paid for it
+     /// Accounts: same as Unstake, then for a sponsored position:
+     /// - [writable] rent_payer_pda
+     /// - [writable] rent_payer (the recorded payer)
+     UnstakeAndClose,
+
+     /// Reset an empty pool (total_staked == 0) to fresh settings with new parameters (only authority).
+     /// Keeps the authority, mint, vault and PDA; every other setting returns to its default.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     ReInitializePool { new_reward_rate: u64, new_min_lock_period: i64 },
+
+     /// Only accept Stake between `start_utc_hour` (inclusive) and `end_utc_hour` (exclusive), UTC (only authority).
+     /// The window wraps past midnight when start > end; equal hours lift the restriction.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetStakingWindow { start_utc_hour: u8, end_utc_hour: u8 },
+
+     /// Reject ClaimRewards with ClaimTooSoon while fewer than `min_reward` reward tokens are pending (0 = no floor,
+     /// only authority). Unstake and ForceClaimForUser always pay out in full.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetRewardFloor { min_reward: u64 },
+
+     /// Create or update incentive campaign `campaign_id`, boosting ClaimRewards by `multiplier_bps`
+     /// between `start` and `end` inclusive, where `end > start > 0` (only authority)
+     /// Accounts:
+     /// - [signer, writable] payer
+     /// - [signer] authority
+     /// - [] pool_pda
+     /// - [writable] campaign_pda
+     /// - [] system_program
+     /// - [] rent sysvar
+     SetIncentiveCampaign { multiplier_bps: u16, start: i64, end: i64, campaign_id: u64 },
+
+     /// Close an incentive campaign once its end time has passed, refunding its rent (only authority)
+     /// Accounts:
+     /// - [signer, writable] authority
+     /// - [] pool_pda
+     /// - [writable] campaign_pda
+     CloseIncentiveCampaign,
+
+     /// Require `cooldown_seconds` between a user's claims (0 = none, only authority). Unstake ignores it.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetClaimCooldown { cooldown_seconds: i64 },
+
+     /// Configure Rebalance: aim for `target_utilization_bps` of `pool_capacity` staked (0 capacity = off),
+     /// recording the current reward_rate as the base it adjusts from (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetRebalanceParams { target_utilization_bps: u16, pool_capacity: u64 },
+
+     /// Nudge reward_rate 5% toward the utilization target: up when under it (at most 2x the base rate
+     /// and never above max_reward_rate), down when over it (never below the base rate) (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     Rebalance,
+
+     /// Hold back accrual until `delay_seconds` after each stake (0 = none, only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetMinAccrualDelay { delay_seconds: i64 },
+
+     /// Charge `bps` of the principal returned by Unstake as a fee to the protocol treasury (only authority).
+     /// Passing a treasury token account (pool mint) records it; a non-zero fee needs one recorded.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     /// - [] protocol_treasury_ata (optional)
+     SetWithdrawFeeBps { bps: u16 },
+
+     /// Send early-unstake penalties to `recipient`'s ATA for the pool mint, or burn them when
+     /// `recipient` is the default pubkey (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetPenaltyRecipient { recipient: Pubkey },
+
+     /// Let EarlyUnstake leave an active lock for `penalty_bps` of the principal (0 = disabled, only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetEarlyUnstakePenalty { penalty_bps: u16 },
+
+     /// Unstake before the lock ends, forfeiting the pool's early-unstake penalty on the principal.
+     /// Past the lock it behaves exactly like Unstake.
+     /// Accounts:
+     /// - [signer] user
+     /// - [writable] user_ata
+     /// - [writable] mint (penalties are burned from the vault)
+     /// - [writable] user_stake_pda
+     /// - [writable] pool_pda
+     /// - [writable] vault_ata
+     /// - [] token_program
+     /// - [writable] penalty_recipient's ATA (only when a recipient is set and penalties are not redistributed)
+     /// - [writable] protocol_treasury_ata (required while withdraw_fee_bps > 0)
+     /// - [writable] pool_stats_pda (optional; updated while analytics is enabled)
+     EarlyUnstake,
+
+     /// Unstake `bps` (1-10_000) of the position after the lock, rounded down; 10_000 empties it.
+     /// Rewards are settled on the whole position; the remainder keeps its lock and start time.
+     /// Accounts: same as Unstake
+     UnstakeBps { bps: u16 },
+
+     /// Require `governance` to co-sign UpdateConfig calls raising reward_rate by more than
+     /// `required_for_rate_change_above_bps` (only authority; default pubkey removes the requirement).
+     /// Once governance is set, changing it needs its signature too.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     /// - [signer] current governance (only when one is set)
+     SetGovernanceAddress { governance: Pubkey, required_for_rate_change_above_bps: u16 },
+
+     /// Add `amount` to an active position. Pending rewards are settled first (compounded instead under
+     /// `CompoundMode::OnIncrease`); the pool's `LockResetPolicy` decides how the lock moves, and the lock tier
+     /// follows the new total.
+     /// Accounts: same as Stake, minus system_program and rent
+     IncreaseStake { amount: u64 },
+
+     /// Choose how IncreaseStake moves a position's lock (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetLockResetPolicy { policy: LockResetPolicy },
+
+     /// Set the pool's idle_threshold_seconds, the inactivity after which a stake may be frozen (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetIdleThreshold { seconds: i64 },
+
+     /// Freeze up to MAX_IDLE_FREEZE_BATCH stakes idle past idle_threshold_seconds in one call (only authority).
+     /// Stakes that are not idle are skipped, so one active user does not fail the batch.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [] pool_pda
+     /// - `user_count` pairs of: [] user_wallet, [writable] user_stake_pda
+     FreezeIdleAccounts { user_count: u8 },
+
+     /// Create the program-wide ProtocolConfig PDA (once, at deploy). Pool creation starts open
+     /// with no default fee and no pause.
+     /// Accounts:
+     /// - [signer, writable] payer
+     /// - [writable] protocol_config_pda
+     /// - [] system_program
+     /// - [] rent
+     InitializeProtocol { super_admin: Pubkey },
+
+     /// Replace every ProtocolConfig setting, including the super admin itself (only super admin).
+     /// `global_pause` blocks the same instructions as SetGlobalPause when the config PDA is passed to them.
+     /// Accounts:
+     /// - [signer] super_admin
+     /// - [writable] protocol_config_pda
+     SetProtocolConfig {
+         super_admin: Pubkey,
+         pool_creation_open: bool,
+         default_fee_bps: u16,
+         global_pause: bool,
+         pool_creators: [Pubkey; MAX_POOL_CREATORS],
+     },
+
+     /// Create or update the pool's VestingConfig PDA (only authority). Staked principal then vests
+     /// linearly over `vesting_duration_seconds`, starting `cliff_seconds` after each stake's start_time.
+     /// Accounts:
+     /// - [signer, writable] payer
+     /// - [signer] authority
+     /// - [] pool_pda
+     /// - [writable] vesting_pda
+     /// - [] system_program
+     /// - [] rent
+     SetVestingSchedule { vesting_duration_seconds: i64, cliff_seconds: i64 },
+
+     /// Withdraw the principal vested so far under the pool's VestingConfig, less withdraw_fee_bps
+     /// Accounts:
+     /// - [signer] user
+     /// - [writable] user_ata
+     /// - [] mint
+     /// - [writable] user_stake_pda
+     /// - [writable] pool_pda
+     /// - [writable] vault_ata
+     /// - [] token_program
+     /// - [] vesting_pda
+     /// - [writable] protocol_treasury_ata (required while withdraw_fee_bps > 0)
+     ClaimVestedPrincipal,
+
+     /// Mint a Metaplex master edition (supply 1, no prints) for the user's active stake at a fresh mint, with
+     /// the pool PDA as update authority and verified creator and the user stake as second creator. Until it is
+     /// burned, every unstake, claim, compound and top-up of the position fails with `PositionTokenized`.
+     /// Accounts:
+     /// - [signer, writable] user
+     /// - [] pool_pda
+     /// - [writable] user_stake_pda
+     /// - [signer, writable] position_mint (new keypair)
+     /// - [writable] user_position_ata (created if missing)
+     /// - [writable] metadata_pda
+     /// - [writable] master_edition_pda
+     /// - [] token_program
+     /// - [] associated_token_program
+     /// - [] token_metadata_program
+     /// - [] system_program
+     /// - [] rent
+     MintStakePositionNFT,
+
+     /// Burn a stake's position token, whoever holds it, and hand them the position: it moves, accrued rewards
+     /// and all, to the holder's user stake PDA in the pool, which must not exist yet. The old account is closed
+     /// and its rent goes to the holder. The trailing accounts are only needed when the holder is not the owner.
+     /// Accounts:
+     /// - [signer, writable] holder
+     /// - [writable] holder_position_ata
+     /// - [writable] position_mint
+     /// - [writable] user_stake_pda
+     /// - [] token_program
+     /// - [] pool_pda
+     /// - [writable] holder_user_stake_pda
+     /// - [] system_program
+     /// - [] rent
+     BurnStakeNFT,
+
+     /// Cap the unclaimed rewards Unstake pays at `max_seconds` worth, forfeiting older ones (0 = no limit, only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetMaxClaimInterval { max_seconds: i64 },
+
+     /// Set reward_rate as epoch 0 plus up to three later `(start_time, reward_rate)` epochs, by strictly
+     /// increasing start_time; unused trailing entries are `(0, 0)` (only authority; slot accrual ignores the epochs)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     /// - [signer] governance (only when a rate goes past the governance threshold)
+     SetRewardSchedule { reward_rate: u64, epochs: [(i64, u64); MAX_REWARD_EPOCHS - 1] },
+
+     /// Stake `amount` for a wallet that signed a permit instead of the transaction, so a relayer can pay the fees.
+     /// The instruction right before this one must be an ed25519 verify of `signature` by the wallet over
+     /// `stake_permit_message(wallet, pool, amount, nonce)`. The wallet's ATA must have approved the pool PDA as
+     /// delegate for at least `amount`, and its stake PDA must already exist (see InitializeUser).
+     /// `nonce` must be at least the PermitNonce PDA's next_nonce (`PermitNonceUsed` otherwise).
+     /// Accounts:
+     /// - [] user_wallet
+     /// - [writable] user_ata
+     /// - [] mint
+     /// - [writable] pool_pda
+     /// - [writable] user_stake_pda
+     /// - [writable] vault_ata
+     /// - [] token_program
+     /// - [signer, writable] payer (creates the PermitNonce PDA on first use)
+     /// - [writable] permit_nonce_pda
+     /// - [] system_program
+     /// - [] instructions_sysvar
+     /// - [] lock_tier_pda (required once the pool has lock tiers)
+     /// - [writable] pool_stats_pda (optional; updated while analytics is enabled)
+     StakeWithPermitSignature { amount: u64, nonce: u64, signature: [u8; 64] },
+
+     /// Create or update the pool's LockTierConfig PDA (only authority). Positions of at least `min_amount` lock for
+     /// `lock_seconds` past min_lock_period and earn `bonus_rate_bps` on top of the reward rate; the tier is fixed at
+     /// each Stake or IncreaseStake. Setting an existing `min_amount` replaces that tier, and zero `lock_seconds` and
+     /// `bonus_rate_bps` remove it.
+     /// Accounts:
+     /// - [signer, writable] payer
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     /// - [writable] lock_tier_pda
+     /// - [] system_program
+     /// - [] rent
+     SetLockPeriodTier { min_amount: u64, lock_seconds: i64, bonus_rate_bps: u16 },
+
+     /// Cap the rewards the pool pays out over its lifetime at `max_total_rewards`, counting what it has paid
+     /// already (0 = uncapped, only authority). Once the budget runs out, claims and unstakes pay no rewards.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetMaxTotalRewards { max_total_rewards: u64 },
+
+     /// Read-only: log `ESTIMATE future_secs=<future_seconds> pending=<n>`, the rewards the position would accrue
+     /// from now over the next `future_seconds` (slots under slot accrual) at its current size. Follows the reward
+     /// schedule, lock tier bonus and user multiplier, and is clamped to the pool's remaining reward budget.
+     /// Accounts:
+     /// - [] pool_pda
+     /// - [] user_stake_pda
+     GetUserRewardEstimate { future_seconds: i64 },
+
+     /// ClaimRewards for up to MAX_CLAIM_MANY_POSITIONS of the signer's positions at once, in any pools served
+     /// by the token program. Any group failing its checks aborts the whole instruction. Campaign boosts do not
+     /// apply; claim boosted positions with ClaimRewards.
+     /// Accounts:
+     /// - [signer] user
+     /// - [] token_program
+     /// - `count` groups of: [writable] user_stake_pda, [writable] pool_pda, [writable] vault_ata,
+     ///   [writable] user_ata, [] mint (each group's pool mint, needed for the checked transfer)
+     /// - [] global_pause_pda (optional)
+     ClaimMany { count: u8 },
+
+     /// Turn analytics on or off (only authority), creating the PoolStats PDA on first use. While on, Stake,
+     /// ClaimRewards and the Unstake variants update the PDA when it is passed as a trailing account.
+     /// Accounts:
+     /// - [signer, writable] payer
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     /// - [writable] pool_stats_pda
+     /// - [] system_program
+     /// - [] rent
+     SetAnalyticsMode { enabled: bool },
+
+     /// Delegate referral management to a second role (only authority); default clears it.
+     /// The role gets no other authority powers.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetReferralAuthority { referral_authority: Pubkey },
+
+     /// Stake from the depositor's ATA into a position owned by `beneficiary`, who alone can later claim
+     /// and unstake it. Fails with DoubleStake while the beneficiary has an active position.
+     /// Accounts:
+     /// - [signer, writable] depositor (pays rent when creating the stake PDA)
+     /// - [writable] depositor_ata
+     /// - [] mint
+     /// - [writable] pool_pda
+     /// - [writable] user_stake_pda (derived for the beneficiary)
+     /// - [writable] vault_ata
+     /// - [] token_program
+     /// - [] system_program (only needed when creating the stake PDA)
+     /// - [] rent (only needed when creating the stake PDA)
+     /// - [] global_pause_pda (optional)
+     /// - [] lock_tier_pda (required once the pool has lock tiers)
+     /// - [writable] pool_stats_pda (optional; updated while analytics is enabled)
+     StakeFor { amount: u64, beneficiary: Pubkey },
+
+     /// Keep at least `buffer` tokens in the vault above total_staked (only authority). Reward payouts that
+     /// would dip below it fail with VaultInsufficient; with 0 they may still use the whole surplus.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetMinimumVaultBuffer { buffer: u64 },
+
+     /// Keep early-unstake penalties in the vault as reward surplus for the remaining stakers instead of
+     /// paying them to the penalty recipient or burning them (only authority)
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetRedistributePenalties { enabled: bool },
+
+     /// InitializePool plus SetRewardSchedule, in one instruction; the schedule follows the same rules
+     /// and its epochs live in the pool account itself. A mint with a freeze authority is refused
+     /// Accounts:
+     /// - the InitializePool accounts, in the same order (including the optional protocol_treasury_ata)
+     CreatePoolWithSchedule {
+         reward_rate: u64,
+         min_lock_period: i64,
+         pool_id: u64,
+         epochs: [(i64, u64); MAX_REWARD_EPOCHS - 1],
+     },
+
+     /// ClaimRewards paying at most `max_amount`: rewards are settled up to the latest second (slot, in slot
+     /// mode) they cover, and the rest keeps accruing for a later claim. u64::MAX claims in full.
+     /// Accounts: as ClaimRewards
+     ClaimUpTo { max_amount: u64 },
+
+     /// Create the program-wide CleanupAuthority PDA (once, at deploy)
+     /// Accounts:
+     /// - [signer, writable] payer
+     /// - [writable] cleanup_authority_pda
+     /// - [] system_program
+     /// - [] rent
+     InitializeCleanupAuthority { authority: Pubkey },
+
+     /// Close a pool nobody stakes in that is older than `pool_age_threshold_seconds` (only cleanup authority).
+     /// The vault's tokens go to the cleanup authority's token account, and the vault's and the pool's rent
+     /// to the cleanup authority.
+     /// Accounts:
+     /// - [signer, writable] cleanup_authority
+     /// - [] cleanup_authority_pda
+     /// - [writable] pool_pda
+     /// - [writable] vault_ata
+     /// - [writable] destination token account (the cleanup authority's, for the pool mint)
+     /// - [] mint
+     /// - [] token_program
+     CloseInactivePool { pool_age_threshold_seconds: i64 },
+
+     /// Pause deposits, or everything, for this pool (only the pauser, which is the authority until
+     /// SetPauser). DepositsPaused soft-pauses and FullyPaused hard-pauses, as SoftPause and HardPause do;
+     /// Active clears both flags.
+     /// Accounts:
+     /// - [signer] pauser
+     /// - [writable] pool_pda
+     /// - [] pool_roles_pda (required once SetFunder or SetPauser has run)
+     SetPoolState { state: PoolState },
+
+     /// Pin one user's unlock time, e.g. to comply with a court order (only authority). Unstake waits
+     /// for `lock_end_time` instead of the pool's lock, top-ups leave it alone and EarlyUnstake is
+     /// refused until it passes. 0 removes the override so the pool's min_lock_period applies again.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [] pool_pda
+     /// - [writable] user_stake_pda
+     SetUserLockOverride { user_wallet: Pubkey, lock_end_time: i64 },
+
+     /// Switch the pool between rate-based rewards and shares mode (only authority), before anyone
+     /// stakes and, to enable it, while the vault is empty (InvalidConfig otherwise). In shares mode a
+     /// stake mints shares at the vault's current exchange rate and an unstake redeems them, so tokens
+     /// sent straight to the vault raise every holder's payout pro rata; no rate-based rewards accrue,
+     /// and AirdropStake and ClaimVestedPrincipal are refused.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     /// - [] vault
+     SetSharesMode { enabled: bool },
+
+     /// Move the pool to another vault (only authority). The new vault must be a plain SPL token account
+     /// for the pool's mint owned by the pool PDA, with no delegate or close authority. The old vault's
+     /// whole balance moves with it, since nothing else can withdraw principal from a retired vault.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     /// - [writable] vault (current)
+     /// - [writable] new_vault
+     /// - [] mint
+     /// - [] token_program
+     RotateVault { new_vault_ata: Pubkey },
+
+     /// InitializeUser for up to `MAX_INIT_USER_BATCH` wallets at once, paid by the payer and without
+     /// their signatures, e.g. to pre-create accounts ahead of an AirdropStake. Accounts that already
+     /// exist for their wallet and pool are skipped.
+     /// Accounts:
+     /// - [signer, writable] payer
+     /// - [] rent
+     /// - then for each wallet, in order:
+     ///   - [] pool_pda
+     ///   - [writable] user_stake_pda
+     ///   - [] system_program
+     InitializeUserBatch { user_wallets: Vec<Pubkey> },
+
+     /// Hand MintRewardsToVault to `funder` (only authority). The first SetFunder or SetPauser creates
+     /// the pool roles PDA, paid by the authority, with both roles starting out as the authority.
+     /// Accounts:
+     /// - [signer, writable] authority
+     /// - [writable] pool_pda
+     /// - [writable] pool_roles_pda
+     /// - [] system_program
+     /// - [] rent
+     SetFunder { funder: Pubkey },
+
+     /// Hand SetPoolState to `pauser` (only authority); accounts as SetFunder
+     SetPauser { pauser: Pubkey },
+
+     /// Create or overwrite the pool's description PDA (only authority), which pays its rent
+     /// Accounts:
+     /// - [signer, writable] authority
+     /// - [] pool_pda
+     /// - [writable] pool_description_pda
+     /// - [] system_program
+     /// - [] rent
+     SetPoolDescription { description: [u8; 256] },
+
+     /// Have claims mint rewards to the staker instead of paying them from the vault (only authority).
+     /// Enabling needs the pool PDA to be the mint's mint authority and a max_total_rewards cap, which
+     /// then bounds the emission. Claims must pass the mint writable; compounding is refused in this mode.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     /// - [] mint
+     SetMintOnClaim { enabled: bool },
+
+     /// Read-only: log `PENDING_REWARD:<n>`, what ClaimRewards would pay the position right now, so wallets
+     /// can simulate this instead of reimplementing the reward formula. Campaign PDAs passed after the
+     /// user stake count as they would for ClaimRewards; the pool's remaining reward budget clamps it.
+     /// Accounts:
+     /// - [] pool_pda
+     /// - [] user_stake_pda
+     /// - [] incentive_campaign_pda or boost_campaign_pda (optional, up to MAX_STACKED_CAMPAIGNS)
+     QueryPendingReward,
+
+     /// Let `user_wallet`'s claims skip the pool's claim_cooldown through `bypass_until`, e.g. for an
+     /// emergency reward distribution (only authority). The cooldown applies again once it passes; 0 ends it
+     /// now. Claims pass the bypass PDA after their other optional accounts for it to count.
+     /// Accounts:
+     /// - [signer, writable] authority
+     /// - [] pool_pda
+     /// - [] user_stake_pda
+     /// - [writable] claim_cooldown_bypass_pda
+     /// - [] system_program
+     /// - [] rent
+     SetClaimCooldownOverride { user_wallet: Pubkey, bypass_until: i64 },
+
+     /// Require user token accounts to be the owner's canonical ATA for the pool mint (only authority).
+     /// Off, the default, any token account the user owns for the mint is accepted; on, Stake, IncreaseStake,
+     /// claims, unstakes and ClaimVestedPrincipal fail with NonCanonicalTokenAccount for any other
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetRequireCanonicalAta { enabled: bool },
+
+     /// Credit `user_wallet` with a stake of `amount` backed by tokens the protocol already holds in the vault,
+     /// without any transfer (only authority). The vault must hold `amount` above its floor (total_staked plus
+     /// minimum_vault_buffer) and the user stake PDA must exist and be empty. The position earns rewards as
+     /// usual, but unstaking it leaves the principal in the vault; ConvertSponsoredStake makes it the user's.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     /// - [writable] user_stake_pda
+     /// - [] vault
+     RecordStakeOnBehalf { user_wallet: Pubkey, amount: u64 },
+
+     /// Turn a sponsored position into an ordinary stake by paying its principal into the vault from the
+     /// owner's token account; unstaking then returns it as usual
+     /// Accounts:
+     /// - [signer] user
+     /// - [writable] user_ata
+     /// - [] mint
+     /// - [writable] user_stake_pda
+     /// - [] pool_pda
+     /// - [writable] vault
+     /// - [] token_program
+     ConvertSponsoredStake,
+
+     /// Create the program-wide GlobalTVLTracker PDA (once, at deploy); the signer becomes its authority.
+     /// Stake, IncreaseStake, StakeWithPermitSignature and StakeFor count against `cap` when the tracker is
+     /// passed after their other accounts, and the unstake instructions take their principal off it likewise.
+     /// Accounts:
+     /// - [signer, writable] authority
+     /// - [writable] global_tvl_pda
+     /// - [] system_program
+     /// - [] rent
+     InitializeGlobalTVLTracker { cap: u64 },
+
+     /// Change the global TVL cap (only the tracker's authority); lowering it below the current total only
+     /// blocks new stakes
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] global_tvl_pda
+     SetGlobalStakeCap { cap: u64 },
+
+     /// Set the key whose permits gate new positions (only authority); Pubkey::default() lifts the gate.
+     /// While one is set, Stake, StakeFor and StakeWithPermitSignature cannot open positions
+     /// (AccessPermitRequired) and StakeWithPermit must; IncreaseStake tops up positions as before.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetAccessSigner { access_signer: Pubkey },
+
+     /// Stake, allowed by a permit from the pool's access_signer. The instruction right before this one must
+     /// be an ed25519 verify by access_signer over `stake_access_permit_message(pool, user, expiry)`; a permit
+     /// for another pool or user or by another key fails with PermitMismatch, and one past `expiry` with
+     /// PermitExpired. A permit may be reused until it expires.
+     /// Accounts: as Stake, with the instructions sysvar inserted after token_program:
+     /// - [signer] user (writable when creating the stake PDA)
+     /// - [writable] user_ata
+     /// - [] mint
+     /// - [writable] pool_pda
+     /// - [writable] user_stake_pda
+     /// - [writable] vault_ata
+     /// - [] token_program
+     /// - [] instructions_sysvar
+     /// - the optional Stake accounts, from system_program on
+     StakeWithPermit { amount: u64, expiry: i64 },
+
+     /// Allow or forbid transferring the pool's positions (only authority); pools start transferable. While they
+     /// are not, TransferStakePosition and MintStakePositionNFT fail with Unauthorized; tokens already minted
+     /// stay transferable.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     SetStakePositionTransferable { transferable: bool },
+
+     /// Pay the pool's rewards from `sibling_vault` instead of its own vault (only authority), so pools of one
+     /// mint, e.g. 30- and 90-day locks, draw on one reward fund. The vault must be owned by another pool PDA
+     /// of the same mint and authority; that pool's stake and vault buffer stay covered. Pubkey::default()
+     /// goes back to the pool's own vault. Principal always stays in, and returns from, each pool's own vault.
+     /// Claims and unstakes of a shared pool then also pass the shared vault and the sibling pool_pda.
+     /// Accounts:
+     /// - [signer] authority
+     /// - [writable] pool_pda
+     /// - [] sibling_vault (unless resetting)
+     /// - [] sibling_pool_pda (unless resetting)
+     SetPoolShared { sibling_vault: Pubkey },
+
+     /// Set `soft_paused` (only the pauser): Stake, IncreaseStake, StakeWithPermitSignature, StakeFor and
+     /// AirdropStake fail with PoolPaused while claims and unstakes go on, e.g. for routine maintenance.
+     /// SetPoolState Active lifts it. Accounts as SetPoolState
+     SoftPause,
+
+     /// Set both `soft_paused` and `hard_paused` (only the pauser): the claim, compound and unstake
+     /// instructions fail with PoolPaused as well, e.g. during an exploit. Accounts as SetPoolState
+     HardPause,
+
+     /// Create boost campaign `campaign_id` (only authority): claims that pass it pay `10_000 + boost_bps`
+     /// bps of the base reward between `start_time` and `end_time` inclusive, where `end_time > start_time > 0`.
+     /// It stacks with incentive campaigns and, unlike them, cannot be changed once created.
+     /// Accounts:
+     /// - [signer, writable] payer
+     /// - [signer] authority
+     /// - [] pool_pda
+     /// - [writable] boost_campaign_pda
+     /// - [] system_program
+     /// - [] rent sysvar
+     CreateBoostCampaign { campaign_id: u64, boost_bps: u16, start_time: i64, end_time: i64 },
+
+     /// Close a boost campaign once its end time has passed, refunding its rent (only authority)
+     /// Accounts:
+     /// - [signer, writable] authority
+     /// - [] pool_pda
+     /// - [writable] boost_campaign_pda
+     CloseBoostCampaign,
+
+     /// Grow a pool or user stake account still at one of its LEGACY_*_SIZES to the current layout
+     /// (permissionless; the payer tops up the rent). Fields past the old end start at their defaults, as for
+     /// a new account. Pools from before `mint` was recorded take it from the mint account, which their PDA
+     /// must be derived from. Until migrated, every other instruction rejects the account as the wrong size.
+     /// Accounts:
+     /// - [signer, writable] payer
+     /// - [writable] pool_pda or user_stake_pda
+     /// - [] system_program
+     /// - [] mint (pools only)
+     MigrateAccount,
+
+     /// Hand the signer's whole position to `new_owner` while the pool's positions are transferable. It moves,
+     /// lock, accrued rewards and all, to new_owner's user stake PDA, which must not exist yet; the old account
+     /// is closed and its rent returned to the owner. Tokenized, frozen and sponsored positions cannot move.
+     /// Accounts:
+     /// - [signer, writable] owner
+     /// - [] pool_pda
+     /// - [writable] user_stake_pda
+     /// - [] new_owner
+     /// - [writable] new_user_stake_pda
+     /// - [] system_program
+     /// - [] rent
+     TransferStakePosition,
+ }
+
+ impl StakingInstruction {
+     /// Decode instruction data, rejecting anything left over after the payload
+     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+         let mut rest = input;
+         let ix = Self::deserialize(&mut rest).map_err(|_| ProgramError::InvalidInstructionData)?;
+         if !rest.is_empty() {
+             return Err(ProgramError::InvalidInstructionData);
+         }
+         Ok(ix)
+     }
+ }
+
+ entrypoint!(process_instruction);
+
+ pub fn process_instruction(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     instruction_data: &[u8],
+ ) -> ProgramResult {
+     let ix = StakingInstruction::unpack(instruction_data)?;
+     match ix {
+         StakingInstruction::InitializePool { reward_rate, min_lock_period, pool_id, allow_freezable_mint } => {
+             process_initialize_pool(program_id, accounts, reward_rate, min_lock_period, pool_id, allow_freezable_mint)
+         }
+         StakingInstruction::UpdateConfig { new_reward_rate, new_min_lock_period } => {
+             process_update_config(program_id, accounts, new_reward_rate, new_min_lock_period)
+         }
+         StakingInstruction::InitializeUser => process_initialize_user(program_id, accounts),
+         StakingInstruction::Stake { amount } => process_stake(program_id, accounts, amount, false),
+         StakingInstruction::ClaimRewards => process_claim(program_id, accounts, None),
+         StakingInstruction::Unstake => process_unstake(program_id, accounts),
+         StakingInstruction::ForceClaimForUser { user_wallet } => {
+             process_force_claim_for_user(program_id, accounts, user_wallet)
+         }
+         StakingInstruction::ProposeAuthority { proposed } => {
+             process_propose_authority(program_id, accounts, proposed)
+         }
+         StakingInstruction::AcceptAuthority => process_accept_authority(program_id, accounts),
+         StakingInstruction::CancelAuthorityTransfer => process_cancel_authority_transfer(program_id, accounts),
+         StakingInstruction::SetUserRewardMultiplier { user_wallet, multiplier_bps } => {
+             process_set_user_reward_multiplier(program_id, accounts, user_wallet, multiplier_bps)
+         }
+         StakingInstruction::SetupAutoCompound { interval_seconds } => {
+             process_setup_auto_compound(program_id, accounts, interval_seconds)
+         }
+         StakingInstruction::AutoCompound { user_wallet } => process_auto_compound(program_id, accounts, user_wallet),
+         StakingInstruction::SetMaxLockPeriod { max_seconds } => {
+             process_set_max_lock_period(program_id, accounts, max_seconds)
+         }
+         StakingInstruction::RecoverOrphanedAccount { target_pda } => {
+             process_recover_orphaned_account(program_id, accounts, target_pda)
+         }
+         StakingInstruction::VerifyPoolIntegrity => process_verify_pool_integrity(program_id, accounts),
+         StakingInstruction::InitializeGlobalPause { authority } => {
+             process_initialize_global_pause(program_id, accounts, authority)
+         }
+         StakingInstruction::SetGlobalPause { paused } => process_set_global_pause(program_id, accounts, paused),
+         StakingInstruction::InitializePoolWithMetadata { reward_rate, min_lock_period, pool_id, name, uri } => {
+             process_initialize_pool_with_metadata(program_id, accounts, reward_rate, min_lock_period, pool_id, name, uri)
+         }
+         StakingInstruction::SetCompoundMode { mode } => process_set_compound_mode(program_id, accounts, mode),
+         StakingInstruction::SetMaxRewardRate { max_rate } => process_set_max_reward_rate(program_id, accounts, max_rate),
+         StakingInstruction::AirdropStake { recipients } => process_airdrop_stake(program_id, accounts, &recipients),
+         StakingInstruction::SetProtocolVersion { version } => process_set_protocol_version(program_id, accounts, version),
+         StakingInstruction::FreezeStalePDA { user_wallet } => process_set_frozen(program_id, accounts, user_wallet, true),
+         StakingInstruction::UnfreezePDA { user_wallet } => process_set_frozen(program_id, accounts, user_wallet, false),
+         StakingInstruction::SetMintAuthorityMode { enabled } => process_set_mint_authority_mode(program_id, accounts, enabled),
+         StakingInstruction::MintRewardsToVault { amount } => process_mint_rewards_to_vault(program_id, accounts, amount),
+         StakingInstruction::SetAccrualMode { use_slots } => process_set_accrual_mode(program_id, accounts, use_slots),
+         StakingInstruction::UnstakeAndClose => process_unstake_and_close(program_id, accounts),
+         StakingInstruction::ReInitializePool { new_reward_rate, new_min_lock_period } => {
+             process_reinitialize_pool(program_id, accounts, new_reward_rate, new_min_lock_period)
+         }
+         StakingInstruction::SetStakingWindow { start_utc_hour, end_utc_hour } => {
+             process_set_staking_window(program_id, accounts, start_utc_hour, end_utc_hour)
+         }
+         StakingInstruction::SetRewardFloor { min_reward } => process_set_reward_floor(program_id, accounts, min_reward),
+         StakingInstruction::SetIncentiveCampaign { multiplier_bps, start, end, campaign_id } => {
+             process_set_incentive_campaign(program_id, accounts, multiplier_bps, start, end, campaign_id)
+         }
+         StakingInstruction::CloseIncentiveCampaign => process_close_incentive_campaign(program_id, accounts),
+         StakingInstruction::SetClaimCooldown { cooldown_seconds } => {
+             process_set_claim_cooldown(program_id, accounts, cooldown_seconds)
+         }
+         StakingInstruction::SetRebalanceParams { target_utilization_bps, pool_capacity } => {
+             process_set_rebalance_params(program_id, accounts, target_utilization_bps, pool_capacity)
+         }
+         StakingInstruction::Rebalance => process_rebalance(program_id, accounts),
+         StakingInstruction::SetMinAccrualDelay { delay_seconds } => {
+             process_set_min_accrual_delay(program_id, accounts, delay_seconds)
+         }
+         StakingInstruction::SetWithdrawFeeBps { bps } => process_set_withdraw_fee_bps(program_id, accounts, bps),
+         StakingInstruction::SetPenaltyRecipient { recipient } => {
+             process_set_penalty_recipient(program_id, accounts, recipient)
+         }
+         StakingInstruction::SetEarlyUnstakePenalty { penalty_bps } => {
+             process_set_early_unstake_penalty(program_id, accounts, penalty_bps)
+         }
+         StakingInstruction::EarlyUnstake => process_early_unstake(program_id, accounts),
+         StakingInstruction::UnstakeBps { bps } => process_unstake_bps(program_id, accounts, bps),
+         StakingInstruction::SetGovernanceAddress { governance, required_for_rate_change_above_bps } => {
+             process_set_governance_address(program_id, accounts, governance, required_for_rate_change_above_bps)
+         }
+         StakingInstruction::IncreaseStake { amount } => process_stake(program_id, accounts, amount, true),
+         StakingInstruction::SetLockResetPolicy { policy } => process_set_lock_reset_policy(program_id, accounts, policy),
+         StakingInstruction::SetIdleThreshold { seconds } => process_set_idle_threshold(program_id, accounts, seconds),
+         StakingInstruction::FreezeIdleAccounts { user_count } => process_freeze_idle_accounts(program_id, accounts, user_count),
+         StakingInstruction::InitializeProtocol { super_admin } => {
+             process_initialize_protocol(program_id, accounts, super_admin)
+         }
+         StakingInstruction::SetProtocolConfig { super_admin, pool_creation_open, default_fee_bps, global_pause, pool_creators } => {
+             let settings = ProtocolConfig {
+                 super_admin,
+                 pool_creation_open: pool_creation_open as u8,
+                 default_fee_bps: default_fee_bps.into(),
+                 global_pause: global_pause as u8,
+                 bump: 0,
+                 pool_creators,
+                 pool_count: 0.into(),
+                 _reserved: [0u8; 87],
+             };
+             process_set_protocol_config(program_id, accounts, settings)
+         }
+         StakingInstruction::SetVestingSchedule { vesting_duration_seconds, cliff_seconds } => {
+             process_set_vesting_schedule(program_id, accounts, vesting_duration_seconds, cliff_seconds)
+         }
+         StakingInstruction::ClaimVestedPrincipal => process_claim_vested_principal(program_id, accounts),
+         StakingInstruction::MintStakePositionNFT => process_mint_stake_position_nft(program_id, accounts),
+         StakingInstruction::BurnStakeNFT => process_burn_stake_nft(program_id, accounts),
+         StakingInstruction::SetMaxClaimInterval { max_seconds } => {
+             process_set_max_claim_interval(program_id, accounts, max_seconds)
+         }
+         StakingInstruction::SetRewardSchedule { reward_rate, epochs } => {
+             process_set_reward_schedule(program_id, accounts, reward_rate, &epochs)
+         }
+         StakingInstruction::StakeWithPermitSignature { amount, nonce, signature } => {
+             process_stake_with_permit(program_id, accounts, amount, nonce, &signature)
+         }
+         StakingInstruction::SetLockPeriodTier { min_amount, lock_seconds, bonus_rate_bps } => {
+             process_set_lock_period_tier(program_id, accounts, min_amount, lock_seconds, bonus_rate_bps)
+         }
+         StakingInstruction::SetMaxTotalRewards { max_total_rewards } => {
+             process_set_max_total_rewards(program_id, accounts, max_total_rewards)
+         }
+         StakingInstruction::GetUserRewardEstimate { future_seconds } => {
+             process_get_user_reward_estimate(program_id, accounts, future_seconds)
+         }
+         StakingInstruction::ClaimMany { count } => process_claim_many(program_id, accounts, count),
+         StakingInstruction::SetAnalyticsMode { enabled } => process_set_analytics_mode(program_id, accounts, enabled),
+         StakingInstruction::SetReferralAuthority { referral_authority } => {
+             process_set_referral_authority(program_id, accounts, referral_authority)
+         }
+         StakingInstruction::StakeFor { amount, beneficiary } => {
+             stake_tokens(program_id, accounts, amount, false, false, Some(beneficiary), false)
+         }
+         StakingInstruction::SetMinimumVaultBuffer { buffer } => process_set_minimum_vault_buffer(program_id, accounts, buffer),
+         StakingInstruction::SetRedistributePenalties { enabled } => {
+             process_set_redistribute_penalties(program_id, accounts, enabled)
+         }
+         StakingInstruction::CreatePoolWithSchedule { reward_rate, min_lock_period, pool_id, epochs } => {
+             process_create_pool_with_schedule(program_id, accounts, reward_rate, min_lock_period, pool_id, &epochs)
+         }
+         StakingInstruction::ClaimUpTo { max_amount } => process_claim(program_id, accounts, Some(max_amount)),
+         StakingInstruction::InitializeCleanupAuthority { authority } => {
+             process_initialize_cleanup_authority(program_id, accounts, authority)
+         }
+         StakingInstruction::CloseInactivePool { pool_age_threshold_seconds } => {
+             process_close_inactive_pool(program_id, accounts, pool_age_threshold_seconds)
+         }
+         StakingInstruction::SetPoolState { state } => {
+             let (soft_paused, hard_paused) = match state {
+                 PoolState::Active => (false, false),
+                 PoolState::DepositsPaused => (true, false),
+                 PoolState::FullyPaused => (true, true),
+             };
+             process_set_pause_flags(program_id, accounts, "SetPoolState", soft_paused, hard_paused)
+         }
+         StakingInstruction::SetUserLockOverride { user_wallet, lock_end_time } => {
+             process_set_user_lock_override(program_id, accounts, user_wallet, lock_end_time)
+         }
+         StakingInstruction::SetSharesMode { enabled } => process_set_shares_mode(program_id, accounts, enabled),
+         StakingInstruction::RotateVault { new_vault_ata } => process_rotate_vault(program_id, accounts, new_vault_ata),
+         StakingInstruction::InitializeUserBatch { user_wallets } => {
+             process_initialize_user_batch(program_id, accounts, &user_wallets)
+         }
+         StakingInstruction::SetFunder { funder } => process_set_pool_role(program_id, accounts, PoolRole::Funder, funder),
+         StakingInstruction::SetPauser { pauser } => process_set_pool_role(program_id, accounts, PoolRole::Pauser, pauser),
+         StakingInstruction::SetPoolDescription { description } => {
+             process_set_pool_description(program_id, accounts, &description)
+         }
+         StakingInstruction::SetMintOnClaim { enabled } => process_set_mint_on_claim(program_id, accounts, enabled),
+         StakingInstruction::QueryPendingReward => process_query_pending_reward(program_id, accounts),
+         StakingInstruction::SetClaimCooldownOverride { user_wallet, bypass_until } => {
+             process_set_claim_cooldown_override(program_id, accounts, user_wallet, bypass_until)
+         }
+         StakingInstruction::SetRequireCanonicalAta { enabled } => {
+             process_set_require_canonical_ata(program_id, accounts, enabled)
+         }
+         StakingInstruction::RecordStakeOnBehalf { user_wallet, amount } => {
+             process_record_stake_on_behalf(program_id, accounts, user_wallet, amount)
+         }
+         StakingInstruction::ConvertSponsoredStake => process_convert_sponsored_stake(program_id, accounts),
+         StakingInstruction::InitializeGlobalTVLTracker { cap } => process_initialize_global_tvl_tracker(program_id, accounts, cap),
+         StakingInstruction::SetGlobalStakeCap { cap } => process_set_global_stake_cap(program_id, accounts, cap),
+         StakingInstruction::SetAccessSigner { access_signer } => process_set_access_signer(program_id, accounts, access_signer),
+         StakingInstruction::StakeWithPermit { amount, expiry } => process_stake_with_access_permit(program_id, accounts, amount, expiry),
+         StakingInstruction::SetStakePositionTransferable { transferable } => {
+             process_set_stake_position_transferable(program_id, accounts, transferable)
+         }
+         StakingInstruction::SetPoolShared { sibling_vault } => process_set_pool_shared(program_id, accounts, sibling_vault),
+         StakingInstruction::SoftPause => process_set_pause_flags(program_id, accounts, "SoftPause", true, false),
+         StakingInstruction::HardPause => process_set_pause_flags(program_id, accounts, "HardPause", true, true),
+         StakingInstruction::CreateBoostCampaign { campaign_id, boost_bps, start_time, end_time } => {
+             process_create_boost_campaign(program_id, accounts, campaign_id, boost_bps, start_time, end_time)
+         }
+         StakingInstruction::CloseBoostCampaign => process_close_boost_campaign(program_id, accounts),
+         StakingInstruction::MigrateAccount => process_migrate_account(program_id, accounts),
+         StakingInstruction::TransferStakePosition => process_transfer_stake_position(program_id, accounts),
+     }
+ }
+
+ /// Returns `$err` unless `$cond` holds, logging the account role that failed and why,
+ /// e.g. `validate!(ata.mint == *mint_ai.key, StakingError::InvalidMint, "user_ata: wrong mint")`
+ macro_rules! validate {
+     ($cond:expr, $err:expr, $($msg:tt)+) => {
+         if !$cond {
+             msg!($($msg)+);
+             return Err($err.into());
+         }
+     };
+ }
+
+ /// Success-path logging, compiled out without the `log` feature; failures use `msg!` directly.
+ /// Keys after `;` go through `Pubkey::log` (a cheap syscall) rather than base58 formatting,
+ /// e.g. `log!("Staked: {} tokens by", amount; user.key)`
+ macro_rules! log {
+     ($($arg:expr),+ $(; $($key:expr),+)?) => {
+         #[cfg(feature = "log")]
+         {
+             msg!($($arg),+);
+             $($($key.log();)+)?
+         }
+         #[cfg(not(feature = "log"))]
+         {
+             let _ = ($(&$arg,)+ $($(&$key,)+)?);
+         }
+     };
+ }
+
+ /// Rejects an account list that is too short or too long for `instruction` before any role is read
+ fn check_account_count(instruction: &str, accounts: &[AccountInfo], min: usize, max: usize) -> ProgramResult {
+     let got = accounts.len();
+     validate!(got >= min, ProgramError::NotEnoughAccountKeys, "{}: expected at least {} accounts, got {}", instruction, min, got);
+     validate!(got <= max, StakingError::TooManyAccounts, "{}: expected at most {} accounts, got {}", instruction, max, got);
+     Ok(())
+ }
+
+ /// Pool PDA and bump for (`mint`, `pool_id`): seeds `[SEED_POOL, mint, pool_id LE]`, or `[SEED_POOL, mint]`
+ /// for pool_id 0 so pools created before pool ids keep their address
+ pub fn find_pool_pda(program_id: &Pubkey, mint: &Pubkey, pool_id: u64) -> (Pubkey, u8) {
+     if pool_id == 0 {
+         Pubkey::find_program_address(&[SEED_POOL, mint.as_ref()], program_id)
+     } else {
+         Pubkey::find_program_address(&[SEED_POOL, mint.as_ref(), &pool_id.to_le_bytes()], program_id)
+     }
+ }
+
+ /// User stake PDA and bump for (`pool`, `owner`): seeds `[SEED_USER, pool, owner]`
+ pub fn find_user_pda(program_id: &Pubkey, pool: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_USER, pool.as_ref(), owner.as_ref()], program_id)
+ }
+
+ /// Incentive campaign PDA and bump for (`pool`, `campaign_id`): seeds `[SEED_CAMPAIGN, pool, campaign_id LE]`
+ pub fn find_campaign_pda(program_id: &Pubkey, pool: &Pubkey, campaign_id: u64) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_CAMPAIGN, pool.as_ref(), &campaign_id.to_le_bytes()], program_id)
+ }
+
+ /// Vesting config PDA and bump for `pool`: seeds `[SEED_VESTING, pool]`
+ pub fn find_vesting_pda(program_id: &Pubkey, pool: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_VESTING, pool.as_ref()], program_id)
+ }
+
+ /// Mint PDA and bump of a legacy position token for `user_stake`: seeds `[SEED_POSITION, user_stake]`
+ pub fn find_position_mint_pda(program_id: &Pubkey, user_stake: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_POSITION, user_stake.as_ref()], program_id)
+ }
+
+ /// Pool registry entry PDA and bump for `index`: seeds `[SEED_REGISTRY, index LE]`
+ pub fn find_registry_pda(program_id: &Pubkey, index: u32) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_REGISTRY, &index.to_le_bytes()], program_id)
+ }
+
+ /// Permit nonce PDA and bump for (`pool`, `owner`): seeds `[SEED_PERMIT_NONCE, pool, owner]`
+ pub fn find_permit_nonce_pda(program_id: &Pubkey, pool: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_PERMIT_NONCE, pool.as_ref(), owner.as_ref()], program_id)
+ }
+
+ /// Lock tier config PDA and bump for `pool`: seeds `[SEED_LOCK_TIER, pool]`
+ pub fn find_lock_tier_pda(program_id: &Pubkey, pool: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_LOCK_TIER, pool.as_ref()], program_id)
+ }
+
+ /// Pool stats PDA and bump for `pool`: seeds `[SEED_POOL_STATS, pool]`
+ pub fn find_pool_stats_pda(program_id: &Pubkey, pool: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_POOL_STATS, pool.as_ref()], program_id)
+ }
+
+ /// Cleanup authority PDA and bump: seeds `[SEED_CLEANUP_AUTHORITY]`
+ pub fn find_cleanup_authority_pda(program_id: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_CLEANUP_AUTHORITY], program_id)
+ }
+
+ /// Pool roles PDA and bump for `pool`: seeds `[SEED_POOL_ROLES, pool]`
+ pub fn find_pool_roles_pda(program_id: &Pubkey, pool: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_POOL_ROLES, pool.as_ref()], program_id)
+ }
+
+ /// Pool description PDA and bump for `pool`: seeds `[SEED_POOL_DESCRIPTION, pool]`
+ pub fn find_pool_description_pda(program_id: &Pubkey, pool: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_POOL_DESCRIPTION, pool.as_ref()], program_id)
+ }
+
+ /// Rent payer record PDA and bump for `account`: seeds `[SEED_RENT_PAYER, account]`
+ pub fn find_rent_payer_pda(program_id: &Pubkey, account: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_RENT_PAYER, account.as_ref()], program_id)
+ }
+
+ /// Seeded vault PDA and bump for `pool`: seeds `[SEED_VAULT, pool]`
+ pub fn find_vault_pda(program_id: &Pubkey, pool: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_VAULT, pool.as_ref()], program_id)
+ }
+
+ /// Claim cooldown bypass PDA and bump for `user_stake`: seeds `[SEED_CLAIM_COOLDOWN_BYPASS, user_stake]`
+ pub fn find_claim_cooldown_bypass_pda(program_id: &Pubkey, user_stake: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_CLAIM_COOLDOWN_BYPASS, user_stake.as_ref()], program_id)
+ }
+
+ /// Global TVL tracker PDA and bump: seeds `[SEED_GLOBAL_TVL]`
+ pub fn find_global_tvl_tracker_pda(program_id: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_GLOBAL_TVL], program_id)
+ }
+
+ /// Boost campaign PDA and bump for (`pool`, `campaign_id`): seeds `[SEED_BOOST_CAMPAIGN, pool, campaign_id LE]`
+ pub fn find_boost_campaign_pda(program_id: &Pubkey, pool: &Pubkey, campaign_id: u64) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_BOOST_CAMPAIGN, pool.as_ref(), &campaign_id.to_le_bytes()], program_id)
+ }
+
+ /// What a wallet signs to authorize StakeWithPermitSignature:
+ /// `sha256(user_wallet || pool || amount LE || nonce LE || "stake_permit")`
+ pub fn stake_permit_message(user_wallet: &Pubkey, pool: &Pubkey, amount: u64, nonce: u64) -> [u8; 32] {
+     solana_program::hash::hashv(&[
+         user_wallet.as_ref(),
+         pool.as_ref(),
+         &amount.to_le_bytes(),
+         &nonce.to_le_bytes(),
+         b"stake_permit",
+     ])
+     .to_bytes()
+ }
+
+ /// What a pool's access_signer signs to let `user_wallet` open a position with StakeWithPermit:
+ /// `sha256(pool || user_wallet || expiry LE || "stake_access")`
+ pub fn stake_access_permit_message(pool: &Pubkey, user_wallet: &Pubkey, expiry: i64) -> [u8; 32] {
+     solana_program::hash::hashv(&[pool.as_ref(), user_wallet.as_ref(), &expiry.to_le_bytes(), b"stake_access"]).to_bytes()
+ }
+
+ /// Protocol config PDA and bump: seeds `[SEED_PROTOCOL_CONFIG]`
+ pub fn find_protocol_config_pda(program_id: &Pubkey) -> (Pubkey, u8) {
+     Pubkey::find_program_address(&[SEED_PROTOCOL_CONFIG], program_id)
+ }
+
+ /// Pool PDA for (`mint`, `pool_id`) under the declared program id
+ pub fn get_pool_address(mint: &Pubkey, pool_id: u64) -> Pubkey {
+     get_pool_address_with_program_id(mint, pool_id, &id())
+ }
+
+ pub fn get_pool_address_with_program_id(mint: &Pubkey, pool_id: u64, program_id: &Pubkey) -> Pubkey {
+     find_pool_pda(program_id, mint, pool_id).0
+ }
+
+ /// User stake PDA for (`pool`, `owner`) under the declared program id
+ pub fn get_user_stake_address(pool: &Pubkey, owner: &Pubkey) -> Pubkey {
+     get_user_stake_address_with_program_id(pool, owner, &id())
+ }
+
+ pub fn get_user_stake_address_with_program_id(pool: &Pubkey, owner: &Pubkey, program_id: &Pubkey) -> Pubkey {
+     find_user_pda(program_id, pool, owner).0
+ }
+
+ /// Re-derive a user stake PDA from its stored bump; a single hash instead of a bump search
+ fn user_pda_with_bump(program_id: &Pubkey, pool: &Pubkey, owner: &Pubkey, bump: u8) -> Result<Pubkey, ProgramError> {
+     Pubkey::create_program_address(&[SEED_USER, pool.as_ref(), owner.as_ref(), &[bump]], program_id)
+         .map_err(|_| ProgramError::InvalidArgument)
+ }
+
+ /// At InitializePool the vault must be the pool PDA's canonical ATA for `mint`, not just any token account
+ /// it owns. Later instructions check against `pool.vault`, which only RotateVault moves.
+ /// A new pool's vault is either its canonical ATA (None) or its seeded vault PDA (Some(bump))
+ fn check_vault(program_id: &Pubkey, pool_key: &Pubkey, mint: &Pubkey, vault_ai: &AccountInfo) -> Result<Option<u8>, ProgramError> {
+     if *vault_ai.key == spl_associated_token_account::get_associated_token_address(pool_key, mint) {
+         return Ok(None);
+     }
+     let (seeded, bump) = find_vault_pda(program_id, pool_key);
+     validate!(*vault_ai.key == seeded, StakingError::InvalidVault, "vault: neither the pool's canonical ATA nor its seeded vault");
+     Ok(Some(bump))
+ }
+
+ /// Under require_canonical_ata, `user_ata` must be `owner`'s ATA for the pool mint; otherwise any token
+ /// account of theirs for the mint passes, as the callers' owner and mint checks already ensure
+ fn check_user_token_account(pool: &StakingPool, owner: &Pubkey, user_ata: &AccountInfo) -> ProgramResult {
+     if pool.require_canonical_ata != 0 {
+         let expected = spl_associated_token_account::get_associated_token_address(owner, &pool.mint);
+         validate!(*user_ata.key == expected, StakingError::NonCanonicalTokenAccount, "user_ata: not the user's canonical ATA");
+     }
+     Ok(())
+ }
+
+ /// Decimals of an SPL Token mint, needed for `transfer_checked`
+ fn mint_decimals(mint_ai: &AccountInfo) -> Result<u8, ProgramError> {
+     validate!(*mint_ai.owner == spl_token::id(), StakingError::InvalidMint, "mint: not owned by the token program");
+     let mint = spl_token::state::Mint::unpack(&mint_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     Ok(mint.decimals)
+ }
+
+ /// Fails with `PoolPaused` if the GlobalPause PDA or the ProtocolConfig PDA is among `accounts`
+ /// and is set. Each PDA is recognised by re-deriving its address from the stored bump.
+ fn check_global_pause(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     for account in accounts {
+         if account.owner != program_id {
+             continue;
+         }
+         let data = account.try_borrow_data()?;
+         let (seed, bump, paused) = match data.len() {
+             GLOBAL_PAUSE_SIZE => {
+                 let global = GlobalPause::load(&data)?;
+                 (SEED_GLOBAL_PAUSE, global.bump, global.paused)
+             }
+             PROTOCOL_CONFIG_SIZE => {
+                 let config = ProtocolConfig::load(&data)?;
+                 (SEED_PROTOCOL_CONFIG, config.bump, config.global_pause)
+             }
+             _ => continue,
+         };
+         let expected = Pubkey::create_program_address(&[seed, &[bump]], program_id);
+         if expected.as_ref() == Ok(account.key) && paused != 0 {
+             return Err(StakingError::PoolPaused.into());
+         }
+     }
+     Ok(())
+ }
+
+ /// Fails with `PoolPaused` if the pool's pause flags stop this instruction: a soft pause stops deposits,
+ /// a hard pause everything
+ fn check_pool_state(pool: &StakingPool, deposit: bool) -> ProgramResult {
+     let open = pool.hard_paused == 0 && !(deposit && pool.soft_paused != 0);
+     validate!(open, StakingError::PoolPaused, "pool_pda: paused by its authority");
+     Ok(())
+ }
+
+ /// A withdrawal-fee treasury must be a token account for the pool's mint
+ fn check_treasury(treasury_ai: &AccountInfo, mint: &Pubkey) -> ProgramResult {
+     validate!(
+         *treasury_ai.owner == spl_token::id(),
+         StakingError::InvalidTreasury,
+         "protocol_treasury_ata: not a token account"
+     );
+     let treasury = spl_token::state::Account::unpack(&treasury_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     validate!(treasury.mint == *mint, StakingError::InvalidMint, "protocol_treasury_ata: wrong mint");
+     Ok(())
+ }
+
+ /// Load the ProtocolConfig at `config_ai`, which must be the canonical PDA.
+ /// `None` while it has not been initialized, so deployments without one keep open pool creation.
+ fn load_protocol_config(program_id: &Pubkey, config_ai: &AccountInfo) -> Result<Option<ProtocolConfig>, ProgramError> {
+     let (expected, _) = find_protocol_config_pda(program_id);
+     validate!(*config_ai.key == expected, ProgramError::InvalidArgument, "protocol_config_pda: derivation mismatch");
+     if config_ai.data_is_empty() {
+         return Ok(None);
+     }
+     validate!(config_ai.owner == program_id, StakingError::InvalidOwner, "protocol_config_pda: not owned by this program");
+     let data = config_ai.try_borrow_data()?;
+     Ok(Some(*ProtocolConfig::load(&data)?))
+ }
+
+ /// The pool and user stake slots must hold this program's accounts of the right size,
+ /// so a swapped pair is named in the logs instead of failing deep inside a load
+ fn check_program_accounts(program_id: &Pubkey, pool_ai: &AccountInfo, user_stake_ai: &AccountInfo) -> ProgramResult {
+     validate!(
+         pool_ai.owner == program_id && pool_ai.data_len() == STAKING_POOL_SIZE,
+         ProgramError::InvalidAccountData,
+         "pool_pda: not a staking pool"
+     );
+     validate!(
+         user_stake_ai.owner == program_id && user_stake_ai.data_len() == USER_STAKE_SIZE,
+         ProgramError::InvalidAccountData,
+         "user_stake_pda: not a user stake account"
+     );
+     Ok(())
+ }
+
+ /// Owner and size checks shared by the typed loaders below
+ fn check_state_account(account: &AccountInfo, program_id: &Pubkey, len: usize, role: &str) -> ProgramResult {
+     validate!(account.owner == program_id, StakingError::InvalidOwner, "{}: not owned by this program", role);
+     validate!(account.data_len() == len, ProgramError::InvalidAccountData, "{}: wrong size", role);
+     Ok(())
+ }
+
+ impl StakingPool {
+     /// Copy of the pool held by `account`, once it is known to be this program's,
+     /// the right size and initialized; write changes back with `save`
+     pub fn load_checked(account: &AccountInfo, program_id: &Pubkey) -> Result<Self, ProgramError> {
+         check_state_account(account, program_id, STAKING_POOL_SIZE, "pool_pda")?;
+         let pool = Self::unpack_unchecked(&account.try_borrow_data()?)?;
+         validate!(pool.is_initialized(), ProgramError::UninitializedAccount, "pool_pda: not initialized");
+         Ok(pool)
+     }
+
+     /// Write the pool back to `account`
+     pub fn save(&self, account: &AccountInfo) -> ProgramResult {
+         Self::pack(*self, &mut account.try_borrow_mut_data()?)
+     }
+ }
+
+ impl UserStake {
+     /// Copy of the user stake held by `account`, once it is known to be this program's,
+     /// the right size and initialized; write changes back with `save`
+     pub fn load_checked(account: &AccountInfo, program_id: &Pubkey) -> Result<Self, ProgramError> {
+         check_state_account(account, program_id, USER_STAKE_SIZE, "user_stake_pda")?;
+         let us = Self::unpack_unchecked(&account.try_borrow_data()?)?;
+         validate!(us.is_initialized(), ProgramError::UninitializedAccount, "user_stake_pda: not initialized");
+         Ok(us)
+     }
+
+     /// Write the user stake back to `account`
+     pub fn save(&self, account: &AccountInfo) -> ProgramResult {
+         Self::pack(*self, &mut account.try_borrow_mut_data()?)
+     }
+ }
+
+ /// Routine pool work the authority can delegate through the PoolRoles PDA
+ #[derive(Debug, Clone, Copy)]
+ enum PoolRole {
+     Funder,
+     Pauser,
+ }
+
+ impl PoolRole {
+     /// Role name used in logs
+     fn name(self) -> &'static str {
+         match self {
+             PoolRole::Funder => "funder",
+             PoolRole::Pauser => "pauser",
+         }
+     }
+ }
+
+ /// Checks `signer` holds `role` on the pool: the authority until roles are set up, then whoever
+ /// PoolRoles names, in which case `roles_ai` must be the pool's roles PDA
+ fn check_pool_role(
+     program_id: &Pubkey,
+     pool_key: &Pubkey,
+     pool: &StakingPool,
+     roles_ai: Option<&AccountInfo>,
+     role: PoolRole,
+     signer: &Pubkey,
+ ) -> ProgramResult {
+     if pool.has_pool_roles == 0 {
+         validate!(pool.authority == *signer, StakingError::Unauthorized, "authority: not the pool authority");
+         return Ok(());
+     }
+     let Some(roles_ai) = roles_ai else {
+         msg!("pool_roles_pda: required once roles are set");
+         return Err(ProgramError::NotEnoughAccountKeys);
+     };
+     validate!(roles_ai.owner == program_id, StakingError::InvalidOwner, "pool_roles_pda: not owned by this program");
+     let data = roles_ai.try_borrow_data()?;
+     let roles = PoolRoles::load(&data)?;
+     let expected = Pubkey::create_program_address(&[SEED_POOL_ROLES, pool_key.as_ref(), &[roles.bump]], program_id);
+     validate!(expected == Ok(*roles_ai.key), ProgramError::InvalidArgument, "pool_roles_pda: derivation mismatch");
+     let holder = match role {
+         PoolRole::Funder => roles.funder,
+         PoolRole::Pauser => roles.pauser,
+     };
+     validate!(holder == *signer, StakingError::Unauthorized, "{}: not the pool's {}", role.name(), role.name());
+     Ok(())
+ }
+
+ /// Rejects a substituted program or sysvar account before we hand it to a CPI
+ fn check_known_id(account: &AccountInfo, expected: &Pubkey, role: &str) -> ProgramResult {
+     validate!(account.key == expected, ProgramError::IncorrectProgramId, "{}: expected {}", role, expected);
+     Ok(())
+ }
+
+ // -------------------------------------------------------------------------------------
+ // Instruction processors
+ // -------------------------------------------------------------------------------------
+
+ fn process_initialize_pool(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     reward_rate: u64,
+     min_lock_period: i64,
+     pool_id: u64,
+     allow_freezable_mint: bool,
+ ) -> ProgramResult {
+     check_account_count("InitializePool", accounts, 9, 12)?;
+     let account_info_iter = &mut accounts.iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let mint_ai = next_account_info(account_info_iter)?; // mint
+     let vault_ai = next_account_info(account_info_iter)?; // writable ATA
+     let token_program_ai = next_account_info(account_info_iter)?;
+     let ata_program_ai = next_account_info(account_info_iter)?;
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+     // The config and registry entry travel together and are told apart from a bare treasury by address
+     let (config_pda, _) = find_protocol_config_pda(program_id);
+     let mut trailing = account_info_iter.as_slice();
+     let config_accounts = match trailing {
+         [config_ai, registry_ai, rest @ ..] if *config_ai.key == config_pda => {
+             trailing = rest;
+             Some((config_ai, registry_ai))
+         }
+         _ => None,
+     };
+     let treasury_ai = trailing.first();
+
+     // Signer checks
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+
+     // A client retrying after a timeout may resend a transaction that already landed: the same
+     // authority and mint is a no-op, anything else is someone else's pool
+     if !pool_ai.data_is_empty() {
+         let (expected_pool, _) = find_pool_pda(program_id, mint_ai.key, pool_id);
+         validate!(*pool_ai.key == expected_pool, ProgramError::InvalidArgument, "pool_pda: derivation mismatch");
+         let pool = StakingPool::load_checked(pool_ai, program_id)?;
+         validate!(
+             pool.authority == *authority.key && pool.mint == *mint_ai.key,
+             ProgramError::AccountAlreadyInitialized,
+             "pool_pda: already initialized"
+         );
+         log!("Pool already initialized"; pool_ai.key);
+         return Ok(());
+     }
+
+     // The pool's authority is its creator; once pool creation is closed it must be allowlisted
+     let config = match config_accounts {
+         Some((config_ai, _)) => load_protocol_config(program_id, config_ai)?,
+         None => None,
+     };
+     if let Some(config) = &config {
+         validate!(config.may_create_pool(authority.key), StakingError::Unauthorized, "authority: not allowed to create pools");
+     }
+     // Pools register in creation order; two pools racing for one index fail the second, which retries
+     let registry_index = config.map_or(0, |c| c.pool_count.get());
+     let (expected_registry, registry_bump) = find_registry_pda(program_id, registry_index);
+     if let Some((_, registry_ai)) = config_accounts {
+         validate!(*registry_ai.key == expected_registry, ProgramError::InvalidArgument, "registry_entry_pda: derivation mismatch");
+     }
+
+     // Derive expected pool PDA
+     let (expected_pool, bump) = find_pool_pda(program_id, mint_ai.key, pool_id);
+     validate!(*pool_ai.key == expected_pool, ProgramError::InvalidArgument, "pool_pda: derivation mismatch");
+     let seeded_vault_bump = check_vault(program_id, pool_ai.key, mint_ai.key, vault_ai)?;
+
+     // Create pool PDA account with program-derived signature
+     let rent = Rent::from_account_info(rent_sysvar_ai)?;
+     let required_lamports = rent.minimum_balance(STAKING_POOL_SIZE);
+     let create_ix = solana_program::system_instruction::create_account(
+         payer.key,
+         pool_ai.key,
+         required_lamports,
+         STAKING_POOL_SIZE as u64,
+         program_id,
+     );
+     let pool_id_bytes = pool_id.to_le_bytes();
+     let mut seeds: Vec<&[u8]> = vec![SEED_POOL, mint_ai.key.as_ref()];
+     if pool_id != 0 {
+         seeds.push(&pool_id_bytes);
+     }
+     let bump_seed = [bump];
+     seeds.push(&bump_seed);
+     invoke_signed(
+         &create_ix,
+         &[payer.clone(), pool_ai.clone(), system_program_ai.clone()],
+         &[&seeds],
+     )?;
+
+     // Sanity: rent exempt
+     if !rent.is_exempt(pool_ai.lamports(), pool_ai.data_len()) {
+         return Err(StakingError::NotRentExempt.into());
+     }
+
+     // Create the vault owned by pool PDA if not exists; a seeded vault is created here directly, without
+     // the ATA program
+     if vault_ai.data_is_empty() {
+         if let Some(vault_bump) = seeded_vault_bump {
+             let create_ix = solana_program::system_instruction::create_account(
+                 payer.key,
+                 vault_ai.key,
+                 rent.minimum_balance(spl_token::state::Account::LEN),
+                 spl_token::state::Account::LEN as u64,
+                 token_program_ai.key,
+             );
+             invoke_signed(
+                 &create_ix,
+                 &[payer.clone(), vault_ai.clone(), system_program_ai.clone()],
+                 &[&[SEED_VAULT, pool_ai.key.as_ref(), &[vault_bump]]],
+             )?;
+             let init_ix = token_ix::initialize_account3(token_program_ai.key, vault_ai.key, mint_ai.key, pool_ai.key)?;
+             invoke(&init_ix, &[vault_ai.clone(), mint_ai.clone(), token_program_ai.clone()])?;
+         } else {
+             check_known_id(ata_program_ai, &spl_associated_token_account::id(), "ata_program")?;
+             let create_ata_ix = ata_ix::create_associated_token_account(
+                 payer.key,
+                 pool_ai.key,
+                 mint_ai.key,
+                 token_program_ai.key,
+             );
+             invoke(
+                 &create_ata_ix,
+                 &[
+                     payer.clone(),
+                     vault_ai.clone(),
+                     pool_ai.clone(),
+                     mint_ai.clone(),
+                     system_program_ai.clone(),
+                     token_program_ai.clone(),
+                     ata_program_ai.clone(),
+                     rent_sysvar_ai.clone(),
+                 ],
+             )?;
+         }
+     }
+
+     // Persist pool state
+     {
+         // Verify the vault is indeed owned by pool PDA and for the given mint
+         let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
+             .map_err(|_| ProgramError::InvalidAccountData)?;
+         validate!(vault_data.owner == *pool_ai.key, StakingError::InvalidOwner, "vault: not owned by pool_pda");
+         validate!(vault_data.mint == *mint_ai.key, StakingError::InvalidMint, "vault: wrong mint");
+
+         // The mint's freeze authority could freeze the vault and with it every withdrawal, so the
+         // creator must accept that explicitly
+         let mint = spl_token::state::Mint::unpack(&mint_ai.try_borrow_data()?).map_err(|_| ProgramError::InvalidAccountData)?;
+         let freezable = mint.freeze_authority.is_some();
+         validate!(!freezable || allow_freezable_mint, StakingError::FreezableMintNotAllowed, "mint: has a freeze authority");
+
+         let mut pool = StakingPool::new(*authority.key, *vault_ai.key, *mint_ai.key, reward_rate, min_lock_period, bump, pool_id);
+         pool.created_at = Clock::get()?.unix_timestamp.into();
+         pool.freezable_mint = u8::from(freezable);
+         if let Some(treasury_ai) = treasury_ai {
+             check_treasury(treasury_ai, mint_ai.key)?;
+             pool.protocol_treasury_ata = *treasury_ai.key;
+             pool.withdraw_fee_bps = config.map_or(0, |c| c.default_fee_bps.get()).into();
+         }
+         pool.save(pool_ai)?;
+     }
+
+     // Without a ProtocolConfig there is no counter to allocate from, so the pool goes unregistered
+     if let (Some(_), Some((config_ai, registry_ai))) = (config, config_accounts) {
+         validate!(registry_ai.data_is_empty(), ProgramError::AccountAlreadyInitialized, "registry_entry_pda: already initialized");
+         let create_ix = solana_program::system_instruction::create_account(
+             payer.key,
+             registry_ai.key,
+             rent.minimum_balance(REGISTRY_ENTRY_SIZE),
+             REGISTRY_ENTRY_SIZE as u64,
+             program_id,
+         );
+         invoke_signed(
+             &create_ix,
+             &[payer.clone(), registry_ai.clone(), system_program_ai.clone()],
+             &[&[SEED_REGISTRY, &registry_index.to_le_bytes(), &[registry_bump]]],
+         )?;
+         let mut registry_data = registry_ai.try_borrow_mut_data()?;
+         *RegistryEntry::load_mut(&mut registry_data)? = RegistryEntry {
+             pool: *pool_ai.key,
+             mint: *mint_ai.key,
+             index: registry_index.into(),
+             bump: registry_bump,
+             _reserved: [0u8; 27],
+         };
+         let mut config_data = config_ai.try_borrow_mut_data()?;
+         let config = ProtocolConfig::load_mut(&mut config_data)?;
+         config.pool_count = registry_index.checked_add(1).ok_or(StakingError::Overflow)?.into();
+         log!("Pool registered at index {}:", registry_index; pool_ai.key);
+     }
+
+     log!(
+         "Pool initialized. Rate={}, Lock={}s, ProtocolVersion={}, Authority:",
+         reward_rate, min_lock_period, INITIAL_PROTOCOL_VERSION;
+         authority.key
+     );
+     Ok(())
+ }
+
+ fn process_update_config(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     new_reward_rate: Option<u64>,
+     new_min_lock_period: Option<i64>,
+ ) -> ProgramResult {
+     check_account_count("UpdateConfig", accounts, 2, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     if let Some(rr) = new_reward_rate {
+         if rr > pool.max_reward_rate.get() {
+             return Err(StakingError::InvalidConfig.into());
+         }
+         if pool.needs_governance_for_rate(rr) {
+             check_governance_signer(&pool, account_info_iter.next())?;
+         }
+         pool.reward_rate = rr.into();
+     }
+     if let Some(lp) = new_min_lock_period {
+         if !pool.lock_within_max(lp) {
+             return Err(StakingError::InvalidConfig.into());
+         }
+         pool.min_lock_period = lp.into();
+     }
+     pool.save(pool_ai)?;
+
+     log!(
+         "Config updated: reward_rate={:?}, min_lock_period={:?}",
+         new_reward_rate, new_min_lock_period
+     );
+     Ok(())
+ }
+
+ /// Unauthorized unless `governance` is the pool's governance address and signed
+ fn check_governance_signer(pool: &StakingPool, governance: Option<&AccountInfo>) -> ProgramResult {
+     let governance = governance.ok_or_else(|| {
+         msg!("governance: co-signature required");
+         StakingError::Unauthorized
+     })?;
+     validate!(*governance.key == pool.governance_address, StakingError::Unauthorized, "governance: not the pool's governance");
+     validate!(governance.is_signer, StakingError::Unauthorized, "governance: missing signature");
+     Ok(())
+ }
+
+ fn process_initialize_user(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("InitializeUser", accounts, 6, 7)?;
+     let account_info_iter = &mut accounts.iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let user = next_account_info(account_info_iter)?;  // signer
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+     let rent_payer_ai = account_info_iter.next(); // writable
+
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+     validate!(user.is_signer, StakingError::Unauthorized, "user: missing signature");
+     // Safe to retry: an account already set up for this user and pool is left as it is
+     if user_stake_initialized(program_id, user_stake_ai, user.key, pool_ai.key)? {
+         log!("User stake already initialized"; user_stake_ai.key);
+         return Ok(());
+     }
+     if rent_payer_ai.is_some() {
+         // Only the wallet whose lamports fund the stake PDA here may claim them back
+         validate!(payer.key != user.key, ProgramError::InvalidArgument, "rent_payer_pda: only for a sponsored account");
+     }
+
+     create_user_stake(program_id, payer, user.key, pool_ai, user_stake_ai, system_program_ai, rent_sysvar_ai)?;
+     if let Some(rent_payer_ai) = rent_payer_ai {
+         create_rent_payer(program_id, payer, user_stake_ai.key, rent_payer_ai, system_program_ai, rent_sysvar_ai)?;
+         let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+         us.rent_sponsored = 1;
+         us.save(user_stake_ai)?;
+         log!("Rent sponsored by"; payer.key);
+     }
+
+     log!("User stake initialized for"; user.key);
+     Ok(())
+ }
+
+ /// Whether `user_stake_ai` already holds the stake account of (owner, pool); an account holding
+ /// anything else is `AccountAlreadyInitialized`, and an empty one still needs creating.
+ fn user_stake_initialized(program_id: &Pubkey, user_stake_ai: &AccountInfo, owner: &Pubkey, pool: &Pubkey) -> Result<bool, ProgramError> {
+     if user_stake_ai.data_is_empty() {
+         return Ok(false);
+     }
+     let us = UserStake::load_checked(user_stake_ai, program_id).map_err(|_| ProgramError::AccountAlreadyInitialized)?;
+     validate!(
+         us.owner == *owner && us.pool == *pool,
+         ProgramError::AccountAlreadyInitialized,
+         "user_stake_pda: initialized for another user or pool"
+     );
+     Ok(true)
+ }
+
+ /// Creates and initializes the user stake PDA for (pool, user), paid by `payer`.
+ /// Shared by InitializeUser and the lazy path in Stake.
+ fn create_user_stake<'a>(
+     program_id: &Pubkey,
+     payer: &AccountInfo<'a>,
+     owner: &Pubkey,
+     pool_ai: &AccountInfo<'a>,
+     user_stake_ai: &AccountInfo<'a>,
+     system_program_ai: &AccountInfo<'a>,
+     rent_sysvar_ai: &AccountInfo<'a>,
+ ) -> ProgramResult {
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+
+     // Derive expected user stake PDA
+     let (expected_user_pda, user_bump) = find_user_pda(program_id, pool_ai.key, owner);
+     validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+
+     // Create user stake PDA account using program-derived signature
+     let rent = Rent::from_account_info(rent_sysvar_ai)?;
+     let required_lamports = rent.minimum_balance(USER_STAKE_SIZE);
+     let create_ix = solana_program::system_instruction::create_account(
+         payer.key,
+         user_stake_ai.key,
+         required_lamports,
+         USER_STAKE_SIZE as u64,
+         program_id,
+     );
+     let seeds: &[&[u8]] = &[SEED_USER, pool_ai.key.as_ref(), owner.as_ref(), &[user_bump]];
+     invoke_signed(
+         &create_ix,
+         &[payer.clone(), user_stake_ai.clone(), system_program_ai.clone()],
+         &[seeds],
+     )?;
+     if !rent.is_exempt(user_stake_ai.lamports(), user_stake_ai.data_len()) {
+         return Err(StakingError::NotRentExempt.into());
+     }
+
+     UserStake {
+         owner: *owner,
+         pool: *pool_ai.key,
+         bump: user_bump,
+         individual_multiplier_bps: MULTIPLIER_BPS_DENOMINATOR.into(),
+         ..UserStake::default()
+     }
+     .save(user_stake_ai)
+ }
+
+ /// Creates the RentPayer record for `account`, naming and paid by `payer`
+ fn create_rent_payer<'a>(
+     program_id: &Pubkey,
+     payer: &AccountInfo<'a>,
+     account: &Pubkey,
+     rent_payer_ai: &AccountInfo<'a>,
+     system_program_ai: &AccountInfo<'a>,
+     rent_sysvar_ai: &AccountInfo<'a>,
+ ) -> ProgramResult {
+     let (expected, bump) = find_rent_payer_pda(program_id, account);
+     validate!(*rent_payer_ai.key == expected, ProgramError::InvalidArgument, "rent_payer_pda: derivation mismatch");
+     validate!(rent_payer_ai.data_is_empty(), ProgramError::AccountAlreadyInitialized, "rent_payer_pda: already initialized");
+
+     let rent = Rent::from_account_info(rent_sysvar_ai)?;
+     let create_ix = solana_program::system_instruction::create_account(
+         payer.key,
+         rent_payer_ai.key,
+         rent.minimum_balance(RENT_PAYER_SIZE),
+         RENT_PAYER_SIZE as u64,
+         program_id,
+     );
+     invoke_signed(
+         &create_ix,
+         &[payer.clone(), rent_payer_ai.clone(), system_program_ai.clone()],
+         &[&[SEED_RENT_PAYER, account.as_ref(), &[bump]]],
+     )?;
+
+     let mut data = rent_payer_ai.try_borrow_mut_data()?;
+     *RentPayer::load_mut(&mut data)? = RentPayer { payer: *payer.key, bump, _reserved: [0u8; 31] };
+     Ok(())
+ }
+
+ /// Moves all of `account`'s lamports to `recipient` and hands it back to the system program
+ fn close_program_account(account: &AccountInfo, recipient: &AccountInfo) -> Result<u64, ProgramError> {
+     let lamports = account.lamports();
+     **account.try_borrow_mut_lamports()? = 0;
+     **recipient.try_borrow_mut_lamports()? = recipient.lamports().checked_add(lamports).ok_or(StakingError::Overflow)?;
+     account.realloc(0, false)?;
+     account.assign(&solana_program::system_program::id());
+     Ok(lamports)
+ }
+
+ /// Stake, or with `top_up` IncreaseStake, which adds to an active position instead of opening one
+ fn process_stake(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64, top_up: bool) -> ProgramResult {
+     stake_tokens(program_id, accounts, amount, top_up, false, None, false)
+ }
+
+ /// Shared by Stake, IncreaseStake, StakeWithPermitSignature, StakeFor and StakeWithPermit. With `delegated` the user
+ /// does not sign: the pool PDA moves the tokens as the user ATA's delegate, and the stake PDA must already exist.
+ /// With `beneficiary` the signer only funds the stake; the position is derived for and owned by the beneficiary.
+ /// `permitted` says the access_signer's permit was checked, which opening a position needs while the pool has one.
+ fn stake_tokens(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     amount: u64,
+     top_up: bool,
+     delegated: bool,
+     beneficiary: Option<Pubkey>,
+     permitted: bool,
+ ) -> ProgramResult {
+     if amount == 0 {
+         return Err(StakingError::ZeroAmount.into());
+     }
+     let instruction = match (delegated, top_up, beneficiary.is_some()) {
+         _ if permitted => "StakeWithPermit",
+         (true, _, _) => "StakeWithPermitSignature",
+         (false, _, true) => "StakeFor",
+         (false, true, false) => "IncreaseStake",
+         (false, false, false) => "Stake",
+     };
+     // 7 fixed accounts, then system program + rent (only read when creating the stake PDA), global pause,
+     // lock tiers, pool stats, the global TVL tracker and, topping up a shared pool, its shared vault and sibling pool
+     check_account_count(instruction, accounts, 7, 15)?;
+
+     let account_info_iter = &mut accounts.iter();
+     let user = next_account_info(account_info_iter)?; // signer
+     let user_ata = next_account_info(account_info_iter)?; // writable
+     let mint_ai = next_account_info(account_info_iter)?; // read-only
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let vault_ai = next_account_info(account_info_iter)?; // writable
+     let token_program_ai = next_account_info(account_info_iter)?;
+
+     validate!(user.is_signer || delegated, StakingError::Unauthorized, "user: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+     // The wallet the position belongs to; the signer's ATA funds it either way
+     let owner = beneficiary.unwrap_or(*user.key);
+
+     // First stake for this wallet: create the stake PDA inline, paid by the user
+     if user_stake_ai.data_is_empty() && !top_up && !delegated {
+         let system_program_ai = next_account_info(account_info_iter)?;
+         let rent_sysvar_ai = next_account_info(account_info_iter)?;
+         create_user_stake(program_id, user, &owner, pool_ai, user_stake_ai, system_program_ai, rent_sysvar_ai)?;
+     }
+     check_global_pause(program_id, account_info_iter.as_slice())?;
+     check_program_accounts(program_id, pool_ai, user_stake_ai)?;
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     check_pool_state(&pool, true)?;
+     validate!(
+         top_up || permitted || pool.access_signer == Pubkey::default(),
+         StakingError::AccessPermitRequired,
+         "pool_pda: new positions need the access signer's permit (StakeWithPermit)"
+     );
+     validate!(*mint_ai.key == pool.mint, StakingError::InvalidMint, "mint: not the pool's mint");
+     let decimals = mint_decimals(mint_ai)?;
+     let lock_tiers = load_lock_tiers(program_id, pool_ai.key, account_info_iter.as_slice())?;
+     validate!(
+         lock_tiers.is_some() || pool.has_lock_tiers == 0,
+         ProgramError::NotEnoughAccountKeys,
+         "lock_tier_pda: required once the pool has lock tiers"
+     );
+
+     // Verify the vault matches pool config
+     validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
+     let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     validate!(vault_data.owner == *pool_ai.key, StakingError::InvalidOwner, "vault: not owned by pool_pda");
+     validate!(vault_data.mint == *mint_ai.key, StakingError::InvalidMint, "vault: wrong mint");
+
+     // Verify user's ATA is for the same mint and owned by the user
+     let user_ata_data = spl_token::state::Account::unpack(&user_ata.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     validate!(user_ata_data.owner == *user.key, StakingError::InvalidOwner, "user_ata: not owned by user");
+     validate!(user_ata_data.mint == *mint_ai.key, StakingError::InvalidMint, "user_ata: wrong mint");
+     check_user_token_account(&pool, user.key, user_ata)?;
+     if user_ata_data.amount < amount {
+         return Err(StakingError::VaultInsufficient.into()); // user insufficient balance
+     }
+
+     // Load user stake, validate the PDA via its stored bump and ensure not already staked (or, topping up, staked)
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, &owner, us.bump)?;
+     validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+     if us.amount.get() != 0 && !top_up {
+         return Err(StakingError::DoubleStake.into());
+     }
+     if us.amount.get() == 0 && top_up {
+         return Err(StakingError::NoActiveStake.into());
+     }
+     validate!(us.sponsored == 0, StakingError::SponsoredStake, "user_stake_pda: sponsored; ConvertSponsoredStake first");
+     validate!(us.owner == owner, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     validate!(us.frozen == 0, StakingError::AccountFrozen, "user_stake_pda: frozen");
+     validate!(!us.tokenized(), StakingError::PositionTokenized, "user_stake_pda: position token outstanding");
+     let clock = Clock::get()?;
+     if !pool.stake_window_open(clock.unix_timestamp) {
+         return Err(StakingError::OutsideStakingWindow.into());
+     }
+
+     // The tier follows the position's size after this stake
+     let position = if top_up { us.amount.get().checked_add(amount).ok_or(StakingError::Overflow)? } else { amount };
+     let tier = lock_tiers.map(|config| config.tier_for(position)).unwrap_or_default();
+     let lock_period = pool.min_lock_period.get().saturating_add(tier.min_lock_seconds.get());
+
+     // Settle what the old amount earned before it grows, and fix the new unlock time while it is still known
+     if top_up {
+         if CompoundMode::try_from(us.compound_mode)? == CompoundMode::OnIncrease {
+             compound_rewards(&mut pool, &mut us, &clock, &[], vault_data.amount)?;
+         } else {
+             settle_rewards(
+                 program_id,
+                 &mut pool,
+                 &mut us,
+                 &clock,
+                 &[],
+                 &vault_data,
+                 mint_ai,
+                 pool_ai,
+                 vault_ai,
+                 user_ata,
+                 token_program_ai,
+                 account_info_iter.as_slice(),
+             )?;
+         }
+         // An authority override pins the unlock time whatever the policy
+         if us.lock_override == 0 {
+             let unlock = us.unlock_time(&pool);
+             let top_up_unlock = clock.unix_timestamp.saturating_add(lock_period);
+             us.unlock_at = match LockResetPolicy::try_from(pool.lock_reset_policy)? {
+                 LockResetPolicy::None => unlock,
+                 LockResetPolicy::ResetAll => unlock.max(top_up_unlock),
+                 LockResetPolicy::WeightedAverage => weighted_unlock_time(unlock, us.amount.get(), top_up_unlock, amount)?,
+             }
+             .into();
+         }
+     }
+
+     // Transfer user's tokens into the pool vault (authority = user, or the pool PDA as their delegate)
+     let transfer_authority = if delegated { pool_ai } else { user };
+     let transfer_ix = token_ix::transfer_checked(
+         token_program_ai.key,
+         user_ata.key,
+         mint_ai.key,
+         vault_ai.key,
+         transfer_authority.key,
+         &[],
+         amount,
+         decimals,
+     )?;
+     let seeds = pool.signer_seeds();
+     let signers: &[&[&[u8]]] = if delegated { &[&seeds] } else { &[] };
+     invoke_signed(
+         &transfer_ix,
+         &[user_ata.clone(), mint_ai.clone(), vault_ai.clone(), transfer_authority.clone(), token_program_ai.clone()],
+         signers,
+     )?;
+
+     // Update user stake and pool totals; in shares mode they count shares, priced before this deposit
+     let credited = if pool.shares_mode != 0 { pool.shares_for_deposit(vault_data.amount, amount)? } else { amount };
+     if credited == 0 {
+         return Err(StakingError::ZeroAmount.into());
+     }
+     if top_up {
+         us.amount = us.amount.get().checked_add(credited).ok_or(StakingError::Overflow)?.into();
+     } else {
+         us.amount = credited.into();
+         us.start_time = clock.unix_timestamp.into();
+         us.last_claim_time = clock.unix_timestamp.into();
+         us.start_slot = clock.slot.into();
+         us.last_claim_slot = clock.slot.into();
+         us.unlock_at = clock.unix_timestamp.saturating_add(lock_period).into();
+         us.principal_claimed = 0.into();
+     }
+     us.last_stake_time = clock.unix_timestamp.into();
+     us.effective_rate_bps = tier.bonus_rate_bps;
+
+     pool.total_staked = pool
+         .total_staked
+         .get()
+         .checked_add(credited)
+         .ok_or(StakingError::Overflow)?
+         .into();
+     us.save(user_stake_ai)?;
+     pool.save(pool_ai)?;
+     update_global_tvl(program_id, account_info_iter.as_slice(), amount, 0)?;
+     record_stats(program_id, &pool, pool_ai.key, account_info_iter.as_slice(), |stats| {
+         stats.stake_volume = stats.stake_volume.get().saturating_add(amount).into();
+         stats.stake_count = stats.stake_count.get().saturating_add(1).into();
+     })?;
+
+     log!("Staked: {} tokens, {} in total, for", amount, us.amount.get(); &owner);
+     Ok(())
+ }
+
+ /// Amount-weighted mean of two unlock times, rounded up so a top-up never unlocks early
+ pub fn weighted_unlock_time(unlock: i64, amount: u64, top_up_unlock: i64, top_up: u64) -> Result<i64, StakingError> {
+     let total = (amount as i128).checked_add(top_up as i128).ok_or(StakingError::Overflow)?;
+     if total == 0 {
+         return Ok(top_up_unlock);
+     }
+     let weighted = (unlock as i128)
+         .checked_mul(amount as i128).ok_or(StakingError::Overflow)?
+         .checked_add((top_up_unlock as i128).checked_mul(top_up as i128).ok_or(StakingError::Overflow)?)
+         .ok_or(StakingError::Overflow)?;
+     let mean = weighted.div_euclid(total) + i128::from(weighted.rem_euclid(total) != 0);
+     mean.try_into().map_err(|_| StakingError::Overflow)
+ }
+
+ /// The pool's LockTierConfig if it is among `accounts`, recognised by size and ownership like campaigns and
+ /// validated by re-deriving its address from the stored bump
+ fn load_lock_tiers(program_id: &Pubkey, pool_key: &Pubkey, accounts: &[AccountInfo]) -> Result<Option<LockTierConfig>, ProgramError> {
+     let Some(account) = accounts.iter().find(|a| a.owner == program_id && a.data_len() == LOCK_TIER_CONFIG_SIZE) else {
+         return Ok(None);
+     };
+     let data = account.try_borrow_data()?;
+     let config = *LockTierConfig::load(&data)?;
+     let expected = Pubkey::create_program_address(&[SEED_LOCK_TIER, pool_key.as_ref(), &[config.bump]], program_id)
+         .map_err(|_| ProgramError::InvalidArgument)?;
+     validate!(*account.key == expected, ProgramError::InvalidArgument, "lock_tier_pda: not the tier config of this pool");
+     Ok(Some(config))
+ }
+
+ /// Adds `added` to and takes `removed` off the GlobalTVLTracker's total, if the tracker is among `accounts`,
+ /// recognised by size and ownership and validated by re-deriving its address from the stored bump. Removals
+ /// saturate at 0, since stakes made before the tracker existed or without passing it were never counted.
+ /// Fails with `MaxStakeExceeded` if an addition takes the total above the cap.
+ fn update_global_tvl(program_id: &Pubkey, accounts: &[AccountInfo], added: u64, removed: u64) -> ProgramResult {
+     let Some(account) = accounts.iter().find(|a| a.owner == program_id && a.data_len() == GLOBAL_TVL_TRACKER_SIZE) else {
+         return Ok(());
+     };
+     let mut data = account.try_borrow_mut_data()?;
+     let tracker = GlobalTVLTracker::load_mut(&mut data)?;
+     let expected = Pubkey::create_program_address(&[SEED_GLOBAL_TVL, &[tracker.bump]], program_id);
+     validate!(expected == Ok(*account.key), ProgramError::InvalidArgument, "global_tvl_pda: derivation mismatch");
+     let total = tracker
+         .total_staked
+         .get()
+         .checked_add(added)
+         .ok_or(StakingError::Overflow)?
+         .saturating_sub(removed);
+     if added > 0 && total > tracker.total_tvl_cap.get() {
+         return Err(StakingError::MaxStakeExceeded.into());
+     }
+     tracker.total_staked = total.into();
+     Ok(())
+ }
+
+ /// Applies `update` to the pool's PoolStats, then raises its peak TVL to `pool.total_staked`, while analytics is
+ /// enabled and the PDA is among `accounts`; otherwise writes nothing
+ fn record_stats(
+     program_id: &Pubkey,
+     pool: &StakingPool,
+     pool_key: &Pubkey,
+     accounts: &[AccountInfo],
+     update: impl FnOnce(&mut PoolStats),
+ ) -> ProgramResult {
+     if pool.analytics_enabled == 0 {
+         return Ok(());
+     }
+     let Some(account) = accounts.iter().find(|a| a.owner == program_id && a.data_len() == POOL_STATS_SIZE) else {
+         return Ok(());
+     };
+     let mut data = account.try_borrow_mut_data()?;
+     let stats = PoolStats::load_mut(&mut data)?;
+     let expected = Pubkey::create_program_address(&[SEED_POOL_STATS, pool_key.as_ref(), &[stats.bump]], program_id)
+         .map_err(|_| ProgramError::InvalidArgument)?;
+     validate!(*account.key == expected, ProgramError::InvalidArgument, "pool_stats_pda: not the stats of this pool");
+     update(stats);
+     stats.peak_tvl = stats.peak_tvl.get().max(pool.total_staked.get()).into();
+     Ok(())
+ }
+
+ /// ClaimRewards, or with `max_amount` ClaimUpTo
+ fn process_claim(program_id: &Pubkey, accounts: &[AccountInfo], max_amount: Option<u64>) -> ProgramResult {
+     if max_amount == Some(0) {
+         return Err(StakingError::ZeroAmount.into());
+     }
+     let instruction = if max_amount.is_some() { "ClaimUpTo" } else { "ClaimRewards" };
+     // 7 fixed accounts, then the optional global pause, up to MAX_STACKED_CAMPAIGNS campaigns, pool stats,
+     // the claim cooldown bypass and a shared pool's shared vault and sibling pool
+     check_account_count(instruction, accounts, 7, 12 + MAX_STACKED_CAMPAIGNS)?;
+     let account_info_iter = &mut accounts.iter();
+     let user = next_account_info(account_info_iter)?; // signer
+     let user_ata = next_account_info(account_info_iter)?; // writable
+     let mint_ai = next_account_info(account_info_iter)?; // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let vault_ai = next_account_info(account_info_iter)?; // writable
+     let token_program_ai = next_account_info(account_info_iter)?;
+
+     validate!(user.is_signer, StakingError::Unauthorized, "user: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+     check_global_pause(program_id, account_info_iter.as_slice())?;
+     claim_position(
+         program_id,
+         user,
+         user_ata,
+         mint_ai,
+         user_stake_ai,
+         pool_ai,
+         vault_ai,
+         token_program_ai,
+         account_info_iter.as_slice(),
+         max_amount.unwrap_or(u64::MAX),
+     )
+ }
+
+ /// ClaimRewards for one position, once the caller has checked the user's signature, the token program
+ /// and the global pause; campaign PDAs among `trailing` boost the claim, a PoolStats PDA records it, and
+ /// the position's ClaimCooldownBypass PDA lets it skip claim_cooldown.
+ /// Pays at most `max_amount`, settling only as far as that covers so the rest keeps accruing.
+ #[allow(clippy::too_many_arguments)]
+ fn claim_position<'a>(
+     program_id: &Pubkey,
+     user: &AccountInfo<'a>,
+     user_ata: &AccountInfo<'a>,
+     mint_ai: &AccountInfo<'a>,
+     user_stake_ai: &AccountInfo<'a>,
+     pool_ai: &AccountInfo<'a>,
+     vault_ai: &AccountInfo<'a>,
+     token_program_ai: &AccountInfo<'a>,
+     trailing: &[AccountInfo<'a>],
+     max_amount: u64,
+ ) -> ProgramResult {
+     check_program_accounts(program_id, pool_ai, user_stake_ai)?;
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     check_pool_state(&pool, false)?;
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+
+     validate!(us.owner == *user.key, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     validate!(us.frozen == 0, StakingError::AccountFrozen, "user_stake_pda: frozen");
+     // The rewards accrued meanwhile belong to whoever burns the token
+     validate!(!us.tokenized(), StakingError::PositionTokenized, "user_stake_pda: position token outstanding");
+
+     // Verify token accounts and mint
+     validate!(*mint_ai.key == pool.mint, StakingError::InvalidMint, "mint: not the pool's mint");
+     validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
+     let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     let user_ata_data = spl_token::state::Account::unpack(&user_ata.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     validate!(vault_data.owner == *pool_ai.key, StakingError::InvalidOwner, "vault: not owned by pool_pda");
+     validate!(vault_data.mint == *mint_ai.key, StakingError::InvalidMint, "vault: wrong mint");
+     validate!(user_ata_data.mint == *mint_ai.key, StakingError::InvalidMint, "user_ata: wrong mint");
+     validate!(user_ata_data.owner == *user.key, StakingError::InvalidOwner, "user_ata: not owned by user");
+     check_user_token_account(&pool, user.key, user_ata)?;
+
+     let clock = Clock::get()?;
+     if clock.unix_timestamp < us.last_claim_time.get() {
+         return Err(StakingError::TimeWentBackwards.into());
+     }
+     // Fail loudly rather than report a successful transaction that did nothing
+     if us.amount.get() == 0 {
+         return Err(StakingError::NothingToClaim.into());
+     }
+     // Like the dust check below, this leaves state alone so the rewards keep accruing
+     if clock.unix_timestamp - us.last_claim_time.get() < pool.claim_cooldown.get()
+         && clock.unix_timestamp > claim_cooldown_bypass_until(program_id, user_stake_ai.key, trailing)?
+     {
+         return Err(StakingError::ClaimTooSoon.into());
+     }
+     let campaigns = live_campaign_multipliers(program_id, pool_ai.key, trailing, clock.unix_timestamp)?;
+     // Settling at an earlier point leaves what accrued after it to a later claim
+     let clock = claim_point(&pool, &us, &clock, &campaigns, max_amount)?;
+     let pending = pending_rewards(&pool, &us, &clock, &campaigns)?;
+     if pending == 0 && max_amount != u64::MAX {
+         return Err(StakingError::NothingToClaim.into());
+     }
+     // Below the floor the reward hasn't built up enough yet; state is left alone so it keeps accruing
+     if pending < pool.min_reward_per_claim.get() {
+         return Err(StakingError::ClaimTooSoon.into());
+     }
+
+     // OnClaim: the rewards stay in the vault and become principal, so there is no token CPI
+     if CompoundMode::try_from(us.compound_mode)? == CompoundMode::OnClaim {
+         let compounded = compound_rewards(&mut pool, &mut us, &clock, &campaigns, vault_data.amount)?;
+         us.save(user_stake_ai)?;
+         pool.save(pool_ai)?;
+         record_stats(program_id, &pool, pool_ai.key, trailing, |stats| stats.record_claim(compounded))?;
+         log!("Rewards compounded: {} by", compounded; user.key);
+         return Ok(());
+     }
+
+     let pending_u64 = settle_rewards(
+         program_id,
+         &mut pool,
+         &mut us,
+         &clock,
+         &campaigns,
+         &vault_data,
+         mint_ai,
+         pool_ai,
+         vault_ai,
+         user_ata,
+         token_program_ai,
+         trailing,
+     )?;
+     us.save(user_stake_ai)?;
+     pool.save(pool_ai)?;
+     record_stats(program_id, &pool, pool_ai.key, trailing, |stats| stats.record_claim(pending_u64))?;
+
+     log!("Rewards claimed: {} by", pending_u64; user.key);
+     Ok(())
+ }
+
+ /// The latest point up to `clock` at which the rewards accrued since the last claim come to at most
+ /// `max_amount`: a second in time mode, a slot in slot mode. Rewards are monotonic in it, so a binary
+ /// search between the last claim and now finds it.
+ fn claim_point(pool: &StakingPool, us: &UserStake, clock: &Clock, campaigns: &[u16], max_amount: u64) -> Result<Clock, ProgramError> {
+     if pending_rewards(pool, us, clock, campaigns)? <= max_amount {
+         return Ok(clock.clone());
+     }
+     let slot_mode = pool.use_slot_accrual != 0;
+     let at = |point: u64| {
+         let mut probe = clock.clone();
+         if slot_mode {
+             probe.slot = point;
+         } else {
+             probe.unix_timestamp = point as i64;
+         }
+         probe
+     };
+     // Nothing has accrued at the last claim, and too much has by now
+     let (mut lo, mut hi) = if slot_mode {
+         (us.last_claim_slot.get(), clock.slot)
+     } else {
+         (us.last_claim_time.get() as u64, clock.unix_timestamp as u64)
+     };
+     while hi - lo > 1 {
+         let mid = lo + (hi - lo) / 2;
+         if pending_rewards(pool, us, &at(mid), campaigns)? <= max_amount {
+             lo = mid;
+         } else {
+             hi = mid;
+         }
+     }
+     Ok(at(lo))
+ }
+
+ /// Pending rewards for `amount` staked over `elapsed` seconds at `reward_rate` (scaled by 1e9).
+ /// Uses u128 intermediates; errors with `Overflow` if the result does not fit a u64.
+ pub fn calculate_pending_rewards(elapsed: u64, amount: u64, reward_rate: u64) -> Result<u64, StakingError> {
+     let pending = (elapsed as u128)
+         .checked_mul(amount as u128).ok_or(StakingError::Overflow)?
+         .checked_mul(reward_rate as u128).ok_or(StakingError::Overflow)?
+         / 1_000_000_000u128;
+     pending.try_into().map_err(|_| StakingError::Overflow)
+ }
+
+ /// reward_rate paying `apr_bps` a year, rounded to the nearest unit. Rewards are paid in the staked mint, so
+ /// the rate is a per-second fraction of the stake and the same for any mint decimals.
+ ///
+ /// This is an off-chain helper for picking a rate, not a general APR setting: reward_rate is coarse, one
+ /// unit paying 315.36 bps APR (1e9 / one year, in bps), so only multiples of that are exact. 10% APR, for
+ /// one, lies between rate 3 (9.46%) and rate 4 (12.61%). Rather than round silently, this fails with
+ /// `AprNotRepresentable` when the nearest rate pays more than APR_TOLERANCE_BPS of `apr_bps` away from it,
+ /// which rules out most APRs below about 158%; pick an APR near a multiple of 315.36 bps or set reward_rate
+ /// directly. For the same reason there is no instruction that initializes a pool from an APR.
+ pub fn reward_rate_from_apr_bps(apr_bps: u64) -> Result<u64, StakingError> {
+     let per_unit = MULTIPLIER_BPS_DENOMINATOR as u128 * SECONDS_PER_YEAR as u128;
+     let requested = apr_bps as u128 * 1_000_000_000;
+     let rate = (requested + per_unit / 2) / per_unit;
+     // Both sides in bps * 1e9, so the comparison is exact
+     let miss = (rate * per_unit).abs_diff(requested);
+     if miss * MULTIPLIER_BPS_DENOMINATOR as u128 > requested * APR_TOLERANCE_BPS as u128 {
+         return Err(StakingError::AprNotRepresentable);
+     }
+     Ok(rate as u64)
+ }
+
+ /// APR in basis points that `reward_rate` pays over a year, rounded to the nearest bps (saturates at u64::MAX)
+ pub fn apr_bps_from_reward_rate(reward_rate: u64) -> u64 {
+     let bps = (reward_rate as u128 * MULTIPLIER_BPS_DENOMINATOR as u128 * SECONDS_PER_YEAR as u128 + 500_000_000) / 1_000_000_000;
+     bps.try_into().unwrap_or(u64::MAX)
+ }
+
+ /// Seconds since the last claim, or slots when the pool uses slot accrual. In time mode nothing
+ /// accrues before the pool's min_accrual_delay has passed since start_time.
+ fn accrual_elapsed(pool: &StakingPool, us: &UserStake, clock: &Clock) -> Result<u64, ProgramError> {
+     if pool.use_slot_accrual != 0 {
+         return clock.slot.checked_sub(us.last_claim_slot.get()).ok_or_else(|| StakingError::TimeWentBackwards.into());
+     }
+     let last_claim_time = us.last_claim_time.get();
+     if clock.unix_timestamp < last_claim_time {
+         return Err(StakingError::TimeWentBackwards.into());
+     }
+     let accrual_start = last_claim_time.max(us.start_time.get().saturating_add(pool.min_accrual_delay.get()));
+     Ok(clock.unix_timestamp.saturating_sub(accrual_start).max(0) as u64)
+ }
+
+ /// Rewards accrued since the last claim, after the lock tier bonus, the user's multiplier and then each
+ /// campaign boost in turn (always 0 in shares mode)
+ fn pending_rewards(pool: &StakingPool, us: &UserStake, clock: &Clock, campaigns: &[u16]) -> Result<u64, ProgramError> {
+     let elapsed = accrual_elapsed(pool, us, clock)?;
+     // Shares-mode holders earn through the exchange rate instead
+     if pool.shares_mode != 0 {
+         return Ok(0);
+     }
+     let pending_base = if pool.use_slot_accrual != 0 {
+         calculate_pending_rewards(elapsed, us.amount.get(), pool.reward_rate.get())?
+     } else {
+         let now = clock.unix_timestamp;
+         pool.scheduled_rewards(now.saturating_sub(elapsed as i64), now, us.amount.get())?
+     };
+     // The lock tier bonus raises the pool rate for this position: rate * (10_000 + bonus) / 10_000
+     let tier_bps = MULTIPLIER_BPS_DENOMINATOR.saturating_add(us.effective_rate_bps.get());
+     let mut pending = apply_reward_multiplier(pending_base, tier_bps)?;
+     pending = apply_reward_multiplier(pending, us.reward_multiplier_bps())?;
+     for &multiplier_bps in campaigns {
+         pending = apply_reward_multiplier(pending, multiplier_bps)?;
+     }
+     Ok(pending)
+ }
+
+ /// bypass_until of `user_stake`'s ClaimCooldownBypass PDA if it is among `accounts`, else 0
+ fn claim_cooldown_bypass_until(program_id: &Pubkey, user_stake: &Pubkey, accounts: &[AccountInfo]) -> Result<i64, ProgramError> {
+     let Some(account) = accounts.iter().find(|a| a.owner == program_id && a.data_len() == CLAIM_COOLDOWN_BYPASS_SIZE) else {
+         return Ok(0);
+     };
+     let data = account.try_borrow_data()?;
+     let bypass = ClaimCooldownBypass::load(&data)?;
+     let seeds: &[&[u8]] = &[SEED_CLAIM_COOLDOWN_BYPASS, user_stake.as_ref(), &[bypass.bump]];
+     let expected = Pubkey::create_program_address(seeds, program_id).map_err(|_| ProgramError::InvalidArgument)?;
+     validate!(*account.key == expected, ProgramError::InvalidArgument, "claim_cooldown_bypass_pda: not this position's bypass");
+     Ok(bypass.bypass_until.get())
+ }
+
+ /// Boosts of the incentive and boost campaigns among `accounts` that are live at `now`. Program-owned accounts
+ /// of either campaign's size must be that kind of campaign PDA of `pool_key`, each passed at most once;
+ /// anything else is skipped.
+ fn live_campaign_multipliers(
+     program_id: &Pubkey,
+     pool_key: &Pubkey,
+     accounts: &[AccountInfo],
+     now: i64,
+ ) -> Result<Vec<u16>, ProgramError> {
+     let mut seen: Vec<&Pubkey> = Vec::new();
+     let mut multipliers = Vec::new();
+     for account in accounts {
+         if account.owner != program_id {
+             continue;
+         }
+         let data = account.try_borrow_data()?;
+         let (seed, campaign_id, bump, live, multiplier_bps) = match account.data_len() {
+             INCENTIVE_CAMPAIGN_SIZE => {
+                 let campaign = IncentiveCampaign::load(&data)?;
+                 (SEED_CAMPAIGN, campaign.campaign_id.0, campaign.bump, campaign.is_live(now), campaign.multiplier_bps.get())
+             }
+             BOOST_CAMPAIGN_SIZE => {
+                 let campaign = BoostCampaign::load(&data)?;
+                 (SEED_BOOST_CAMPAIGN, campaign.campaign_id.0, campaign.bump, campaign.is_live(now), campaign.multiplier_bps())
+             }
+             _ => continue,
+         };
+         let seeds: &[&[u8]] = &[seed, pool_key.as_ref(), &campaign_id, &[bump]];
+         let expected = Pubkey::create_program_address(seeds, program_id).map_err(|_| ProgramError::InvalidArgument)?;
+         validate!(*account.key == expected, ProgramError::InvalidArgument, "campaign_pda: not a campaign of this pool");
+         validate!(!seen.contains(&account.key), ProgramError::InvalidArgument, "campaign_pda: passed more than once");
+         seen.push(account.key);
+         if live {
+             multipliers.push(multiplier_bps);
+         }
+     }
+     Ok(multipliers)
+ }
+
+ /// Scales a base reward by a multiplier in basis points (10_000 = 1x)
+ pub fn apply_reward_multiplier(pending: u64, multiplier_bps: u16) -> Result<u64, StakingError> {
+     let scaled = (pending as u128) * (multiplier_bps as u128) / (MULTIPLIER_BPS_DENOMINATOR as u128);
+     scaled.try_into().map_err(|_| StakingError::Overflow)
+ }
+
+ /// Pays out rewards accrued since the last claim, up to the pool's remaining reward budget, from the vault
+ /// (or minted, in mint-on-claim mode) to `dest_ata`, signed by the pool PDA, and advances the last claim
+ /// time and slot to `clock`. A shared pool pays from its shared_reward_vault instead, which with the sibling
+ /// pool PDA owning it must be among `trailing`.
+ /// Caller is responsible for validating the accounts and saving `pool`; `pool_ai` must not be borrowed.
+ #[allow(clippy::too_many_arguments)]
+ fn settle_rewards<'a>(
+     program_id: &Pubkey,
+     pool: &mut StakingPool,
+     us: &mut UserStake,
+     clock: &Clock,
+     campaigns: &[u16],
+     vault_data: &spl_token::state::Account,
+     mint_ai: &AccountInfo<'a>,
+     pool_ai: &AccountInfo<'a>,
+     vault_ai: &AccountInfo<'a>,
+     dest_ata: &AccountInfo<'a>,
+     token_program_ai: &AccountInfo<'a>,
+     trailing: &[AccountInfo<'a>],
+ ) -> Result<u64, ProgramError> {
+     let pending_u64 = spend_reward_budget(pool, pending_rewards(pool, us, clock, campaigns)?)?;
+
+     if pending_u64 > 0 && pool.mint_on_claim != 0 {
+         mint_from_pool(pool, mint_ai, pool_ai, dest_ata, token_program_ai, pending_u64)?;
+     } else if pending_u64 > 0 && pool.shares_reward_vault() {
+         pay_from_shared_vault(program_id, pool, pool_ai.key, mint_ai, dest_ata, token_program_ai, trailing, pending_u64)?;
+     } else if pending_u64 > 0 {
+         if vault_data.amount < pending_u64 || vault_data.amount - pending_u64 < pool.vault_floor()? {
+             return Err(StakingError::VaultInsufficient.into());
+         }
+         transfer_from_vault(pool, mint_ai, pool_ai, vault_ai, dest_ata, token_program_ai, pending_u64)?;
+     }
+     if pending_u64 > 0 {
+         us.rewards_claimed = us
+             .rewards_claimed
+             .get()
+             .checked_add(pending_u64)
+             .ok_or(StakingError::Overflow)?
+             .into();
+     }
+
+     us.last_claim_time = clock.unix_timestamp.into();
+     us.last_claim_slot = clock.slot.into();
+     Ok(pending_u64)
+ }
+
+ /// Pays `amount` of `pool`'s rewards from its shared_reward_vault, signed by the sibling pool PDA that owns it;
+ /// both must be among `accounts`. The sibling's own floor, its stake plus vault buffer, stays covered.
+ #[allow(clippy::too_many_arguments)]
+ fn pay_from_shared_vault<'a>(
+     program_id: &Pubkey,
+     pool: &StakingPool,
+     pool_key: &Pubkey,
+     mint_ai: &AccountInfo<'a>,
+     dest_ata: &AccountInfo<'a>,
+     token_program_ai: &AccountInfo<'a>,
+     accounts: &[AccountInfo<'a>],
+     amount: u64,
+ ) -> ProgramResult {
+     let Some(shared_vault_ai) = accounts.iter().find(|a| *a.key == pool.shared_reward_vault) else {
+         msg!("shared_reward_vault: required while the pool shares a reward vault");
+         return Err(ProgramError::NotEnoughAccountKeys);
+     };
+     let vault_data = spl_token::state::Account::unpack(&shared_vault_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     validate!(vault_data.mint == pool.mint, StakingError::InvalidMint, "shared_reward_vault: wrong mint");
+     let Some(sibling_ai) = accounts.iter().find(|a| *a.key == vault_data.owner) else {
+         msg!("sibling_pool_pda: required while the pool shares a reward vault");
+         return Err(ProgramError::NotEnoughAccountKeys);
+     };
+     let sibling = load_sibling_pool(program_id, pool, pool_key, sibling_ai)?;
+     if vault_data.amount < amount || vault_data.amount - amount < sibling.vault_floor()? {
+         return Err(StakingError::VaultInsufficient.into());
+     }
+     transfer_from_vault(&sibling, mint_ai, sibling_ai, shared_vault_ai, dest_ata, token_program_ai, amount)
+ }
+
+ /// The pool at `sibling_ai`, once it is known to be another pool PDA of `pool`'s mint under the same
+ /// authority: the only pools whose vault `pool` may pay its rewards from
+ fn load_sibling_pool(program_id: &Pubkey, pool: &StakingPool, pool_key: &Pubkey, sibling_ai: &AccountInfo) -> Result<StakingPool, ProgramError> {
+     validate!(sibling_ai.key != pool_key, ProgramError::InvalidArgument, "sibling_pool_pda: the pool itself");
+     let sibling = StakingPool::load_checked(sibling_ai, program_id)?;
+     let expected = Pubkey::create_program_address(&sibling.signer_seeds(), program_id).map_err(|_| ProgramError::InvalidArgument)?;
+     validate!(*sibling_ai.key == expected, ProgramError::InvalidArgument, "sibling_pool_pda: derivation mismatch");
+     validate!(sibling.mint == pool.mint, StakingError::InvalidMint, "sibling_pool_pda: pool of another mint");
+     validate!(sibling.authority == pool.authority, StakingError::Unauthorized, "sibling_pool_pda: under another authority");
+     Ok(sibling)
+ }
+
+ /// Adds rewards accrued since the last claim, up to the pool's remaining reward budget, to the stake itself
+ /// and advances the last claim to `clock`. Rewards never leave the vault: they move from the reward surplus
+ /// into principal, so the vault must already cover the new `total_staked`.
+ fn compound_rewards(
+     pool: &mut StakingPool,
+     us: &mut UserStake,
+     clock: &Clock,
+     campaigns: &[u16],
+     vault_amount: u64,
+ ) -> Result<u64, ProgramError> {
+     // Minted rewards never pass through the vault, so there is nothing there to compound
+     validate!(pool.mint_on_claim == 0, StakingError::InvalidConfig, "pool_pda: mint-on-claim pools cannot compound");
+     // Compounded rewards would join a principal that stays in the vault on unstake
+     validate!(us.sponsored == 0, StakingError::SponsoredStake, "user_stake_pda: sponsored; ConvertSponsoredStake first");
+     validate!(!us.tokenized(), StakingError::PositionTokenized, "user_stake_pda: position token outstanding");
+     let pending = spend_reward_budget(pool, pending_rewards(pool, us, clock, campaigns)?)?;
+     let new_total = pool.total_staked.get().checked_add(pending).ok_or(StakingError::Overflow)?;
+     if vault_amount < new_total.checked_add(pool.minimum_vault_buffer.get()).ok_or(StakingError::Overflow)? {
+         return Err(StakingError::VaultInsufficient.into());
+     }
+
+     us.amount = us.amount.get().checked_add(pending).ok_or(StakingError::Overflow)?.into();
+     us.rewards_claimed = us.rewards_claimed.get().checked_add(pending).ok_or(StakingError::Overflow)?.into();
+     us.last_claim_time = clock.unix_timestamp.into();
+     us.last_claim_slot = clock.slot.into();
+     pool.total_staked = new_total.into();
+     Ok(pending)
+ }
+
+ /// Clamps `pending` to the pool's remaining reward budget and counts the result as distributed. Whatever is
+ /// clamped off is forfeited: callers still advance the claim time past it, so it never accrues again.
+ fn spend_reward_budget(pool: &mut StakingPool, pending: u64) -> Result<u64, StakingError> {
+     let paid = pending.min(pool.remaining_reward_budget());
+     pool.total_rewards_distributed = pool
+         .total_rewards_distributed
+         .get()
+         .checked_add(paid)
+         .ok_or(StakingError::Overflow)?
+         .into();
+     if pool.max_total_rewards.get() != 0 && pool.remaining_reward_budget() == 0 && pending > 0 {
+         log!("Reward budget exhausted: {} of {} pending paid", paid, pending);
+     }
+     Ok(paid)
+ }
+
+ /// Transfers `amount` out of the pool vault, signed by the pool PDA.
+ /// Signs with the stored bump; the runtime rejects the CPI if `pool_ai` is not that PDA.
+ fn transfer_from_vault<'a>(
+     pool: &StakingPool,
+     mint_ai: &AccountInfo<'a>,
+     pool_ai: &AccountInfo<'a>,
+     vault_ai: &AccountInfo<'a>,
+     dest_ata: &AccountInfo<'a>,
+     token_program_ai: &AccountInfo<'a>,
+     amount: u64,
+ ) -> ProgramResult {
+     let transfer_ix = token_ix::transfer_checked(
+         token_program_ai.key,
+         vault_ai.key,
+         mint_ai.key,
+         dest_ata.key,
+         pool_ai.key,
+         &[],
+         amount,
+         mint_decimals(mint_ai)?,
+     )?;
+     let seeds = pool.signer_seeds();
+     invoke_signed(
+         &transfer_ix,
+         &[vault_ai.clone(), mint_ai.clone(), dest_ata.clone(), pool_ai.clone(), token_program_ai.clone()],
+         &[&seeds],
+     )
+ }
+
+ /// Mints `amount` of the pool's mint to `dest`, signed by the pool PDA as mint authority
+ fn mint_from_pool<'a>(
+     pool: &StakingPool,
+     mint_ai: &AccountInfo<'a>,
+     pool_ai: &AccountInfo<'a>,
+     dest: &AccountInfo<'a>,
+     token_program_ai: &AccountInfo<'a>,
+     amount: u64,
+ ) -> ProgramResult {
+     let mint_ix = token_ix::mint_to(token_program_ai.key, mint_ai.key, dest.key, pool_ai.key, &[], amount)?;
+     let seeds = pool.signer_seeds();
+     invoke_signed(&mint_ix, &[mint_ai.clone(), dest.clone(), pool_ai.clone(), token_program_ai.clone()], &[&seeds])
+ }
+
+ /// `fee_bps` basis points of `principal`, rounded down: fees, penalties and partial exits
+ pub fn bps_fee(principal: u64, fee_bps: u16) -> Result<u64, StakingError> {
+     let fee = (principal as u128) * (fee_bps as u128) / (MULTIPLIER_BPS_DENOMINATOR as u128);
+     fee.try_into().map_err(|_| StakingError::Overflow)
+ }
+
+ fn process_unstake(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     unstake_position("Unstake", program_id, accounts, false, MULTIPLIER_BPS_DENOMINATOR)
+ }
+
+ fn process_early_unstake(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     unstake_position("EarlyUnstake", program_id, accounts, true, MULTIPLIER_BPS_DENOMINATOR)
+ }
+
+ fn process_unstake_bps(program_id: &Pubkey, accounts: &[AccountInfo], bps: u16) -> ProgramResult {
+     if bps == 0 || bps > MULTIPLIER_BPS_DENOMINATOR {
+         return Err(ProgramError::InvalidArgument);
+     }
+     unstake_position("UnstakeBps", program_id, accounts, false, bps)
+ }
+
+ /// Unstake, then close the emptied user stake PDA and return its lamports to the user
+ fn process_unstake_and_close(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     // A sponsored position ends with its RentPayer record and the recorded payer, who gets the rent back
+     let sponsored = match accounts.get(3) {
+         Some(user_stake_ai) => UserStake::load_checked(user_stake_ai, program_id)?.rent_sponsored != 0,
+         None => false,
+     };
+     let (accounts, sponsor) = if sponsored {
+         validate!(accounts.len() >= 9, ProgramError::NotEnoughAccountKeys, "rent_payer: required for a sponsored position");
+         let (accounts, tail) = accounts.split_at(accounts.len() - 2);
+         (accounts, Some((&tail[0], &tail[1])))
+     } else {
+         (accounts, None)
+     };
+     unstake_position("UnstakeAndClose", program_id, accounts, false, MULTIPLIER_BPS_DENOMINATOR)?;
+     let user = &accounts[0];
+     let user_stake_ai = &accounts[3];
+
+     let recipient = match sponsor {
+         Some((rent_payer_ai, payer)) => {
+             validate!(rent_payer_ai.owner == program_id, StakingError::InvalidOwner, "rent_payer_pda: not owned by this program");
+             {
+                 let data = rent_payer_ai.try_borrow_data()?;
+                 let record = RentPayer::load(&data)?;
+                 let expected = Pubkey::create_program_address(&[SEED_RENT_PAYER, user_stake_ai.key.as_ref(), &[record.bump]], program_id);
+                 validate!(expected == Ok(*rent_payer_ai.key), ProgramError::InvalidArgument, "rent_payer_pda: derivation mismatch");
+                 validate!(record.payer == *payer.key, ProgramError::InvalidArgument, "rent_payer: not the recorded payer");
+             }
+             close_program_account(rent_payer_ai, payer)?;
+             payer
+         }
+         None => user,
+     };
+     let lamports = close_program_account(user_stake_ai, recipient)?;
+
+     log!("User stake closed, {} lamports returned to", lamports; recipient.key);
+     Ok(())
+ }
+
+ /// Shared by Unstake, UnstakeAndClose, EarlyUnstake and UnstakeBps: enforce the lock (or, for `early`, take
+ /// the penalty instead), settle rewards, return `portion_bps` of the principal
+ fn unstake_position(
+     instruction: &str,
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     early: bool,
+     portion_bps: u16,
+ ) -> ProgramResult {
+     check_account_count(instruction, accounts, 7, if early { 13 } else { 12 })?;
+     let account_info_iter = &mut accounts.iter();
+     let user = next_account_info(account_info_iter)?; // signer
+     let user_ata = next_account_info(account_info_iter)?; // writable
+     let mint_ai = next_account_info(account_info_iter)?; // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let vault_ai = next_account_info(account_info_iter)?; // writable
+     let token_program_ai = next_account_info(account_info_iter)?;
+
+     validate!(user.is_signer, StakingError::Unauthorized, "user: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+     check_program_accounts(program_id, pool_ai, user_stake_ai)?;
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     check_pool_state(&pool, false)?;
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+
+     validate!(us.owner == *user.key, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     validate!(us.frozen == 0, StakingError::AccountFrozen, "user_stake_pda: frozen");
+     validate!(!us.tokenized(), StakingError::PositionTokenized, "user_stake_pda: position token outstanding");
+
+     // Verify token accounts and mint
+     validate!(*mint_ai.key == pool.mint, StakingError::InvalidMint, "mint: not the pool's mint");
+     validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
+     let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     let user_ata_data = spl_token::state::Account::unpack(&user_ata.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     validate!(vault_data.owner == *pool_ai.key, StakingError::InvalidOwner, "vault: not owned by pool_pda");
+     validate!(vault_data.mint == *mint_ai.key, StakingError::InvalidMint, "vault: wrong mint");
+     validate!(user_ata_data.mint == *mint_ai.key, StakingError::InvalidMint, "user_ata: wrong mint");
+     validate!(user_ata_data.owner == *user.key, StakingError::InvalidOwner, "user_ata: not owned by user");
+     check_user_token_account(&pool, user.key, user_ata)?;
+
+     let clock = Clock::get()?;
+     let now = clock.unix_timestamp;
+     if now < us.start_time.get() {
+         return Err(StakingError::TimeWentBackwards.into());
+     }
+     let staked = us.amount.get();
+     if staked == 0 {
+         return Err(StakingError::NoActiveStake.into());
+     }
+     let locked = now < us.unlock_time(&pool);
+     if locked && (!early || us.lock_override != 0) {
+         return Err(StakingError::LockActive.into());
+     }
+     let penalty_bps = if locked { pool.early_unstake_penalty_bps.get() } else { 0 };
+     if locked && penalty_bps == 0 {
+         return Err(StakingError::EarlyUnstakeDisabled.into());
+     }
+     // Floors, so a dust portion withdraws nothing and fails; 10_000 bps is always the whole stake
+     let withdrawn = bps_fee(staked, portion_bps)?;
+     if withdrawn == 0 {
+         return Err(StakingError::ZeroAmount.into());
+     }
+
+     // Rewards left unclaimed for longer than the pool allows are forfeited; only the latest
+     // max_claim_interval_seconds of them are paid (time accrual only)
+     let max_interval = pool.max_claim_interval_seconds.get();
+     if max_interval > 0 && pool.use_slot_accrual == 0 && now.saturating_sub(us.last_claim_time.get()) > max_interval {
+         us.last_claim_time = now.saturating_sub(max_interval).into();
+         log!("Rewards older than {}s forfeited by", max_interval; user.key);
+     }
+
+     // First, settle any pending rewards to keep accounting consistent
+     let paid = settle_rewards(
+         program_id,
+         &mut pool,
+         &mut us,
+         &clock,
+         &[],
+         &vault_data,
+         mint_ai,
+         pool_ai,
+         vault_ai,
+         user_ata,
+         token_program_ai,
+         &accounts[7..],
+     )?;
+     // A shared pool's rewards came out of the sibling's vault, leaving this one whole
+     let paid_from_vault = if pool.shares_reward_vault() { 0 } else { paid };
+
+     // Now return principal, less any early-unstake penalty and the withdrawal fee; in shares mode the
+     // withdrawn shares redeem at the vault's exchange rate
+     let principal = if pool.shares_mode != 0 { pool.tokens_for_shares(vault_data.amount, withdrawn)? } else { withdrawn };
+     if principal == 0 {
+         return Err(StakingError::ZeroAmount.into());
+     }
+     if vault_data.amount.saturating_sub(paid_from_vault) < principal {
+         return Err(StakingError::VaultInsufficient.into());
+     }
+     // A sponsored principal is the protocol's and stays in the vault, so there is nothing to return,
+     // penalize or charge a fee on
+     let (returned, exit_fee, penalty) = if us.sponsored != 0 {
+         (0, 0, 0)
+     } else {
+         // EarlyUnstake always takes the recipient's ATA when one is set and penalties are not redistributed,
+         // so the treasury's position is fixed
+         let recipient_ata = if early && pool.penalty_disposition() == PenaltyDisposition::Treasury {
+             let recipient_ata = next_account_info(account_info_iter)?;
+             let expected = spl_associated_token_account::get_associated_token_address(&pool.penalty_recipient, &pool.mint);
+             validate!(
+                 *recipient_ata.key == expected,
+                 StakingError::InvalidPenaltyRecipient,
+                 "penalty_recipient_ata: not the penalty recipient's ATA"
+             );
+             Some(recipient_ata)
+         } else {
+             None
+         };
+         let penalty = bps_fee(principal, penalty_bps)?;
+         if penalty > 0 {
+             forfeit_penalty(&pool, mint_ai, pool_ai, vault_ai, token_program_ai, recipient_ata, penalty)?;
+         }
+         let exit_fee = bps_fee(principal - penalty, pool.withdraw_fee_bps.get())?;
+         transfer_from_vault(&pool, mint_ai, pool_ai, vault_ai, user_ata, token_program_ai, principal - penalty - exit_fee)?;
+         if exit_fee > 0 {
+             let treasury_ai = next_account_info(account_info_iter)?;
+             validate!(
+                 *treasury_ai.key == pool.protocol_treasury_ata,
+                 StakingError::InvalidTreasury,
+                 "protocol_treasury_ata: not the pool's treasury"
+             );
+             transfer_from_vault(&pool, mint_ai, pool_ai, vault_ai, treasury_ai, token_program_ai, exit_fee)?;
+         }
+         (principal - penalty - exit_fee, exit_fee, penalty)
+     };
+
+     // Update states; a partial exit keeps the remainder's lock and accrual times
+     let remaining = staked - withdrawn;
+     us.amount = remaining.into();
+     if remaining == 0 {
+         us.start_time = 0.into();
+         us.last_stake_time = 0.into();
+         us.last_claim_time = 0.into();
+         us.start_slot = 0.into();
+         us.last_claim_slot = 0.into();
+         us.unlock_at = 0.into();
+         us.lock_override = 0;
+         us.principal_claimed = 0.into();
+         us.sponsored = 0;
+     }
+     pool.total_staked = pool
+         .total_staked
+         .get()
+         .checked_sub(withdrawn)
+         .ok_or(StakingError::Overflow)?
+         .into();
+     us.save(user_stake_ai)?;
+     pool.save(pool_ai)?;
+     update_global_tvl(program_id, &accounts[7..], 0, returned + exit_fee + penalty)?;
+     record_stats(program_id, &pool, pool_ai.key, &accounts[7..], |stats| {
+         stats.unstake_volume = stats.unstake_volume.get().saturating_add(principal).into();
+         stats.unstake_count = stats.unstake_count.get().saturating_add(1).into();
+         stats.rewards_paid = stats.rewards_paid.get().saturating_add(paid).into();
+     })?;
+
+     log!(
+         "Unstaked: {} returned ({} fee, {} penalty), {} still staked, to",
+         returned,
+         exit_fee,
+         penalty,
+         remaining;
+         user.key
+     );
+     Ok(())
+ }
+
+ /// Pays `penalty` from the vault to the penalty recipient's ATA, or burns it when the pool has no recipient.
+ /// With redistribute_penalties it stays in the vault, where it funds the remaining stakers' rewards.
+ fn forfeit_penalty<'a>(
+     pool: &StakingPool,
+     mint_ai: &AccountInfo<'a>,
+     pool_ai: &AccountInfo<'a>,
+     vault_ai: &AccountInfo<'a>,
+     token_program_ai: &AccountInfo<'a>,
+     recipient_ata: Option<&AccountInfo<'a>>,
+     penalty: u64,
+ ) -> ProgramResult {
+     match pool.penalty_disposition() {
+         PenaltyDisposition::Redistribute => return Ok(()),
+         PenaltyDisposition::Treasury => {
+             let recipient_ata = recipient_ata.ok_or(StakingError::InvalidPenaltyRecipient)?;
+             return transfer_from_vault(pool, mint_ai, pool_ai, vault_ai, recipient_ata, token_program_ai, penalty);
+         }
+         PenaltyDisposition::Burn => {}
+     }
+     let burn_ix = token_ix::burn_checked(
+         token_program_ai.key,
+         vault_ai.key,
+         mint_ai.key,
+         pool_ai.key,
+         &[],
+         penalty,
+         mint_decimals(mint_ai)?,
+     )?;
+     let seeds = pool.signer_seeds();
+     invoke_signed(
+         &burn_ix,
+         &[vault_ai.clone(), mint_ai.clone(), pool_ai.clone(), token_program_ai.clone()],
+         &[&seeds],
+     )
+ }
+
+ fn process_force_claim_for_user(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     user_wallet: Pubkey,
+ ) -> ProgramResult {
+     // 7 fixed accounts, then a shared pool's shared vault and sibling pool
+     check_account_count("ForceClaimForUser", accounts, 7, 9)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let user_ata = next_account_info(account_info_iter)?; // writable
+     let mint_ai = next_account_info(account_info_iter)?; // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let vault_ai = next_account_info(account_info_iter)?; // writable
+     let token_program_ai = next_account_info(account_info_iter)?;
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+     check_program_accounts(program_id, pool_ai, user_stake_ai)?;
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     check_pool_state(&pool, false)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     validate!(*mint_ai.key == pool.mint, StakingError::InvalidMint, "mint: not the pool's mint");
+
+     // The stake PDA is derived from the wallet, never trusted from the caller
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, &user_wallet, us.bump)?;
+     validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+     validate!(us.owner == user_wallet, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     validate!(us.frozen == 0, StakingError::AccountFrozen, "user_stake_pda: frozen");
+     validate!(!us.tokenized(), StakingError::PositionTokenized, "user_stake_pda: position token outstanding");
+
+     // Rewards always go to the user's canonical ATA, which must already exist
+     let expected_ata = spl_associated_token_account::get_associated_token_address(&user_wallet, mint_ai.key);
+     validate!(*user_ata.key == expected_ata, ProgramError::InvalidArgument, "user_ata: not the user's canonical ATA");
+     validate!(!user_ata.data_is_empty(), StakingError::ATAMissing, "user_ata: not created");
+
+     // Verify token accounts and mint
+     validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
+     let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     let user_ata_data = spl_token::state::Account::unpack(&user_ata.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     validate!(vault_data.owner == *pool_ai.key, StakingError::InvalidOwner, "vault: not owned by pool_pda");
+     validate!(vault_data.mint == *mint_ai.key, StakingError::InvalidMint, "vault: wrong mint");
+     validate!(user_ata_data.mint == *mint_ai.key, StakingError::InvalidMint, "user_ata: wrong mint");
+     validate!(user_ata_data.owner == user_wallet, StakingError::InvalidOwner, "user_ata: not owned by user");
+
+     let clock = Clock::get()?;
+     if us.amount.get() == 0 {
+         return Ok(());
+     }
+
+     let pending_u64 = settle_rewards(
+         program_id,
+         &mut pool,
+         &mut us,
+         &clock,
+         &[],
+         &vault_data,
+         mint_ai,
+         pool_ai,
+         vault_ai,
+         user_ata,
+         token_program_ai,
+         &accounts[7..],
+     )?;
+     us.save(user_stake_ai)?;
+     pool.save(pool_ai)?;
+
+     log!("Force-claimed: {} for user, by authority", pending_u64; user_wallet, authority.key);
+     Ok(())
+ }
+
+ fn process_propose_authority(program_id: &Pubkey, accounts: &[AccountInfo], proposed: Pubkey) -> ProgramResult {
+     check_account_count("ProposeAuthority", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     // A new proposal simply replaces any outstanding one
+     pool.pending_authority = proposed;
+     pool.save(pool_ai)?;
+
+     log!("Authority transfer proposed: current, proposed"; authority.key, proposed);
+     Ok(())
+ }
+
+ fn process_accept_authority(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("AcceptAuthority", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let new_authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;       // writable
+
+     validate!(new_authority.is_signer, StakingError::Unauthorized, "new_authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(
+         pool.pending_authority != Pubkey::default() && pool.pending_authority == *new_authority.key,
+         StakingError::Unauthorized,
+         "new_authority: not the pending authority"
+     );
+
+     pool.authority = pool.pending_authority;
+     pool.pending_authority = Pubkey::default();
+     pool.save(pool_ai)?;
+
+     log!("Authority transfer accepted: new authority"; new_authority.key);
+     Ok(())
+ }
+
+ fn process_cancel_authority_transfer(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("CancelAuthorityTransfer", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     pool.pending_authority = Pubkey::default();
+     pool.save(pool_ai)?;
+
+     log!("Authority transfer cancelled by"; authority.key);
+     Ok(())
+ }
+
+ fn process_set_user_reward_multiplier(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     user_wallet: Pubkey,
+     multiplier_bps: u16,
+ ) -> ProgramResult {
+     check_account_count("SetUserRewardMultiplier", accounts, 3, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     if !(MIN_USER_MULTIPLIER_BPS..=MAX_USER_MULTIPLIER_BPS).contains(&multiplier_bps) {
+         return Err(StakingError::InvalidMultiplier.into());
+     }
+
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, &user_wallet, us.bump)?;
+     validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+     validate!(us.owner == user_wallet, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+
+     us.individual_multiplier_bps = multiplier_bps.into();
+     us.save(user_stake_ai)?;
+
+     log!("Reward multiplier set to {} bps for", multiplier_bps; user_wallet);
+     Ok(())
+ }
+
+ fn process_setup_auto_compound(program_id: &Pubkey, accounts: &[AccountInfo], interval_seconds: i64) -> ProgramResult {
+     check_account_count("SetupAutoCompound", accounts, 3, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let user = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+
+     validate!(user.is_signer, StakingError::Unauthorized, "user: missing signature");
+     if interval_seconds < 0 {
+         return Err(StakingError::InvalidCompoundInterval.into());
+     }
+
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, user.key, us.bump)?;
+     validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+     validate!(us.owner == *user.key, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+
+     us.auto_compound_enabled = (interval_seconds > 0) as u8;
+     us.auto_compound_interval_seconds = interval_seconds.into();
+     us.save(user_stake_ai)?;
+
+     log!("Auto-compound set to every {}s for", interval_seconds; user.key);
+     Ok(())
+ }
+
+ fn process_auto_compound(program_id: &Pubkey, accounts: &[AccountInfo], user_wallet: Pubkey) -> ProgramResult {
+     check_account_count("AutoCompound", accounts, 4, 5)?;
+     let account_info_iter = &mut accounts.iter();
+     let caller = next_account_info(account_info_iter)?; // signer, writable
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let vault_ai = next_account_info(account_info_iter)?; // read-only
+
+     validate!(caller.is_signer, StakingError::Unauthorized, "caller: missing signature");
+     check_global_pause(program_id, account_info_iter.as_slice())?;
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     check_pool_state(&pool, false)?;
+     validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
+     let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, &user_wallet, us.bump)?;
+     validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+     validate!(us.owner == user_wallet, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     validate!(us.frozen == 0, StakingError::AccountFrozen, "user_stake_pda: frozen");
+     if us.auto_compound_enabled == 0 || us.amount.get() == 0 {
+         return Err(StakingError::AutoCompoundDisabled.into());
+     }
+
+     let clock = Clock::get()?;
+     let last_claim_time = us.last_claim_time.get();
+     if clock.unix_timestamp < last_claim_time {
+         return Err(StakingError::TimeWentBackwards.into());
+     }
+     if clock.unix_timestamp - last_claim_time < us.auto_compound_interval_seconds.get() {
+         return Err(StakingError::CompoundTooEarly.into());
+     }
+
+     let pending = compound_rewards(&mut pool, &mut us, &clock, &[], vault_data.amount)?;
+     us.save(user_stake_ai)?;
+     pool.save(pool_ai)?;
+     log!("Auto-compounded: {} for user, by caller", pending; user_wallet, caller.key);
+
+     // Tip the caller from lamports deposited above the rent-exempt minimum
+     let rent_floor = Rent::get()?.minimum_balance(user_stake_ai.data_len());
+     if user_stake_ai.lamports().saturating_sub(rent_floor) >= AUTO_COMPOUND_TIP_LAMPORTS {
+         **user_stake_ai.try_borrow_mut_lamports()? -= AUTO_COMPOUND_TIP_LAMPORTS;
+         **caller.try_borrow_mut_lamports()? += AUTO_COMPOUND_TIP_LAMPORTS;
+     }
+     Ok(())
+ }
+
+ fn process_set_max_lock_period(program_id: &Pubkey, accounts: &[AccountInfo], max_seconds: i64) -> ProgramResult {
+     check_account_count("SetMaxLockPeriod", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     // The cap must not already be violated by the pool's own minimum lock
+     if max_seconds < 0 || (max_seconds > 0 && pool.min_lock_period.get() > max_seconds) {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     pool.max_lock_period = max_seconds.into();
+     pool.save(pool_ai)?;
+
+     log!("Max lock period set to {}s", max_seconds);
+     Ok(())
+ }
+
+ fn process_recover_orphaned_account(program_id: &Pubkey, accounts: &[AccountInfo], target_pda: Pubkey) -> ProgramResult {
+     check_account_count("RecoverOrphanedAccount", accounts, 3, 4)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer, writable
+     let pool_ai = next_account_info(account_info_iter)?;   // read-only
+     let target_ai = next_account_info(account_info_iter)?; // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     validate!(*target_ai.key == target_pda, ProgramError::InvalidArgument, "target: does not match target_pda");
+     validate!(
+         target_ai.owner == program_id && target_ai.data_len() == USER_STAKE_SIZE,
+         StakingError::InvalidOwner,
+         "target: not a user stake account"
+     );
+     let us = UserStake::unpack_unchecked(&target_ai.try_borrow_data()?)?;
+     if us.amount.get() != 0 {
+         return Err(StakingError::StillStaked.into());
+     }
+     // Orphaned means never initialized, or recorded against something that is not a live pool
+     if us.pool == *pool_ai.key {
+         return Err(StakingError::NotOrphaned.into());
+     }
+     if us.pool != Pubkey::default() {
+         let recorded_pool_ai = next_account_info(account_info_iter)?;
+         validate!(*recorded_pool_ai.key == us.pool, ProgramError::InvalidArgument, "recorded_pool: not the target's recorded pool");
+         if recorded_pool_ai.owner == program_id && recorded_pool_ai.data_len() == STAKING_POOL_SIZE {
+             return Err(StakingError::NotOrphaned.into());
+         }
+     }
+
+     // Drain rent to the authority and hand the emptied account back to the system program
+     let lamports = target_ai.lamports();
+     **target_ai.try_borrow_mut_lamports()? = 0;
+     **authority.try_borrow_mut_lamports()? = authority
+         .lamports()
+         .checked_add(lamports)
+         .ok_or(StakingError::Overflow)?;
+     target_ai.try_borrow_mut_data()?.fill(0);
+     target_ai.realloc(0, false)?;
+     target_ai.assign(&solana_program::system_program::id());
+
+     log!("Recovered {} lamports from orphaned account", lamports; target_pda);
+     Ok(())
+ }
+
+ fn process_verify_pool_integrity(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("VerifyPoolIntegrity", accounts, 2, usize::MAX)?;
+     let account_info_iter = &mut accounts.iter();
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let vault_ai = next_account_info(account_info_iter)?; // read-only
+
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     let total_staked = pool.total_staked.get();
+     let mut violations = 0u32;
+
+     if pool.vault != *vault_ai.key {
+         msg!("INTEGRITY_FAIL: vault {} is not the pool vault {}", vault_ai.key, pool.vault);
+         violations += 1;
+     } else {
+         match spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?) {
+             Ok(vault) if vault.amount < total_staked => {
+                 msg!("INTEGRITY_FAIL: vault holds {} < total_staked {}", vault.amount, total_staked);
+                 violations += 1;
+             }
+             Ok(_) => {}
+             Err(_) => {
+                 msg!("INTEGRITY_FAIL: vault {} is not a token account", vault_ai.key);
+                 violations += 1;
+             }
+         }
+     }
+
+     // Optional: the supplied user stakes must account for exactly total_staked
+     let mut users = 0u32;
+     let mut user_sum: u128 = 0;
+     for user_stake_ai in account_info_iter {
+         match UserStake::load_checked(user_stake_ai, program_id) {
+             Ok(us) if us.pool == *pool_ai.key => {
+                 user_sum += us.amount.get() as u128;
+                 users += 1;
+             }
+             _ => {
+                 msg!("INTEGRITY_FAIL: {} is not a user stake of this pool", user_stake_ai.key);
+                 violations += 1;
+             }
+         }
+     }
+     if users > 0 && user_sum != total_staked as u128 {
+         msg!("INTEGRITY_FAIL: user stakes sum to {} != total_staked {}", user_sum, total_staked);
+         violations += 1;
+     }
+
+     msg!("INTEGRITY_DONE violations={}", violations);
+     Ok(())
+ }
+
+ fn process_initialize_global_pause(program_id: &Pubkey, accounts: &[AccountInfo], authority: Pubkey) -> ProgramResult {
+     check_account_count("InitializeGlobalPause", accounts, 4, 4)?;
+     let account_info_iter = &mut accounts.iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let global_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+
+     let (expected, bump) = Pubkey::find_program_address(&[SEED_GLOBAL_PAUSE], program_id);
+     validate!(*global_ai.key == expected, ProgramError::InvalidArgument, "global_pause_pda: derivation mismatch");
+     // Singleton: never re-initialize, or anyone could seize the switch
+     if !global_ai.data_is_empty() {
+         return Err(ProgramError::AccountAlreadyInitialized);
+     }
+
+     let rent = Rent::from_account_info(rent_sysvar_ai)?;
+     let create_ix = solana_program::system_instruction::create_account(
+         payer.key,
+         global_ai.key,
+         rent.minimum_balance(GLOBAL_PAUSE_SIZE),
+         GLOBAL_PAUSE_SIZE as u64,
+         program_id,
+     );
+     invoke_signed(
+         &create_ix,
+         &[payer.clone(), global_ai.clone(), system_program_ai.clone()],
+         &[&[SEED_GLOBAL_PAUSE, &[bump]]],
+     )?;
+
+     let mut global_data = global_ai.try_borrow_mut_data()?;
+     let global = GlobalPause::load_mut(&mut global_data)?;
+     *global = GlobalPause { authority, paused: 0, bump, _reserved: [0u8; 30] };
+
+     log!("Global pause initialized. Authority:"; authority);
+     Ok(())
+ }
+
+ fn process_set_global_pause(program_id: &Pubkey, accounts: &[AccountInfo], paused: bool) -> ProgramResult {
+     check_account_count("SetGlobalPause", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let global_ai = next_account_info(account_info_iter)?; // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     validate!(global_ai.owner == program_id, StakingError::InvalidOwner, "global_pause_pda: not owned by this program");
+
+     let mut global_data = global_ai.try_borrow_mut_data()?;
+     let global = GlobalPause::load_mut(&mut global_data)?;
+     // Other program-owned accounts (pools) would also load; only the singleton PDA may be flipped
+     let expected = Pubkey::create_program_address(&[SEED_GLOBAL_PAUSE, &[global.bump]], program_id);
+     validate!(expected == Ok(*global_ai.key), ProgramError::InvalidArgument, "global_pause_pda: derivation mismatch");
+     validate!(global.authority == *authority.key, StakingError::Unauthorized, "authority: not the pause authority");
+     global.paused = paused as u8;
+
+     log!("Global pause set to {}", paused);
+     Ok(())
+ }
+
+ fn process_initialize_pool_with_metadata(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     reward_rate: u64,
+     min_lock_period: i64,
+     pool_id: u64,
+     name: [u8; 32],
+     uri: [u8; 128],
+ ) -> ProgramResult {
+     if name.iter().all(|&b| b == 0) {
+         return Err(StakingError::InvalidMetadata.into());
+     }
+     check_account_count("InitializePoolWithMetadata", accounts, 10, 13)?;
+     // The metadata PDA sits between the pool accounts, with or without the config pair, and the optional treasury
+     let (config_pda, _) = find_protocol_config_pda(program_id);
+     let metadata_index = if accounts[9].key == &config_pda { 11 } else { 9 };
+     check_account_count("InitializePoolWithMetadata", accounts, metadata_index + 1, metadata_index + 2)?;
+     let mut pool_accounts = accounts[..metadata_index].to_vec();
+     pool_accounts.extend(accounts.get(metadata_index + 1).cloned());
+     let retried = !accounts[2].data_is_empty();
+     process_initialize_pool(program_id, &pool_accounts, reward_rate, min_lock_period, pool_id, false)?;
+
+     let payer = &pool_accounts[0];
+     let pool_ai = &pool_accounts[2];
+     let system_program_ai = &pool_accounts[7];
+     let rent_sysvar_ai = &pool_accounts[8];
+     let metadata_ai = &accounts[metadata_index];
+
+     let (expected_meta, bump) = Pubkey::find_program_address(&[SEED_META, pool_ai.key.as_ref()], program_id);
+     validate!(*metadata_ai.key == expected_meta, ProgramError::InvalidArgument, "metadata_pda: derivation mismatch");
+     // A retry finds both accounts in place; a pool created without metadata doesn't get it this way
+     if retried {
+         validate!(metadata_ai.owner == program_id, ProgramError::AccountAlreadyInitialized, "metadata_pda: pool exists without metadata");
+         return Ok(());
+     }
+     if !metadata_ai.data_is_empty() {
+         return Err(ProgramError::AccountAlreadyInitialized);
+     }
+
+     let rent = Rent::from_account_info(rent_sysvar_ai)?;
+     let create_ix = solana_program::system_instruction::create_account(
+         payer.key,
+         metadata_ai.key,
+         rent.minimum_balance(POOL_METADATA_SIZE),
+         POOL_METADATA_SIZE as u64,
+         program_id,
+     );
+     invoke_signed(
+         &create_ix,
+         &[payer.clone(), metadata_ai.clone(), system_program_ai.clone()],
+         &[&[SEED_META, pool_ai.key.as_ref(), &[bump]]],
+     )?;
+
+     let mut metadata_data = metadata_ai.try_borrow_mut_data()?;
+     let meta = PoolMetadata::load_mut(&mut metadata_data)?;
+     *meta = PoolMetadata { pool: *pool_ai.key, name, uri, bump, _reserved: [0u8; 31] };
+
+     log!("Pool metadata initialized. Name={}", String::from_utf8_lossy(&name).trim_end_matches('\0'));
+     Ok(())
+ }
+
+ fn process_set_compound_mode(program_id: &Pubkey, accounts: &[AccountInfo], mode: CompoundMode) -> ProgramResult {
+     check_account_count("SetCompoundMode", accounts, 3, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let user = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+
+     validate!(user.is_signer, StakingError::Unauthorized, "user: missing signature");
+
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, user.key, us.bump)?;
+     validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+     validate!(us.owner == *user.key, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     us.compound_mode = mode as u8;
+     us.save(user_stake_ai)?;
+
+     log!("Compound mode set to {:?} for", mode; user.key);
+     Ok(())
+ }
+
+ fn process_set_max_reward_rate(program_id: &Pubkey, accounts: &[AccountInfo], max_rate: u64) -> ProgramResult {
+     check_account_count("SetMaxRewardRate", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     // Ratchet: a compromised or careless authority cannot lift the ceiling back up
+     if max_rate > pool.max_reward_rate.get() || max_rate < pool.reward_rate.get() {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     pool.max_reward_rate = max_rate.into();
+     pool.save(pool_ai)?;
+
+     log!("Max reward rate set to {}", max_rate);
+     Ok(())
+ }
+
+ fn process_airdrop_stake(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     recipients: &[(Pubkey, u64, i64)],
+ ) -> ProgramResult {
+     let recipients: Vec<_> = recipients.iter().filter(|(wallet, ..)| *wallet != Pubkey::default()).collect();
+     check_account_count("AirdropStake", accounts, 6 + recipients.len(), 6 + recipients.len())?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let authority_ata = next_account_info(account_info_iter)?; // writable
+     let mint_ai = next_account_info(account_info_iter)?; // read-only
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let vault_ai = next_account_info(account_info_iter)?; // writable
+     let token_program_ai = next_account_info(account_info_iter)?;
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     check_pool_state(&pool, true)?;
+     validate!(pool.shares_mode == 0, StakingError::InvalidConfig, "pool_pda: airdrops credit tokens, not shares");
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     validate!(*mint_ai.key == pool.mint, StakingError::InvalidMint, "mint: not the pool's mint");
+     validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
+
+     let clock = Clock::get()?;
+     let now = clock.unix_timestamp;
+     let mut total: u64 = 0;
+     for &&(user_wallet, amount, unlock_at) in &recipients {
+         let user_stake_ai = next_account_info(account_info_iter)?;
+         if amount == 0 {
+             return Err(StakingError::ZeroAmount.into());
+         }
+         if !pool.lock_within_max(unlock_at.saturating_sub(now)) {
+             return Err(StakingError::InvalidConfig.into());
+         }
+         validate!(
+             user_stake_ai.owner == program_id && user_stake_ai.data_len() == USER_STAKE_SIZE,
+             ProgramError::InvalidAccountData,
+             "user_stake_pda: not a user stake account"
+         );
+
+         let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+         let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, &user_wallet, us.bump)?;
+         validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+         validate!(us.owner == user_wallet, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+         validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+         if us.amount.get() != 0 {
+             return Err(StakingError::DoubleStake.into());
+         }
+
+         us.amount = amount.into();
+         us.start_time = now.into();
+         us.last_claim_time = now.into();
+         us.start_slot = clock.slot.into();
+         us.last_claim_slot = clock.slot.into();
+         us.unlock_at = unlock_at.into();
+         us.save(user_stake_ai)?;
+         total = total.checked_add(amount).ok_or(StakingError::Overflow)?;
+     }
+
+     // One transfer for the whole batch
+     let transfer_ix = token_ix::transfer_checked(
+         token_program_ai.key,
+         authority_ata.key,
+         mint_ai.key,
+         vault_ai.key,
+         authority.key,
+         &[],
+         total,
+         mint_decimals(mint_ai)?,
+     )?;
+     invoke(
+         &transfer_ix,
+         &[authority_ata.clone(), mint_ai.clone(), vault_ai.clone(), authority.clone(), token_program_ai.clone()],
+     )?;
+     pool.total_staked = pool.total_staked.get().checked_add(total).ok_or(StakingError::Overflow)?.into();
+     pool.save(pool_ai)?;
+
+     log!("Airdropped {} tokens to {} stakers", total, recipients.len());
+     Ok(())
+ }
+
+ fn process_set_protocol_version(program_id: &Pubkey, accounts: &[AccountInfo], version: u8) -> ProgramResult {
+     check_account_count("SetProtocolVersion", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     // Clients select the reward formula by version, so going back would misprice history
+     if version <= pool.protocol_version {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     pool.protocol_version = version;
+     pool.save(pool_ai)?;
+
+     log!("Protocol version set to {}", version);
+     Ok(())
+ }
+
+ /// FreezeStalePDA (`frozen = true`, only once idle past the pool threshold) and UnfreezePDA
+ fn process_set_frozen(program_id: &Pubkey, accounts: &[AccountInfo], user_wallet: Pubkey, frozen: bool) -> ProgramResult {
+     check_account_count(if frozen { "FreezeStalePDA" } else { "UnfreezePDA" }, accounts, 3, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, &user_wallet, us.bump)?;
+     validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+     validate!(us.owner == user_wallet, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+
+     if frozen {
+         let idle = Clock::get()?.unix_timestamp.saturating_sub(us.last_claim_time.get());
+         if idle <= pool.idle_threshold_seconds.get() {
+             return Err(StakingError::AccountNotIdle.into());
+         }
+     }
+     us.frozen = frozen as u8;
+     us.save(user_stake_ai)?;
+
+     log!("User stake frozen={} for", frozen; user_wallet);
+     Ok(())
+ }
+
+ fn process_set_mint_authority_mode(program_id: &Pubkey, accounts: &[AccountInfo], enabled: bool) -> ProgramResult {
+     check_account_count("SetMintAuthorityMode", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     pool.mint_authority_mode = enabled as u8;
+     pool.save(pool_ai)?;
+
+     log!("Mint authority mode set to {}", enabled);
+     Ok(())
+ }
+
+ fn process_mint_rewards_to_vault(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+     check_account_count("MintRewardsToVault", accounts, 5, 6)?;
+     let account_info_iter = &mut accounts.iter();
+     let funder = next_account_info(account_info_iter)?; // signer
+     let mint_ai = next_account_info(account_info_iter)?; // writable
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let vault_ai = next_account_info(account_info_iter)?; // writable
+     let token_program_ai = next_account_info(account_info_iter)?;
+     let roles_ai = account_info_iter.next();
+
+     validate!(funder.is_signer, StakingError::Unauthorized, "funder: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     if amount == 0 {
+         return Err(StakingError::ZeroAmount.into());
+     }
+
+     check_pool_role(program_id, pool_ai.key, &pool, roles_ai, PoolRole::Funder, funder.key)?;
+     validate!(pool.mint_authority_mode != 0, StakingError::MintAuthorityModeDisabled, "pool_pda: mint authority mode disabled");
+     validate!(*mint_ai.key == pool.mint, StakingError::InvalidMint, "mint: not the pool's mint");
+     validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
+
+     mint_from_pool(&pool, mint_ai, pool_ai, vault_ai, token_program_ai, amount)?;
+
+     log!("Minted {} reward tokens to vault", amount);
+     Ok(())
+ }
+
+ fn process_set_accrual_mode(program_id: &Pubkey, accounts: &[AccountInfo], use_slots: bool) -> ProgramResult {
+     check_account_count("SetAccrualMode", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     // Switching units under live stakes would reprice their accrued rewards
+     if pool.total_staked.get() != 0 {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     pool.use_slot_accrual = use_slots as u8;
+     pool.save(pool_ai)?;
+
+     log!("Accrual mode set to use_slots={}", use_slots);
+     Ok(())
+ }
+
+ fn process_reinitialize_pool(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     new_reward_rate: u64,
+     new_min_lock_period: i64,
+ ) -> ProgramResult {
+     check_account_count("ReInitializePool", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if pool.total_staked.get() != 0 {
+         return Err(StakingError::StakersStillActive.into());
+     }
+
+     // pool_id is part of the PDA seeds, so it survives the reset; so does the LockTierConfig PDA,
+     // which stakes must keep passing, and the pool's age
+     let mut fresh =
+         StakingPool::new(pool.authority, pool.vault, pool.mint, new_reward_rate, new_min_lock_period, pool.bump, pool.pool_id.get());
+     fresh.has_lock_tiers = pool.has_lock_tiers;
+     fresh.has_pool_roles = pool.has_pool_roles;
+     fresh.created_at = pool.created_at;
+     fresh.freezable_mint = pool.freezable_mint;
+     fresh.save(pool_ai)?;
+
+     log!("Pool re-initialized. Rate={}, Lock={}s", new_reward_rate, new_min_lock_period);
+     Ok(())
+ }
+
+ fn process_set_staking_window(program_id: &Pubkey, accounts: &[AccountInfo], start_utc_hour: u8, end_utc_hour: u8) -> ProgramResult {
+     check_account_count("SetStakingWindow", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if start_utc_hour > 23 || end_utc_hour > 23 {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     pool.stake_window_start_hour = start_utc_hour;
+     pool.stake_window_end_hour = end_utc_hour;
+     pool.save(pool_ai)?;
+
+     log!("Staking window set to {}:00-{}:00 UTC", start_utc_hour, end_utc_hour);
+     Ok(())
+ }
+
+ fn process_set_reward_floor(program_id: &Pubkey, accounts: &[AccountInfo], min_reward: u64) -> ProgramResult {
+     check_account_count("SetRewardFloor", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     pool.min_reward_per_claim = min_reward.into();
+     pool.save(pool_ai)?;
+
+     log!("Reward floor set to {} per claim", min_reward);
+     Ok(())
+ }
+
+ fn process_set_incentive_campaign(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     multiplier_bps: u16,
+     start: i64,
+     end: i64,
+     campaign_id: u64,
+ ) -> ProgramResult {
+     check_account_count("SetIncentiveCampaign", accounts, 6, 6)?;
+     let account_info_iter = &mut accounts.iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // read-only
+     let campaign_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if !(MIN_USER_MULTIPLIER_BPS..=MAX_USER_MULTIPLIER_BPS).contains(&multiplier_bps) {
+         return Err(StakingError::InvalidMultiplier.into());
+     }
+     // A campaign needs a real window: a zero start or an empty span is almost always a client bug
+     if start <= 0 || end <= start {
+         return Err(StakingError::InvalidConfig.into());
+     }
+
+     let (expected, bump) = find_campaign_pda(program_id, pool_ai.key, campaign_id);
+     validate!(*campaign_ai.key == expected, ProgramError::InvalidArgument, "campaign_pda: derivation mismatch");
+     if campaign_ai.data_is_empty() {
+         let rent = Rent::from_account_info(rent_sysvar_ai)?;
+         let create_ix = solana_program::system_instruction::create_account(
+             payer.key,
+             campaign_ai.key,
+             rent.minimum_balance(INCENTIVE_CAMPAIGN_SIZE),
+             INCENTIVE_CAMPAIGN_SIZE as u64,
+             program_id,
+         );
+         invoke_signed(
+             &create_ix,
+             &[payer.clone(), campaign_ai.clone(), system_program_ai.clone()],
+             &[&[SEED_CAMPAIGN, pool_ai.key.as_ref(), &campaign_id.to_le_bytes(), &[bump]]],
+         )?;
+     } else {
+         validate!(campaign_ai.owner == program_id, StakingError::InvalidOwner, "campaign_pda: not owned by this program");
+     }
+
+     let mut campaign_data = campaign_ai.try_borrow_mut_data()?;
+     let campaign = IncentiveCampaign::load_mut(&mut campaign_data)?;
+     *campaign = IncentiveCampaign {
+         pool: *pool_ai.key,
+         campaign_id: campaign_id.into(),
+         multiplier_bps: multiplier_bps.into(),
+         start_time: start.into(),
+         end_time: end.into(),
+         bump,
+         _reserved: [0u8; 5],
+     };
+
+     log!("Incentive campaign {} set: {} bps from {} to {}", campaign_id, multiplier_bps, start, end);
+     Ok(())
+ }
+
+ fn process_close_incentive_campaign(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("CloseIncentiveCampaign", accounts, 3, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer, writable
+     let pool_ai = next_account_info(account_info_iter)?;   // read-only
+     let campaign_ai = next_account_info(account_info_iter)?; // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     validate!(
+         campaign_ai.owner == program_id && campaign_ai.data_len() == INCENTIVE_CAMPAIGN_SIZE,
+         StakingError::InvalidOwner,
+         "campaign_pda: not an incentive campaign"
+     );
+
+     let campaign = *IncentiveCampaign::load(&campaign_ai.try_borrow_data()?)?;
+     validate!(campaign.pool == *pool_ai.key, ProgramError::InvalidArgument, "campaign_pda: belongs to another pool");
+     if Clock::get()?.unix_timestamp <= campaign.end_time.get() {
+         return Err(StakingError::CampaignStillActive.into());
+     }
+
+     // Drain rent to the authority and hand the emptied account back to the system program
+     let lamports = campaign_ai.lamports();
+     **campaign_ai.try_borrow_mut_lamports()? = 0;
+     **authority.try_borrow_mut_lamports()? = authority
+         .lamports()
+         .checked_add(lamports)
+         .ok_or(StakingError::Overflow)?;
+     campaign_ai.realloc(0, false)?;
+     campaign_ai.assign(&solana_program::system_program::id());
+
+     log!("Incentive campaign {} closed", campaign.campaign_id.get());
+     Ok(())
+ }
+
+ fn process_set_claim_cooldown(program_id: &Pubkey, accounts: &[AccountInfo], cooldown_seconds: i64) -> ProgramResult {
+     check_account_count("SetClaimCooldown", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if cooldown_seconds < 0 {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     pool.claim_cooldown = cooldown_seconds.into();
+     pool.save(pool_ai)?;
+
+     log!("Claim cooldown set to {}s", cooldown_seconds);
+     Ok(())
+ }
+
+ fn process_set_rebalance_params(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     target_utilization_bps: u16,
+     pool_capacity: u64,
+ ) -> ProgramResult {
+     check_account_count("SetRebalanceParams", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if target_utilization_bps > 10_000 {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     pool.pool_capacity = pool_capacity.into();
+     pool.target_utilization_bps = target_utilization_bps.into();
+     pool.base_reward_rate = pool.reward_rate;
+     pool.save(pool_ai)?;
+
+     log!(
+         "Rebalance params set: target {} bps of capacity {}, base rate {}",
+         target_utilization_bps, pool_capacity, pool.base_reward_rate.get()
+     );
+     Ok(())
+ }
+
+ fn process_rebalance(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("Rebalance", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     let capacity = pool.pool_capacity.get();
+     if capacity == 0 {
+         return Err(StakingError::InvalidConfig.into());
+     }
+
+     let utilization = pool.total_staked.get() as u128 * 10_000 / capacity as u128;
+     let target = pool.target_utilization_bps.get() as u128;
+     let old_rate = pool.reward_rate.get() as u128;
+     let base = pool.base_reward_rate.get() as u128;
+     let max_rate = pool.max_reward_rate.get() as u128;
+     // A rate already outside the band (set by UpdateConfig) is never pushed the wrong way
+     let new_rate = match utilization.cmp(&target) {
+         core::cmp::Ordering::Less => (old_rate * 105 / 100).min(base * 2).min(max_rate).max(old_rate),
+         core::cmp::Ordering::Greater => (old_rate * 95 / 100).max(base).min(old_rate),
+         core::cmp::Ordering::Equal => old_rate,
+     };
+     // At most the larger of the old rate and max_reward_rate, so it fits a u64
+     let new_rate = new_rate as u64;
+     pool.reward_rate = new_rate.into();
+     pool.save(pool_ai)?;
+
+     log!("Rebalanced at {} bps utilization: reward_rate {} -> {}", utilization, old_rate, new_rate);
+     Ok(())
+ }
+
+ fn process_set_min_accrual_delay(program_id: &Pubkey, accounts: &[AccountInfo], delay_seconds: i64) -> ProgramResult {
+     check_account_count("SetMinAccrualDelay", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if delay_seconds < 0 {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     pool.min_accrual_delay = delay_seconds.into();
+     pool.save(pool_ai)?;
+
+     log!("Minimum accrual delay set to {}s", delay_seconds);
+     Ok(())
+ }
+
+ fn process_set_withdraw_fee_bps(program_id: &Pubkey, accounts: &[AccountInfo], bps: u16) -> ProgramResult {
+     check_account_count("SetWithdrawFeeBps", accounts, 2, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if let Some(treasury_ai) = account_info_iter.next() {
+         check_treasury(treasury_ai, &pool.mint)?;
+         pool.protocol_treasury_ata = *treasury_ai.key;
+     }
+     if bps > MULTIPLIER_BPS_DENOMINATOR || (bps > 0 && pool.protocol_treasury_ata == Pubkey::default()) {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     pool.withdraw_fee_bps = bps.into();
+     pool.save(pool_ai)?;
+
+     log!("Withdrawal fee set to {} bps, treasury", bps; &pool.protocol_treasury_ata);
+     Ok(())
+ }
+
+ fn process_set_penalty_recipient(program_id: &Pubkey, accounts: &[AccountInfo], recipient: Pubkey) -> ProgramResult {
+     check_account_count("SetPenaltyRecipient", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     pool.penalty_recipient = recipient;
+     pool.save(pool_ai)?;
+
+     log!("Penalty recipient set to"; &recipient);
+     Ok(())
+ }
+
+ fn process_set_early_unstake_penalty(program_id: &Pubkey, accounts: &[AccountInfo], penalty_bps: u16) -> ProgramResult {
+     check_account_count("SetEarlyUnstakePenalty", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if penalty_bps > MULTIPLIER_BPS_DENOMINATOR {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     pool.early_unstake_penalty_bps = penalty_bps.into();
+     pool.save(pool_ai)?;
+
+     log!("Early unstake penalty set to {} bps", penalty_bps);
+     Ok(())
+ }
+
+ fn process_set_governance_address(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     governance: Pubkey,
+     required_for_rate_change_above_bps: u16,
+ ) -> ProgramResult {
+     check_account_count("SetGovernanceAddress", accounts, 2, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     // Otherwise the authority could drop governance and raise the rate in the next instruction
+     if pool.governance_address != Pubkey::default() {
+         check_governance_signer(&pool, account_info_iter.next())?;
+     }
+     pool.governance_address = governance;
+     pool.governance_rate_change_threshold_bps = required_for_rate_change_above_bps.into();
+     pool.save(pool_ai)?;
+
+     log!("Governance threshold set to {} bps, governance", required_for_rate_change_above_bps; &governance);
+     Ok(())
+ }
+
+ fn process_set_lock_reset_policy(program_id: &Pubkey, accounts: &[AccountInfo], policy: LockResetPolicy) -> ProgramResult {
+     check_account_count("SetLockResetPolicy", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     pool.lock_reset_policy = policy as u8;
+     pool.save(pool_ai)?;
+
+     log!("Lock reset policy set to {:?}", policy);
+     Ok(())
+ }
+
+ fn process_set_idle_threshold(program_id: &Pubkey, accounts: &[AccountInfo], seconds: i64) -> ProgramResult {
+     check_account_count("SetIdleThreshold", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if seconds < 0 {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     pool.idle_threshold_seconds = seconds.into();
+     pool.save(pool_ai)?;
+
+     log!("Idle threshold set to {}s", seconds);
+     Ok(())
+ }
+
+ fn process_freeze_idle_accounts(program_id: &Pubkey, accounts: &[AccountInfo], user_count: u8) -> ProgramResult {
+     if user_count == 0 || user_count > MAX_IDLE_FREEZE_BATCH {
+         return Err(ProgramError::InvalidArgument);
+     }
+     let pairs = 2 * user_count as usize;
+     check_account_count("FreezeIdleAccounts", accounts, 2 + pairs, 2 + pairs)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // read-only
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     let now = Clock::get()?.unix_timestamp;
+     for _ in 0..user_count {
+         let user_wallet = next_account_info(account_info_iter)?;
+         let user_stake_ai = next_account_info(account_info_iter)?; // writable
+         let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+         let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, user_wallet.key, us.bump)?;
+         validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+         validate!(us.owner == *user_wallet.key, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+         validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+
+         if us.frozen != 0 || now.saturating_sub(us.last_claim_time.get()) <= pool.idle_threshold_seconds.get() {
+             continue;
+         }
+         us.frozen = 1;
+         us.save(user_stake_ai)?;
+         // Compliance trail: logged regardless of the `log` feature
+         msg!("FROZEN: {}", us.owner);
+     }
+     Ok(())
+ }
+
+ fn process_initialize_protocol(program_id: &Pubkey, accounts: &[AccountInfo], super_admin: Pubkey) -> ProgramResult {
+     check_account_count("InitializeProtocol", accounts, 4, 4)?;
+     let account_info_iter = &mut accounts.iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let config_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+
+     let (expected, bump) = find_protocol_config_pda(program_id);
+     validate!(*config_ai.key == expected, ProgramError::InvalidArgument, "protocol_config_pda: derivation mismatch");
+     // Singleton: never re-initialize, or anyone could make themselves super admin
+     if !config_ai.data_is_empty() {
+         return Err(ProgramError::AccountAlreadyInitialized);
+     }
+
+     let rent = Rent::from_account_info(rent_sysvar_ai)?;
+     let create_ix = solana_program::system_instruction::create_account(
+         payer.key,
+         config_ai.key,
+         rent.minimum_balance(PROTOCOL_CONFIG_SIZE),
+         PROTOCOL_CONFIG_SIZE as u64,
+         program_id,
+     );
+     invoke_signed(
+         &create_ix,
+         &[payer.clone(), config_ai.clone(), system_program_ai.clone()],
+         &[&[SEED_PROTOCOL_CONFIG, &[bump]]],
+     )?;
+
+     let mut config_data = config_ai.try_borrow_mut_data()?;
+     let config = ProtocolConfig::load_mut(&mut config_data)?;
+     *config = ProtocolConfig {
+         super_admin,
+         pool_creation_open: 1,
+         default_fee_bps: 0.into(),
+         global_pause: 0,
+         bump,
+         pool_creators: [Pubkey::default(); MAX_POOL_CREATORS],
+         pool_count: 0.into(),
+         _reserved: [0u8; 87],
+     };
+
+     log!("Protocol config initialized. Super admin:"; super_admin);
+     Ok(())
+ }
+
+ fn process_set_protocol_config(program_id: &Pubkey, accounts: &[AccountInfo], settings: ProtocolConfig) -> ProgramResult {
+     check_account_count("SetProtocolConfig", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let super_admin = next_account_info(account_info_iter)?; // signer
+     let config_ai = next_account_info(account_info_iter)?; // writable
+
+     validate!(super_admin.is_signer, StakingError::Unauthorized, "super_admin: missing signature");
+     validate!(config_ai.owner == program_id, StakingError::InvalidOwner, "protocol_config_pda: not owned by this program");
+     if settings.default_fee_bps.get() > MULTIPLIER_BPS_DENOMINATOR || settings.super_admin == Pubkey::default() {
+         return Err(StakingError::InvalidConfig.into());
+     }
+
+     let mut config_data = config_ai.try_borrow_mut_data()?;
+     let config = ProtocolConfig::load_mut(&mut config_data)?;
+     let expected = Pubkey::create_program_address(&[SEED_PROTOCOL_CONFIG, &[config.bump]], program_id);
+     validate!(expected == Ok(*config_ai.key), ProgramError::InvalidArgument, "protocol_config_pda: derivation mismatch");
+     validate!(config.super_admin == *super_admin.key, StakingError::Unauthorized, "super_admin: not the super admin");
+     *config = ProtocolConfig { bump: config.bump, pool_count: config.pool_count, ..settings };
+
+     log!(
+         "Protocol config set: creation_open={}, default_fee_bps={}, global_pause={}, super admin:",
+         settings.pool_creation_open != 0, settings.default_fee_bps.get(), settings.global_pause != 0;
+         settings.super_admin
+     );
+     Ok(())
+ }
+
+ fn process_set_vesting_schedule(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     vesting_duration_seconds: i64,
+     cliff_seconds: i64,
+ ) -> ProgramResult {
+     check_account_count("SetVestingSchedule", accounts, 6, 6)?;
+     let account_info_iter = &mut accounts.iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // read-only
+     let vesting_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if vesting_duration_seconds <= 0 || cliff_seconds < 0 {
+         return Err(StakingError::InvalidConfig.into());
+     }
+
+     let (expected, bump) = find_vesting_pda(program_id, pool_ai.key);
+     validate!(*vesting_ai.key == expected, ProgramError::InvalidArgument, "vesting_pda: derivation mismatch");
+     if vesting_ai.data_is_empty() {
+         let rent = Rent::from_account_info(rent_sysvar_ai)?;
+         let create_ix = solana_program::system_instruction::create_account(
+             payer.key,
+             vesting_ai.key,
+             rent.minimum_balance(VESTING_CONFIG_SIZE),
+             VESTING_CONFIG_SIZE as u64,
+             program_id,
+         );
+         invoke_signed(
+             &create_ix,
+             &[payer.clone(), vesting_ai.clone(), system_program_ai.clone()],
+             &[&[SEED_VESTING, pool_ai.key.as_ref(), &[bump]]],
+         )?;
+     } else {
+         validate!(vesting_ai.owner == program_id, StakingError::InvalidOwner, "vesting_pda: not owned by this program");
+     }
+
+     let mut vesting_data = vesting_ai.try_borrow_mut_data()?;
+     let vesting = VestingConfig::load_mut(&mut vesting_data)?;
+     *vesting = VestingConfig {
+         pool: *pool_ai.key,
+         vesting_duration: vesting_duration_seconds.into(),
+         cliff_seconds: cliff_seconds.into(),
+         bump,
+         _reserved: [0u8; 15],
+     };
+
+     log!("Vesting schedule set: {}s cliff, then {}s linear", cliff_seconds, vesting_duration_seconds);
+     Ok(())
+ }
+
+ fn process_claim_vested_principal(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("ClaimVestedPrincipal", accounts, 8, 11)?;
+     let account_info_iter = &mut accounts.iter();
+     let user = next_account_info(account_info_iter)?; // signer
+     let user_ata = next_account_info(account_info_iter)?; // writable
+     let mint_ai = next_account_info(account_info_iter)?; // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let vault_ai = next_account_info(account_info_iter)?; // writable
+     let token_program_ai = next_account_info(account_info_iter)?;
+     let vesting_ai = next_account_info(account_info_iter)?; // read-only
+
+     validate!(user.is_signer, StakingError::Unauthorized, "user: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+     check_program_accounts(program_id, pool_ai, user_stake_ai)?;
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     check_pool_state(&pool, false)?;
+     validate!(pool.shares_mode == 0, StakingError::InvalidConfig, "pool_pda: vesting pays tokens, not shares");
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     validate!(us.owner == *user.key, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     validate!(us.frozen == 0, StakingError::AccountFrozen, "user_stake_pda: frozen");
+     validate!(!us.tokenized(), StakingError::PositionTokenized, "user_stake_pda: position token outstanding");
+     validate!(us.sponsored == 0, StakingError::SponsoredStake, "user_stake_pda: sponsored principal does not vest to the user");
+
+     let (expected, _) = find_vesting_pda(program_id, pool_ai.key);
+     validate!(*vesting_ai.key == expected, ProgramError::InvalidArgument, "vesting_pda: derivation mismatch");
+     validate!(
+         vesting_ai.owner == program_id && vesting_ai.data_len() == VESTING_CONFIG_SIZE,
+         StakingError::InvalidOwner,
+         "vesting_pda: not a vesting config"
+     );
+     let vesting = *VestingConfig::load(&vesting_ai.try_borrow_data()?)?;
+
+     validate!(*mint_ai.key == pool.mint, StakingError::InvalidMint, "mint: not the pool's mint");
+     validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
+     let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     let user_ata_data = spl_token::state::Account::unpack(&user_ata.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     validate!(user_ata_data.mint == *mint_ai.key, StakingError::InvalidMint, "user_ata: wrong mint");
+     validate!(user_ata_data.owner == *user.key, StakingError::InvalidOwner, "user_ata: not owned by user");
+     check_user_token_account(&pool, user.key, user_ata)?;
+
+     let clock = Clock::get()?;
+     let staked = us.amount.get();
+     if staked == 0 {
+         return Err(StakingError::NoActiveStake.into());
+     }
+     // The grant is what is still staked plus what has vested out already
+     let claimed = us.principal_claimed.get();
+     let grant = staked.checked_add(claimed).ok_or(StakingError::Overflow)?;
+     let vested = vesting
+         .vested(grant, us.start_time.get(), clock.unix_timestamp)
+         .ok_or(StakingError::LockActive)?;
+     let unlocked = vested.saturating_sub(claimed).min(staked);
+     if unlocked == 0 {
+         return Err(StakingError::NothingToClaim.into());
+     }
+
+     // Rewards accrued on the full amount are settled before it shrinks
+     let paid = settle_rewards(
+         program_id,
+         &mut pool,
+         &mut us,
+         &clock,
+         &[],
+         &vault_data,
+         mint_ai,
+         pool_ai,
+         vault_ai,
+         user_ata,
+         token_program_ai,
+         &accounts[8..],
+     )?;
+     let paid_from_vault = if pool.shares_reward_vault() { 0 } else { paid };
+     if vault_data.amount.saturating_sub(paid_from_vault) < unlocked {
+         return Err(StakingError::VaultInsufficient.into());
+     }
+     let exit_fee = bps_fee(unlocked, pool.withdraw_fee_bps.get())?;
+     transfer_from_vault(&pool, mint_ai, pool_ai, vault_ai, user_ata, token_program_ai, unlocked - exit_fee)?;
+     if exit_fee > 0 {
+         let treasury_ai = next_account_info(account_info_iter)?;
+         validate!(
+             *treasury_ai.key == pool.protocol_treasury_ata,
+             StakingError::InvalidTreasury,
+             "protocol_treasury_ata: not the pool's treasury"
+         );
+         transfer_from_vault(&pool, mint_ai, pool_ai, vault_ai, treasury_ai, token_program_ai, exit_fee)?;
+     }
+
+     let remaining = staked - unlocked;
+     us.amount = remaining.into();
+     us.principal_claimed = (claimed + unlocked).into();
+     if remaining == 0 {
+         us.start_time = 0.into();
+         us.last_stake_time = 0.into();
+         us.last_claim_time = 0.into();
+         us.start_slot = 0.into();
+         us.last_claim_slot = 0.into();
+         us.unlock_at = 0.into();
+         us.lock_override = 0;
+         us.principal_claimed = 0.into();
+     }
+     pool.total_staked = pool
+         .total_staked
+         .get()
+         .checked_sub(unlocked)
+         .ok_or(StakingError::Overflow)?
+         .into();
+     us.save(user_stake_ai)?;
+     pool.save(pool_ai)?;
+
+     log!("Vested principal claimed: {} ({} fee), {} still vesting, by", unlocked - exit_fee, exit_fee, remaining; user.key);
+     Ok(())
+ }
+
+ fn process_mint_stake_position_nft(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("MintStakePositionNFT", accounts, 12, 12)?;
+     let account_info_iter = &mut accounts.iter();
+     let user = next_account_info(account_info_iter)?; // signer, writable
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let position_mint_ai = next_account_info(account_info_iter)?; // signer, writable
+     let position_ata = next_account_info(account_info_iter)?; // writable
+     let metadata_ai = next_account_info(account_info_iter)?; // writable
+     let master_edition_ai = next_account_info(account_info_iter)?; // writable
+     let token_program_ai = next_account_info(account_info_iter)?;
+     let ata_program_ai = next_account_info(account_info_iter)?;
+     let metadata_program_ai = next_account_info(account_info_iter)?;
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(user.is_signer, StakingError::Unauthorized, "user: missing signature");
+     validate!(position_mint_ai.is_signer, StakingError::Unauthorized, "position_mint: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+     check_known_id(ata_program_ai, &spl_associated_token_account::id(), "ata_program")?;
+     check_known_id(metadata_program_ai, &mpl_token_metadata::ID, "token_metadata_program")?;
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+     check_program_accounts(program_id, pool_ai, user_stake_ai)?;
+
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.positions_transferable(), StakingError::Unauthorized, "pool_pda: positions are not transferable");
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     validate!(us.owner == *user.key, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     validate!(us.frozen == 0, StakingError::AccountFrozen, "user_stake_pda: frozen");
+     if us.amount.get() == 0 {
+         return Err(StakingError::NoActiveStake.into());
+     }
+     validate!(!us.tokenized(), StakingError::PositionTokenized, "user_stake_pda: position token outstanding");
+     // A holder taking the position over could not take on the protocol's principal or a sponsor's refund
+     validate!(us.sponsored == 0 && us.rent_sponsored == 0, ProgramError::InvalidArgument, "user_stake_pda: sponsored positions cannot move");
+
+     let expected_ata = spl_associated_token_account::get_associated_token_address(user.key, position_mint_ai.key);
+     validate!(*position_ata.key == expected_ata, ProgramError::InvalidArgument, "user_position_ata: not the user's ATA");
+     let (expected_metadata, _) = mpl_token_metadata::accounts::Metadata::find_pda(position_mint_ai.key);
+     validate!(*metadata_ai.key == expected_metadata, ProgramError::InvalidArgument, "metadata_pda: derivation mismatch");
+     let (expected_edition, _) = mpl_token_metadata::accounts::MasterEdition::find_pda(position_mint_ai.key);
+     validate!(*master_edition_ai.key == expected_edition, ProgramError::InvalidArgument, "master_edition_pda: derivation mismatch");
+
+     // A fresh mint per token: the master edition takes over its mint authority, so it can never be reused
+     let rent = Rent::from_account_info(rent_sysvar_ai)?;
+     let create_ix = solana_program::system_instruction::create_account(
+         user.key,
+         position_mint_ai.key,
+         rent.minimum_balance(spl_token::state::Mint::LEN),
+         spl_token::state::Mint::LEN as u64,
+         token_program_ai.key,
+     );
+     invoke(&create_ix, &[user.clone(), position_mint_ai.clone(), system_program_ai.clone()])?;
+     let init_ix = token_ix::initialize_mint2(token_program_ai.key, position_mint_ai.key, pool_ai.key, None, 0)?;
+     invoke(&init_ix, &[position_mint_ai.clone(), token_program_ai.clone()])?;
+     if position_ata.data_is_empty() {
+         let create_ata_ix = ata_ix::create_associated_token_account(
+             user.key,
+             user.key,
+             position_mint_ai.key,
+             token_program_ai.key,
+         );
+         invoke(
+             &create_ata_ix,
+             &[
+                 user.clone(),
+                 position_ata.clone(),
+                 user.clone(),
+                 position_mint_ai.clone(),
+                 system_program_ai.clone(),
+                 token_program_ai.clone(),
+                 ata_program_ai.clone(),
+             ],
+         )?;
+     }
+
+     let mint_ix = token_ix::mint_to(token_program_ai.key, position_mint_ai.key, position_ata.key, pool_ai.key, &[], 1)?;
+     let seeds = pool.signer_seeds();
+     invoke_signed(
+         &mint_ix,
+         &[position_mint_ai.clone(), position_ata.clone(), pool_ai.clone(), token_program_ai.clone()],
+         &[&seeds],
+     )?;
+
+     let data = mpl_types::DataV2 {
+         name: POSITION_TOKEN_NAME.to_string(),
+         symbol: POSITION_TOKEN_SYMBOL.to_string(),
+         uri: String::new(),
+         seller_fee_basis_points: 0,
+         creators: Some(vec![
+             mpl_types::Creator { address: *pool_ai.key, verified: true, share: 100 },
+             mpl_types::Creator { address: *user_stake_ai.key, verified: false, share: 0 },
+         ]),
+         collection: None,
+         uses: None,
+     };
+     mpl_ix::CreateMetadataAccountV3CpiBuilder::new(metadata_program_ai)
+         .metadata(metadata_ai)
+         .mint(position_mint_ai)
+         .mint_authority(pool_ai)
+         .payer(user)
+         .update_authority(pool_ai, true)
+         .system_program(system_program_ai)
+         .rent(Some(rent_sysvar_ai))
+         .data(data)
+         .is_mutable(false)
+         .invoke_signed(&[&seeds])?;
+     mpl_ix::CreateMasterEditionV3CpiBuilder::new(metadata_program_ai)
+         .edition(master_edition_ai)
+         .mint(position_mint_ai)
+         .update_authority(pool_ai)
+         .mint_authority(pool_ai)
+         .payer(user)
+         .metadata(metadata_ai)
+         .token_program(token_program_ai)
+         .system_program(system_program_ai)
+         .rent(Some(rent_sysvar_ai))
+         .max_supply(0)
+         .invoke_signed(&[&seeds])?;
+
+     us.position_nft_mint = *position_mint_ai.key;
+     us.save(user_stake_ai)?;
+
+     log!("Stake position tokenized as mint"; position_mint_ai.key);
+     Ok(())
+ }
+
+ fn process_burn_stake_nft(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("BurnStakeNFT", accounts, 5, 9)?;
+     let account_info_iter = &mut accounts.iter();
+     let holder = next_account_info(account_info_iter)?; // signer, writable
+     let holder_ata = next_account_info(account_info_iter)?; // writable
+     let position_mint_ai = next_account_info(account_info_iter)?; // writable
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let token_program_ai = next_account_info(account_info_iter)?;
+
+     validate!(holder.is_signer, StakingError::Unauthorized, "holder: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     validate!(us.tokenized(), ProgramError::InvalidArgument, "user_stake_pda: no position token outstanding");
+     validate!(*position_mint_ai.key == us.position_nft_mint, ProgramError::InvalidArgument, "position_mint: not the position's token");
+
+     // The token program checks that `holder` owns the token account and that it holds the token
+     let burn_ix = token_ix::burn_checked(token_program_ai.key, holder_ata.key, position_mint_ai.key, holder.key, &[], 1, 0)?;
+     invoke(&burn_ix, &[holder_ata.clone(), position_mint_ai.clone(), holder.clone(), token_program_ai.clone()])?;
+     us.position_nft_mint = Pubkey::default();
+
+     if us.owner == *holder.key {
+         us.save(user_stake_ai)?;
+         log!("Stake position token burned by"; holder.key);
+         return Ok(());
+     }
+
+     // Anyone else who burns the token takes the position over, into their own stake PDA
+     validate!(accounts.len() == 9, ProgramError::NotEnoughAccountKeys, "holder_user_stake_pda: required unless the owner burns");
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let holder_stake_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+     check_program_accounts(program_id, pool_ai, user_stake_ai)?;
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     validate!(holder_stake_ai.data_is_empty(), ProgramError::AccountAlreadyInitialized, "holder_user_stake_pda: already in use");
+
+     create_user_stake(program_id, holder, holder.key, pool_ai, holder_stake_ai, system_program_ai, rent_sysvar_ai)?;
+     let mut moved = us;
+     moved.owner = *holder.key;
+     moved.bump = UserStake::load_checked(holder_stake_ai, program_id)?.bump;
+     moved.save(holder_stake_ai)?;
+     close_program_account(user_stake_ai, holder)?;
+
+     log!("Stake position token burned; position now held by"; holder.key);
+     Ok(())
+ }
+
+ fn process_set_max_claim_interval(program_id: &Pubkey, accounts: &[AccountInfo], max_seconds: i64) -> ProgramResult {
+     check_account_count("SetMaxClaimInterval", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if max_seconds < 0 {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     pool.max_claim_interval_seconds = max_seconds.into();
+     pool.save(pool_ai)?;
+
+     log!("Max claim interval set to {}s", max_seconds);
+     Ok(())
+ }
+
+ fn process_set_reward_schedule(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     reward_rate: u64,
+     epochs: &[(i64, u64); MAX_REWARD_EPOCHS - 1],
+ ) -> ProgramResult {
+     check_account_count("SetRewardSchedule", accounts, 2, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     let used = epochs.iter().take_while(|(start_time, _)| *start_time != 0).count();
+     let starts_increase = epochs[..used].windows(2).all(|pair| pair[0].0 < pair[1].0);
+     let unused_zeroed = epochs[used..].iter().all(|&epoch| epoch == (0, 0));
+     let starts_positive = epochs[..used].iter().all(|(start_time, _)| *start_time > 0);
+     if !starts_increase || !unused_zeroed || !starts_positive {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     let rates = core::iter::once(reward_rate).chain(epochs[..used].iter().map(|(_, rate)| *rate));
+     let mut needs_governance = false;
+     for rate in rates {
+         if rate > pool.max_reward_rate.get() {
+             return Err(StakingError::InvalidConfig.into());
+         }
+         needs_governance |= pool.needs_governance_for_rate(rate);
+     }
+     if needs_governance {
+         check_governance_signer(&pool, account_info_iter.next())?;
+     }
+
+     pool.reward_rate = reward_rate.into();
+     for (slot, &(start_time, rate)) in pool.reward_schedule.iter_mut().zip(epochs) {
+         *slot = RewardEpoch { start_time: start_time.into(), reward_rate: rate.into() };
+     }
+     pool.save(pool_ai)?;
+
+     log!("Reward schedule set: rate {} plus {} later epochs", reward_rate, used);
+     Ok(())
+ }
+
+ /// The key, message and signature of the ed25519 precompile instruction just before this one, which must verify
+ /// exactly one signature with every offset pointing into its own data (`InvalidPermit` otherwise)
+ fn previous_ed25519_verify(instructions_ai: &AccountInfo) -> Result<(Pubkey, Vec<u8>, [u8; 64]), ProgramError> {
+     check_known_id(instructions_ai, &solana_program::sysvar::instructions::id(), "instructions_sysvar")?;
+     let current = solana_program::sysvar::instructions::load_current_index_checked(instructions_ai)?;
+     let index = current.checked_sub(1).ok_or(StakingError::InvalidPermit)?;
+     let verify_ix = solana_program::sysvar::instructions::load_instruction_at_checked(index as usize, instructions_ai)?;
+     validate!(
+         verify_ix.program_id == solana_program::ed25519_program::id(),
+         StakingError::InvalidPermit,
+         "permit: previous instruction is not an ed25519 verify"
+     );
+
+     // Signature count and padding, then seven u16 offsets; u16::MAX indices mean "this instruction's data"
+     let data = &verify_ix.data;
+     validate!(data.len() >= 16 && data[0] == 1, StakingError::InvalidPermit, "permit: expected exactly one ed25519 signature");
+     let field = |i: usize| u16::from_le_bytes([data[2 + 2 * i], data[3 + 2 * i]]);
+     let [signature_offset, signature_ix, key_offset, key_ix, message_offset, message_len, message_ix] =
+         core::array::from_fn(field);
+     validate!(
+         [signature_ix, key_ix, message_ix].iter().all(|&ix| ix == u16::MAX),
+         StakingError::InvalidPermit,
+         "permit: ed25519 data must be inline"
+     );
+     let bytes = |offset: u16, len: u16| data.get(offset as usize..offset as usize + len as usize);
+     let (Some(key), Some(message), Some(signature)) =
+         (bytes(key_offset, 32), bytes(message_offset, message_len), bytes(signature_offset, 64))
+     else {
+         msg!("permit: ed25519 offsets out of range");
+         return Err(StakingError::InvalidPermit.into());
+     };
+     let key = Pubkey::try_from(key).map_err(|_| StakingError::InvalidPermit)?;
+     let signature = signature.try_into().map_err(|_| StakingError::InvalidPermit)?;
+     Ok((key, message.to_vec(), signature))
+ }
+
+ /// The instruction just before this one must be the ed25519 precompile verifying exactly `signature` by
+ /// `signer` over `message`
+ fn check_ed25519_permit(instructions_ai: &AccountInfo, signer: &Pubkey, message: &[u8], signature: &[u8; 64]) -> ProgramResult {
+     let (key, signed, verified) = previous_ed25519_verify(instructions_ai)?;
+     validate!(key == *signer, StakingError::InvalidPermit, "permit: signed by another key");
+     validate!(verified == *signature, StakingError::InvalidPermit, "permit: signature mismatch");
+     validate!(signed == message, StakingError::InvalidPermit, "permit: signed another message");
+     Ok(())
+ }
+
+ fn process_stake_with_permit(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     amount: u64,
+     nonce: u64,
+     signature: &[u8; 64],
+ ) -> ProgramResult {
+     check_account_count("StakeWithPermitSignature", accounts, 11, 13)?;
+     let user = &accounts[0];
+     let pool_ai = &accounts[3];
+     let account_info_iter = &mut accounts[7..].iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let nonce_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let instructions_ai = next_account_info(account_info_iter)?;
+
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     let message = stake_permit_message(user.key, pool_ai.key, amount, nonce);
+     check_ed25519_permit(instructions_ai, user.key, &message, signature)?;
+
+     let (expected, bump) = find_permit_nonce_pda(program_id, pool_ai.key, user.key);
+     validate!(*nonce_ai.key == expected, ProgramError::InvalidArgument, "permit_nonce_pda: derivation mismatch");
+     if nonce_ai.data_is_empty() {
+         let create_ix = solana_program::system_instruction::create_account(
+             payer.key,
+             nonce_ai.key,
+             Rent::get()?.minimum_balance(PERMIT_NONCE_SIZE),
+             PERMIT_NONCE_SIZE as u64,
+             program_id,
+         );
+         invoke_signed(
+             &create_ix,
+             &[payer.clone(), nonce_ai.clone(), system_program_ai.clone()],
+             &[&[SEED_PERMIT_NONCE, pool_ai.key.as_ref(), user.key.as_ref(), &[bump]]],
+         )?;
+         let mut nonce_data = nonce_ai.try_borrow_mut_data()?;
+         *PermitNonce::load_mut(&mut nonce_data)? = PermitNonce {
+             pool: *pool_ai.key,
+             owner: *user.key,
+             next_nonce: 0.into(),
+             bump,
+             _reserved: [0u8; 23],
+         };
+     } else {
+         validate!(nonce_ai.owner == program_id, StakingError::InvalidOwner, "permit_nonce_pda: not owned by this program");
+     }
+     {
+         let mut nonce_data = nonce_ai.try_borrow_mut_data()?;
+         let record = PermitNonce::load_mut(&mut nonce_data)?;
+         validate!(nonce >= record.next_nonce.get(), StakingError::PermitNonceUsed, "permit: nonce already used");
+         record.next_nonce = nonce.checked_add(1).ok_or(StakingError::Overflow)?.into();
+     }
+
+     // The fixed stake accounts, plus the lock tier and pool stats PDAs when they trail
+     let stake_accounts: Vec<AccountInfo> = accounts[..7].iter().chain(&accounts[11..]).cloned().collect();
+     stake_tokens(program_id, &stake_accounts, amount, false, true, None, false)?;
+     log!("Stake permit {} redeemed by", nonce; payer.key);
+     Ok(())
+ }
+
+ fn process_set_lock_period_tier(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     min_amount: u64,
+     lock_seconds: i64,
+     bonus_rate_bps: u16,
+ ) -> ProgramResult {
+     check_account_count("SetLockPeriodTier", accounts, 6, 6)?;
+     let account_info_iter = &mut accounts.iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+     let tier_ai = next_account_info(account_info_iter)?;   // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     // The bonus stacks with the user multiplier, so keep the tier alone within the multiplier ceiling
+     let lock = pool.min_lock_period.get().saturating_add(lock_seconds);
+     if lock_seconds < 0 || bonus_rate_bps > MAX_USER_MULTIPLIER_BPS - MULTIPLIER_BPS_DENOMINATOR || !pool.lock_within_max(lock) {
+         return Err(StakingError::InvalidConfig.into());
+     }
+
+     let (expected, bump) = find_lock_tier_pda(program_id, pool_ai.key);
+     validate!(*tier_ai.key == expected, ProgramError::InvalidArgument, "lock_tier_pda: derivation mismatch");
+     if tier_ai.data_is_empty() {
+         let rent = Rent::from_account_info(rent_sysvar_ai)?;
+         let create_ix = solana_program::system_instruction::create_account(
+             payer.key,
+             tier_ai.key,
+             rent.minimum_balance(LOCK_TIER_CONFIG_SIZE),
+             LOCK_TIER_CONFIG_SIZE as u64,
+             program_id,
+         );
+         invoke_signed(
+             &create_ix,
+             &[payer.clone(), tier_ai.clone(), system_program_ai.clone()],
+             &[&[SEED_LOCK_TIER, pool_ai.key.as_ref(), &[bump]]],
+         )?;
+         let mut tier_data = tier_ai.try_borrow_mut_data()?;
+         let config = LockTierConfig::load_mut(&mut tier_data)?;
+         config.pool = *pool_ai.key;
+         config.bump = bump;
+     } else {
+         validate!(tier_ai.owner == program_id, StakingError::InvalidOwner, "lock_tier_pda: not owned by this program");
+     }
+
+     let mut tier_data = tier_ai.try_borrow_mut_data()?;
+     let config = LockTierConfig::load_mut(&mut tier_data)?;
+     let tier = LockTier {
+         min_amount: min_amount.into(),
+         min_lock_seconds: lock_seconds.into(),
+         bonus_rate_bps: bonus_rate_bps.into(),
+     };
+     let existing = config.lock_tiers.iter().position(|t| t.is_used() && t.min_amount.get() == min_amount);
+     if lock_seconds == 0 && bonus_rate_bps == 0 {
+         if let Some(index) = existing {
+             config.lock_tiers[index] = LockTier::default();
+         }
+     } else {
+         let index = existing
+             .or_else(|| config.lock_tiers.iter().position(|t| !t.is_used()))
+             .ok_or(StakingError::InvalidConfig)?;
+         config.lock_tiers[index] = tier;
+     }
+     pool.has_lock_tiers = 1;
+     pool.save(pool_ai)?;
+
+     log!("Lock tier set: from {} tokens, {}s extra lock, {} bps bonus", min_amount, lock_seconds, bonus_rate_bps);
+     Ok(())
+ }
+
+ fn process_set_max_total_rewards(program_id: &Pubkey, accounts: &[AccountInfo], max_total_rewards: u64) -> ProgramResult {
+     check_account_count("SetMaxTotalRewards", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     // A cap below what has been paid already cannot hold; equal to it stops emissions
+     if max_total_rewards != 0 && max_total_rewards < pool.total_rewards_distributed.get() {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     validate!(
+         max_total_rewards != 0 || pool.mint_on_claim == 0,
+         StakingError::InvalidConfig,
+         "max_total_rewards: mint-on-claim pools must stay capped"
+     );
+     pool.max_total_rewards = max_total_rewards.into();
+     pool.save(pool_ai)?;
+
+     log!("Max total rewards set to {}, {} distributed so far", max_total_rewards, pool.total_rewards_distributed.get());
+     Ok(())
+ }
+
+ fn process_get_user_reward_estimate(program_id: &Pubkey, accounts: &[AccountInfo], future_seconds: i64) -> ProgramResult {
+     check_account_count("GetUserRewardEstimate", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // read-only
+
+     check_program_accounts(program_id, pool_ai, user_stake_ai)?;
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     let us = UserStake::load_checked(user_stake_ai, program_id)?;
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     if future_seconds < 0 {
+         return Err(ProgramError::InvalidArgument);
+     }
+
+     // Settle on paper at `now`, then accrue as if the clock were `future_seconds` ahead
+     let clock = Clock::get()?;
+     let mut projected = us;
+     projected.last_claim_time = clock.unix_timestamp.max(us.last_claim_time.get()).into();
+     projected.last_claim_slot = clock.slot.max(us.last_claim_slot.get()).into();
+     let horizon = Clock {
+         unix_timestamp: clock.unix_timestamp.saturating_add(future_seconds),
+         slot: clock.slot.saturating_add(future_seconds as u64),
+         ..clock
+     };
+     let estimated = pending_rewards(&pool, &projected, &horizon, &[])?.min(pool.remaining_reward_budget());
+
+     msg!("ESTIMATE future_secs={} pending={}", future_seconds, estimated);
+     Ok(())
+ }
+
+ fn process_claim_many(program_id: &Pubkey, accounts: &[AccountInfo], count: u8) -> ProgramResult {
+     if count == 0 || count > MAX_CLAIM_MANY_POSITIONS {
+         return Err(ProgramError::InvalidArgument);
+     }
+     let groups = 5 * count as usize;
+     check_account_count("ClaimMany", accounts, 2 + groups, 3 + groups)?;
+     let account_info_iter = &mut accounts.iter();
+     let user = next_account_info(account_info_iter)?; // signer
+     let token_program_ai = next_account_info(account_info_iter)?;
+
+     validate!(user.is_signer, StakingError::Unauthorized, "user: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+     check_global_pause(program_id, &accounts[2 + groups..])?;
+
+     for _ in 0..count {
+         let user_stake_ai = next_account_info(account_info_iter)?; // writable
+         let pool_ai = next_account_info(account_info_iter)?; // writable
+         let vault_ai = next_account_info(account_info_iter)?; // writable
+         let user_ata = next_account_info(account_info_iter)?; // writable
+         let mint_ai = next_account_info(account_info_iter)?; // read-only
+         claim_position(program_id, user, user_ata, mint_ai, user_stake_ai, pool_ai, vault_ai, token_program_ai, &[], u64::MAX)?;
+     }
+     Ok(())
+ }
+
+ fn process_set_analytics_mode(program_id: &Pubkey, accounts: &[AccountInfo], enabled: bool) -> ProgramResult {
+     check_account_count("SetAnalyticsMode", accounts, 6, 6)?;
+     let account_info_iter = &mut accounts.iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+     let stats_ai = next_account_info(account_info_iter)?;  // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     let (expected, bump) = find_pool_stats_pda(program_id, pool_ai.key);
+     validate!(*stats_ai.key == expected, ProgramError::InvalidArgument, "pool_stats_pda: derivation mismatch");
+     if stats_ai.data_is_empty() {
+         let rent = Rent::from_account_info(rent_sysvar_ai)?;
+         let create_ix = solana_program::system_instruction::create_account(
+             payer.key,
+             stats_ai.key,
+             rent.minimum_balance(POOL_STATS_SIZE),
+             POOL_STATS_SIZE as u64,
+             program_id,
+         );
+         invoke_signed(
+             &create_ix,
+             &[payer.clone(), stats_ai.clone(), system_program_ai.clone()],
+             &[&[SEED_POOL_STATS, pool_ai.key.as_ref(), &[bump]]],
+         )?;
+         let mut stats_data = stats_ai.try_borrow_mut_data()?;
+         let stats = PoolStats::load_mut(&mut stats_data)?;
+         stats.pool = *pool_ai.key;
+         stats.bump = bump;
+     } else {
+         validate!(stats_ai.owner == program_id, StakingError::InvalidOwner, "pool_stats_pda: not owned by this program");
+     }
+
+     pool.analytics_enabled = enabled as u8;
+     pool.save(pool_ai)?;
+
+     log!("Analytics {}", if enabled { "enabled" } else { "disabled" });
+     Ok(())
+ }
+
+ fn process_set_referral_authority(program_id: &Pubkey, accounts: &[AccountInfo], referral_authority: Pubkey) -> ProgramResult {
+     check_account_count("SetReferralAuthority", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     pool.referral_authority = referral_authority;
+     pool.save(pool_ai)?;
+
+     log!("Referral authority set to"; &referral_authority);
+     Ok(())
+ }
+
+ fn process_set_minimum_vault_buffer(program_id: &Pubkey, accounts: &[AccountInfo], buffer: u64) -> ProgramResult {
+     check_account_count("SetMinimumVaultBuffer", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     pool.minimum_vault_buffer = buffer.into();
+     pool.save(pool_ai)?;
+
+     log!("Minimum vault buffer set to {} above {} staked", buffer, pool.total_staked.get());
+     Ok(())
+ }
+
+ fn process_set_redistribute_penalties(program_id: &Pubkey, accounts: &[AccountInfo], enabled: bool) -> ProgramResult {
+     check_account_count("SetRedistributePenalties", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     pool.redistribute_penalties = enabled as u8;
+     pool.save(pool_ai)?;
+
+     log!("Penalty redistribution set to {}", enabled);
+     Ok(())
+ }
+
+ fn process_create_pool_with_schedule(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     reward_rate: u64,
+     min_lock_period: i64,
+     pool_id: u64,
+     epochs: &[(i64, u64); MAX_REWARD_EPOCHS - 1],
+ ) -> ProgramResult {
+     check_account_count("CreatePoolWithSchedule", accounts, 9, 12)?;
+     process_initialize_pool(program_id, accounts, reward_rate, min_lock_period, pool_id, false)?;
+     // The new pool has no governance address and an uncapped max rate, so the authority alone may set it
+     let schedule_accounts = [accounts[1].clone(), accounts[2].clone()];
+     process_set_reward_schedule(program_id, &schedule_accounts, reward_rate, epochs)?;
+
+     let epoch_count = epochs.iter().take_while(|(start_time, _)| *start_time != 0).count();
+     msg!("PoolWithScheduleCreated: pool={} epochs={}", accounts[2].key, epoch_count);
+     Ok(())
+ }
+
+ fn process_initialize_cleanup_authority(program_id: &Pubkey, accounts: &[AccountInfo], authority: Pubkey) -> ProgramResult {
+     check_account_count("InitializeCleanupAuthority", accounts, 4, 4)?;
+     let account_info_iter = &mut accounts.iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let cleanup_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+
+     let (expected, bump) = find_cleanup_authority_pda(program_id);
+     validate!(*cleanup_ai.key == expected, ProgramError::InvalidArgument, "cleanup_authority_pda: derivation mismatch");
+     // Singleton, like the global pause: re-initializing would let anyone take over pool cleanup
+     if !cleanup_ai.data_is_empty() {
+         return Err(ProgramError::AccountAlreadyInitialized);
+     }
+
+     let rent = Rent::from_account_info(rent_sysvar_ai)?;
+     let create_ix = solana_program::system_instruction::create_account(
+         payer.key,
+         cleanup_ai.key,
+         rent.minimum_balance(CLEANUP_AUTHORITY_SIZE),
+         CLEANUP_AUTHORITY_SIZE as u64,
+         program_id,
+     );
+     invoke_signed(
+         &create_ix,
+         &[payer.clone(), cleanup_ai.clone(), system_program_ai.clone()],
+         &[&[SEED_CLEANUP_AUTHORITY, &[bump]]],
+     )?;
+
+     let mut cleanup_data = cleanup_ai.try_borrow_mut_data()?;
+     *CleanupAuthority::load_mut(&mut cleanup_data)? = CleanupAuthority { authority, bump, _reserved: [0u8; 47] };
+
+     log!("Cleanup authority initialized. Authority:"; authority);
+     Ok(())
+ }
+
+ fn process_close_inactive_pool(program_id: &Pubkey, accounts: &[AccountInfo], pool_age_threshold_seconds: i64) -> ProgramResult {
+     check_account_count("CloseInactivePool", accounts, 7, 7)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer, writable
+     let cleanup_ai = next_account_info(account_info_iter)?; // read-only
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let vault_ai = next_account_info(account_info_iter)?; // writable
+     let destination_ai = next_account_info(account_info_iter)?; // writable
+     let mint_ai = next_account_info(account_info_iter)?; // read-only
+     let token_program_ai = next_account_info(account_info_iter)?;
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "cleanup_authority: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+     if pool_age_threshold_seconds < 0 {
+         return Err(ProgramError::InvalidArgument);
+     }
+     validate!(cleanup_ai.owner == program_id, StakingError::InvalidOwner, "cleanup_authority_pda: not owned by this program");
+     {
+         let cleanup_data = cleanup_ai.try_borrow_data()?;
+         let cleanup = CleanupAuthority::load(&cleanup_data)?;
+         let expected = Pubkey::create_program_address(&[SEED_CLEANUP_AUTHORITY, &[cleanup.bump]], program_id);
+         validate!(expected == Ok(*cleanup_ai.key), ProgramError::InvalidArgument, "cleanup_authority_pda: derivation mismatch");
+         validate!(cleanup.authority == *authority.key, StakingError::Unauthorized, "cleanup_authority: not the cleanup authority");
+     }
+
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(*mint_ai.key == pool.mint, StakingError::InvalidMint, "mint: not the pool's mint");
+     validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
+     let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     let destination_data = spl_token::state::Account::unpack(&destination_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     validate!(destination_data.mint == pool.mint, StakingError::InvalidMint, "destination: wrong mint");
+     validate!(destination_data.owner == *authority.key, StakingError::InvalidOwner, "destination: not owned by the cleanup authority");
+
+     // Any principal at all means live positions; with none, every position is empty
+     if pool.total_staked.get() != 0 {
+         return Err(StakingError::StakersStillActive.into());
+     }
+     let age = Clock::get()?.unix_timestamp.saturating_sub(pool.created_at.get());
+     if age <= pool_age_threshold_seconds {
+         return Err(StakingError::PoolNotInactive.into());
+     }
+
+     // Sweep the leftover reward tokens, then close the vault and the pool, returning their rent
+     if vault_data.amount > 0 {
+         transfer_from_vault(&pool, mint_ai, pool_ai, vault_ai, destination_ai, token_program_ai, vault_data.amount)?;
+     }
+     let close_ix = token_ix::close_account(token_program_ai.key, vault_ai.key, authority.key, pool_ai.key, &[])?;
+     let seeds = pool.signer_seeds();
+     invoke_signed(
+         &close_ix,
+         &[vault_ai.clone(), authority.clone(), pool_ai.clone(), token_program_ai.clone()],
+         &[&seeds],
+     )?;
+     let lamports = pool_ai.lamports();
+     **pool_ai.try_borrow_mut_lamports()? = 0;
+     **authority.try_borrow_mut_lamports()? = authority.lamports().checked_add(lamports).ok_or(StakingError::Overflow)?;
+     pool_ai.realloc(0, false)?;
+     pool_ai.assign(&solana_program::system_program::id());
+
+     log!("Inactive pool closed after {}s, {} tokens swept:", age, vault_data.amount; pool_ai.key);
+     Ok(())
+ }
+
+ /// Shared by SetPoolState, SoftPause and HardPause, which differ only in the flags they leave behind
+ fn process_set_pause_flags(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     instruction: &str,
+     soft_paused: bool,
+     hard_paused: bool,
+ ) -> ProgramResult {
+     check_account_count(instruction, accounts, 2, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let pauser = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let roles_ai = account_info_iter.next();
+
+     validate!(pauser.is_signer, StakingError::Unauthorized, "pauser: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     check_pool_role(program_id, pool_ai.key, &pool, roles_ai, PoolRole::Pauser, pauser.key)?;
+     pool.soft_paused = u8::from(soft_paused);
+     pool.hard_paused = u8::from(hard_paused);
+     pool.save(pool_ai)?;
+
+     log!("{}: soft_paused={}, hard_paused={}", instruction, soft_paused, hard_paused);
+     Ok(())
+ }
+
+ fn process_set_user_lock_override(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     user_wallet: Pubkey,
+     lock_end_time: i64,
+ ) -> ProgramResult {
+     check_account_count("SetUserLockOverride", accounts, 3, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     validate!(lock_end_time >= 0, ProgramError::InvalidArgument, "lock_end_time: negative");
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, &user_wallet, us.bump)?;
+     validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+     validate!(us.owner == user_wallet, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     // An empty position has no lock to override, and its next stake would reset unlock_at anyway
+     validate!(us.amount.get() > 0, StakingError::NoActiveStake, "user_stake_pda: nothing staked");
+
+     // Clearing falls back to start_time + min_lock_period through unlock_time
+     us.unlock_at = lock_end_time.into();
+     us.lock_override = u8::from(lock_end_time != 0);
+     us.save(user_stake_ai)?;
+
+     log!("Lock override set to {} for", lock_end_time; user_wallet);
+     Ok(())
+ }
+
+ fn process_set_shares_mode(program_id: &Pubkey, accounts: &[AccountInfo], enabled: bool) -> ProgramResult {
+     check_account_count("SetSharesMode", accounts, 3, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+     let vault_ai = next_account_info(account_info_iter)?;  // read-only
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     // Live stakes would be reread as shares, or shares as tokens
+     if pool.total_staked.get() != 0 {
+         return Err(StakingError::InvalidConfig.into());
+     }
+     // Tokens already in the vault would be up for grabs by the first shares minted
+     if enabled {
+         validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
+         let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
+             .map_err(|_| ProgramError::InvalidAccountData)?;
+         validate!(vault_data.amount == 0, StakingError::InvalidConfig, "vault: must be empty to enable shares mode");
+     }
+     pool.shares_mode = enabled as u8;
+     pool.save(pool_ai)?;
+
+     log!("Shares mode set to {}", enabled);
+     Ok(())
+ }
+
+ fn process_rotate_vault(program_id: &Pubkey, accounts: &[AccountInfo], new_vault_ata: Pubkey) -> ProgramResult {
+     check_account_count("RotateVault", accounts, 6, 6)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let vault_ai = next_account_info(account_info_iter)?; // writable
+     let new_vault_ai = next_account_info(account_info_iter)?; // writable
+     let mint_ai = next_account_info(account_info_iter)?; // read-only
+     let token_program_ai = next_account_info(account_info_iter)?;
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     validate!(*mint_ai.key == pool.mint, StakingError::InvalidMint, "mint: not the pool's mint");
+     validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
+     validate!(*new_vault_ai.key == new_vault_ata, ProgramError::InvalidArgument, "new_vault: not the account named in the instruction");
+     validate!(new_vault_ata != pool.vault, StakingError::InvalidVault, "new_vault: already the pool's vault");
+
+     // Anything other than the pool PDA able to move or close the new vault's tokens would own the pool
+     validate!(*new_vault_ai.owner == spl_token::id(), StakingError::InvalidVault, "new_vault: not an SPL token account");
+     let new_vault = spl_token::state::Account::unpack(&new_vault_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     validate!(new_vault.owner == *pool_ai.key, StakingError::InvalidOwner, "new_vault: not owned by pool_pda");
+     validate!(new_vault.mint == pool.mint, StakingError::InvalidMint, "new_vault: wrong mint");
+     validate!(
+         new_vault.delegate.is_none() && new_vault.close_authority.is_none(),
+         StakingError::InvalidVault,
+         "new_vault: has a delegate or close authority"
+     );
+
+     let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
+         .map_err(|_| ProgramError::InvalidAccountData)?;
+     if vault_data.amount > 0 {
+         transfer_from_vault(&pool, mint_ai, pool_ai, vault_ai, new_vault_ai, token_program_ai, vault_data.amount)?;
+     }
+     let old_vault = pool.vault;
+     pool.vault = new_vault_ata;
+     pool.save(pool_ai)?;
+
+     msg!("VaultRotated: old={} new={}", old_vault, new_vault_ata);
+     Ok(())
+ }
+
+ fn process_initialize_user_batch(program_id: &Pubkey, accounts: &[AccountInfo], user_wallets: &[Pubkey]) -> ProgramResult {
+     if user_wallets.is_empty() || user_wallets.len() > MAX_INIT_USER_BATCH {
+         return Err(ProgramError::InvalidArgument);
+     }
+     let groups = 3 * user_wallets.len();
+     check_account_count("InitializeUserBatch", accounts, 2 + groups, 2 + groups)?;
+     let account_info_iter = &mut accounts.iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+
+     let mut created = 0u32;
+     for wallet in user_wallets {
+         let pool_ai = next_account_info(account_info_iter)?; // read-only
+         let user_stake_ai = next_account_info(account_info_iter)?; // writable
+         let system_program_ai = next_account_info(account_info_iter)?;
+         // Without the user's signature, only a real pool may get a stake account
+         StakingPool::load_checked(pool_ai, program_id)?;
+         if user_stake_initialized(program_id, user_stake_ai, wallet, pool_ai.key)? {
+             continue;
+         }
+         create_user_stake(program_id, payer, wallet, pool_ai, user_stake_ai, system_program_ai, rent_sysvar_ai)?;
+         created += 1;
+     }
+
+     log!("Created {} of {} user stake accounts, paid by", created, user_wallets.len(); payer.key);
+     Ok(())
+ }
+
+ fn process_set_pool_role(program_id: &Pubkey, accounts: &[AccountInfo], role: PoolRole, holder: Pubkey) -> ProgramResult {
+     let instruction = match role {
+         PoolRole::Funder => "SetFunder",
+         PoolRole::Pauser => "SetPauser",
+     };
+     check_account_count(instruction, accounts, 5, 5)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer, writable
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let roles_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     validate!(holder != Pubkey::default(), ProgramError::InvalidArgument, "{}: cannot be the default pubkey", role.name());
+
+     let (expected, bump) = find_pool_roles_pda(program_id, pool_ai.key);
+     validate!(*roles_ai.key == expected, ProgramError::InvalidArgument, "pool_roles_pda: derivation mismatch");
+     if roles_ai.data_is_empty() {
+         let rent = Rent::from_account_info(rent_sysvar_ai)?;
+         let create_ix = solana_program::system_instruction::create_account(
+             authority.key,
+             roles_ai.key,
+             rent.minimum_balance(POOL_ROLES_SIZE),
+             POOL_ROLES_SIZE as u64,
+             program_id,
+         );
+         invoke_signed(
+             &create_ix,
+             &[authority.clone(), roles_ai.clone(), system_program_ai.clone()],
+             &[&[SEED_POOL_ROLES, pool_ai.key.as_ref(), &[bump]]],
+         )?;
+         let mut data = roles_ai.try_borrow_mut_data()?;
+         *PoolRoles::load_mut(&mut data)? =
+             PoolRoles { pool: *pool_ai.key, funder: pool.authority, pauser: pool.authority, bump, _reserved: [0u8; 31] };
+         pool.has_pool_roles = 1;
+         pool.save(pool_ai)?;
+     }
+     validate!(roles_ai.owner == program_id, StakingError::InvalidOwner, "pool_roles_pda: not owned by this program");
+
+     let mut data = roles_ai.try_borrow_mut_data()?;
+     let roles = PoolRoles::load_mut(&mut data)?;
+     match role {
+         PoolRole::Funder => roles.funder = holder,
+         PoolRole::Pauser => roles.pauser = holder,
+     }
+
+     log!("Pool {} set to", role.name(); holder);
+     Ok(())
+ }
+
+ fn process_set_pool_description(program_id: &Pubkey, accounts: &[AccountInfo], description: &[u8; 256]) -> ProgramResult {
+     check_account_count("SetPoolDescription", accounts, 5, 5)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer, writable
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let description_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+
+     let (expected, bump) = find_pool_description_pda(program_id, pool_ai.key);
+     validate!(*description_ai.key == expected, ProgramError::InvalidArgument, "pool_description_pda: derivation mismatch");
+     if description_ai.data_is_empty() {
+         let rent = Rent::from_account_info(rent_sysvar_ai)?;
+         let create_ix = solana_program::system_instruction::create_account(
+             authority.key,
+             description_ai.key,
+             rent.minimum_balance(POOL_DESCRIPTION_SIZE),
+             POOL_DESCRIPTION_SIZE as u64,
+             program_id,
+         );
+         invoke_signed(
+             &create_ix,
+             &[authority.clone(), description_ai.clone(), system_program_ai.clone()],
+             &[&[SEED_POOL_DESCRIPTION, pool_ai.key.as_ref(), &[bump]]],
+         )?;
+     }
+     validate!(description_ai.owner == program_id, StakingError::InvalidOwner, "pool_description_pda: not owned by this program");
+
+     let updated_at = Clock::get()?.unix_timestamp;
+     let mut data = description_ai.try_borrow_mut_data()?;
+     *PoolDescription::load_mut(&mut data)? =
+         PoolDescription { pool: *pool_ai.key, description: *description, updated_at: updated_at.into(), bump, _reserved: [0u8; 23] };
+
+     msg!("PoolDescriptionUpdated: pool={} updated_at={}", pool_ai.key, updated_at);
+     Ok(())
+ }
+
+ fn process_set_mint_on_claim(program_id: &Pubkey, accounts: &[AccountInfo], enabled: bool) -> ProgramResult {
+     check_account_count("SetMintOnClaim", accounts, 3, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?; // writable
+     let mint_ai = next_account_info(account_info_iter)?; // read-only
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if enabled {
+         validate!(*mint_ai.key == pool.mint, StakingError::InvalidMint, "mint: not the pool's mint");
+         validate!(mint_ai.owner == &spl_token::id(), StakingError::InvalidMint, "mint: not an SPL token mint");
+         let mint = spl_token::state::Mint::unpack(&mint_ai.try_borrow_data()?).map_err(|_| StakingError::InvalidMint)?;
+         validate!(
+             mint.mint_authority.contains(pool_ai.key),
+             StakingError::MintAuthorityMismatch,
+             "mint: mint authority is not the pool PDA"
+         );
+         validate!(pool.max_total_rewards.get() != 0, StakingError::InvalidConfig, "pool_pda: set max_total_rewards to cap emission first");
      }
+     pool.mint_on_claim = enabled as u8;
+     pool.save(pool_ai)?;
 
-     pool.serialize(&mut &mut pool_ai.data.borrow_mut()[..])
-         .map_err(|_| ProgramError::AccountDataTooSmall)?;
+     log!("Mint on claim set to {}", enabled);
+     Ok(())
+ }
 
-     msg!(
-         "Config updated: reward_rate={:?}, min_lock_period={:?}",
-         new_reward_rate, new_min_lock_period
-     );
+ fn process_query_pending_reward(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("QueryPendingReward", accounts, 2, 2 + MAX_STACKED_CAMPAIGNS)?;
+     let account_info_iter = &mut accounts.iter();
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // read-only
+
+     check_program_accounts(program_id, pool_ai, user_stake_ai)?;
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     let us = UserStake::load_checked(user_stake_ai, program_id)?;
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+
+     let clock = Clock::get()?;
+     let campaigns = live_campaign_multipliers(program_id, pool_ai.key, account_info_iter.as_slice(), clock.unix_timestamp)?;
+     let pending = pending_rewards(&pool, &us, &clock, &campaigns)?.min(pool.remaining_reward_budget());
+
+     msg!("{}{}", PENDING_REWARD_LOG_PREFIX, pending);
      Ok(())
  }
 
- fn process_initialize_user(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+ fn process_set_claim_cooldown_override(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     user_wallet: Pubkey,
+     bypass_until: i64,
+ ) -> ProgramResult {
+     check_account_count("SetClaimCooldownOverride", accounts, 6, 6)?;
      let account_info_iter = &mut accounts.iter();
-     let payer = next_account_info(account_info_iter)?; // signer, writable
-     let user = next_account_info(account_info_iter)?;  // signer
+     let authority = next_account_info(account_info_iter)?; // signer, writable
      let pool_ai = next_account_info(account_info_iter)?; // read-only
-     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let user_stake_ai = next_account_info(account_info_iter)?; // read-only
+     let bypass_ai = next_account_info(account_info_iter)?; // writable
      let system_program_ai = next_account_info(account_info_iter)?;
      let rent_sysvar_ai = next_account_info(account_info_iter)?;
 
-     if !payer.is_signer || !user.is_signer {
-         return Err(StakingError::Unauthorized.into());
-     }
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     validate!(bypass_until >= 0, ProgramError::InvalidArgument, "bypass_until: negative");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
 
-     // Derive expected user stake PDA
-     let (expected_user_pda, _bump) = find_user_pda(program_id, pool_ai.key, user.key);
-     if *user_stake_ai.key != expected_user_pda {
-         return Err(ProgramError::InvalidArgument);
-     }
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     let us = UserStake::load_checked(user_stake_ai, program_id)?;
+     let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, &user_wallet, us.bump)?;
+     validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+     validate!(us.owner == user_wallet, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
 
-     // Create user stake PDA account using program-derived signature
-     if user_stake_ai.data_is_empty() {
+     let (expected, bump) = find_claim_cooldown_bypass_pda(program_id, user_stake_ai.key);
+     validate!(*bypass_ai.key == expected, ProgramError::InvalidArgument, "claim_cooldown_bypass_pda: derivation mismatch");
+     if bypass_ai.data_is_empty() {
          let rent = Rent::from_account_info(rent_sysvar_ai)?;
-         let required_lamports = rent.minimum_balance(USER_STAKE_SIZE);
          let create_ix = solana_program::system_instruction::create_account(
-             payer.key,
-             user_stake_ai.key,
-             required_lamports,
-             USER_STAKE_SIZE as u64,
+             authority.key,
+             bypass_ai.key,
+             rent.minimum_balance(CLAIM_COOLDOWN_BYPASS_SIZE),
+             CLAIM_COOLDOWN_BYPASS_SIZE as u64,
              program_id,
          );
-         let (_expected, user_bump) = find_user_pda(program_id, pool_ai.key, user.key);
-         let seeds: &[&[u8]] = &[SEED_USER, pool_ai.key.as_ref(), user.key.as_ref(), &[user_bump]];
          invoke_signed(
              &create_ix,
-             &[payer.clone(), user_stake_ai.clone(), system_program_ai.clone()],
-             &[seeds],
+             &[authority.clone(), bypass_ai.clone(), system_program_ai.clone()],
+             &[&[SEED_CLAIM_COOLDOWN_BYPASS, user_stake_ai.key.as_ref(), &[bump]]],
          )?;
-         if !rent.is_exempt(user_stake_ai.lamports(), user_stake_ai.data_len()) {
-             return Err(StakingError::NotRentExempt.into());
-         }
      }
+     validate!(bypass_ai.owner == program_id, StakingError::InvalidOwner, "claim_cooldown_bypass_pda: not owned by this program");
 
-     // Initialize zeroed user stake
-     let mut us = UserStake::default();
-     us.owner = *user.key;
-     us.pool = *pool_ai.key;
-     us.serialize(&mut &mut user_stake_ai.data.borrow_mut()[..])
-         .map_err(|_| ProgramError::AccountDataTooSmall)?;
+     let mut data = bypass_ai.try_borrow_mut_data()?;
+     *ClaimCooldownBypass::load_mut(&mut data)? =
+         ClaimCooldownBypass { user_stake: *user_stake_ai.key, bypass_until: bypass_until.into(), bump, _reserved: [0u8; 7] };
 
-     msg!("User stake initialized for {}", user.key);
+     log!("Claim cooldown bypassed until {} for", bypass_until; user_wallet);
      Ok(())
  }
 
- fn process_stake(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
-     if amount == 0 {
-         return Err(StakingError::ZeroAmount.into());
-     }
+ fn process_set_require_canonical_ata(program_id: &Pubkey, accounts: &[AccountInfo], enabled: bool) -> ProgramResult {
+     check_account_count("SetRequireCanonicalAta", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
 
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     pool.require_canonical_ata = enabled as u8;
+     pool.save(pool_ai)?;
+
+     log!("Canonical ATA requirement set to {}", enabled);
+     Ok(())
+ }
+
+ fn process_record_stake_on_behalf(program_id: &Pubkey, accounts: &[AccountInfo], user_wallet: Pubkey, amount: u64) -> ProgramResult {
+     check_account_count("RecordStakeOnBehalf", accounts, 4, 4)?;
      let account_info_iter = &mut accounts.iter();
-     let user = next_account_info(account_info_iter)?; // signer
-     let user_ata = next_account_info(account_info_iter)?; // writable
-     let mint_ai = next_account_info(account_info_iter)?; // read-only
-     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?; // writable
      let user_stake_ai = next_account_info(account_info_iter)?; // writable
-     let vault_ai = next_account_info(account_info_iter)?; // writable
-     let token_program_ai = next_account_info(account_info_iter)?;
+     let vault_ai = next_account_info(account_info_iter)?; // read-only
 
-     if !user.is_signer {
-         return Err(StakingError::Unauthorized.into());
-     }
-
-     // Validate PDAs
-     let (expected_user_pda, _) = find_user_pda(program_id, pool_ai.key, user.key);
-     if *user_stake_ai.key != expected_user_pda {
-         return Err(ProgramError::InvalidArgument);
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     if amount == 0 {
+         return Err(StakingError::ZeroAmount.into());
      }
+     check_program_accounts(program_id, pool_ai, user_stake_ai)?;
 
-     let mut pool: StakingPool = StakingPool::try_from_slice(&pool_ai.try_borrow_data()?)
-         .map_err(|_| ProgramError::InvalidAccountData)?;
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     check_pool_state(&pool, true)?;
+     validate!(pool.shares_mode == 0, StakingError::InvalidConfig, "pool_pda: shares are only minted for deposits");
 
-     // Verify vault ATA matches pool config
+     // The credited principal must already sit in the vault, above what backs the other stakes
+     validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
      let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
          .map_err(|_| ProgramError::InvalidAccountData)?;
-     if vault_data.owner != *pool_ai.key {
-         return Err(StakingError::InvalidOwner.into());
-     }
-     if vault_data.mint != *mint_ai.key || pool.vault != *vault_ai.key {
-         return Err(StakingError::InvalidMint.into());
-     }
-
-     // Verify user's ATA is for the same mint and owned by the user
-     let user_ata_data = spl_token::state::Account::unpack(&user_ata.try_borrow_data()?)
-         .map_err(|_| ProgramError::InvalidAccountData)?;
-     if user_ata_data.owner != *user.key {
-         return Err(StakingError::InvalidOwner.into());
-     }
-     if user_ata_data.mint != *mint_ai.key {
-         return Err(StakingError::InvalidMint.into());
-     }
-     if user_ata_data.amount < amount {
-         return Err(StakingError::VaultInsufficient.into()); // user insufficient balance
+     let new_floor = pool.vault_floor()?.checked_add(amount).ok_or(StakingError::Overflow)?;
+     if vault_data.amount < new_floor {
+         return Err(StakingError::VaultInsufficient.into());
      }
 
-     // Load user stake and ensure not already staked
-     let mut us: UserStake = UserStake::try_from_slice(&user_stake_ai.try_borrow_data()?)
-         .map_err(|_| ProgramError::InvalidAccountData)?;
-     if us.amount != 0 {
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, &user_wallet, us.bump)?;
+     validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+     validate!(us.owner == user_wallet, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     validate!(us.frozen == 0, StakingError::AccountFrozen, "user_stake_pda: frozen");
+     if us.amount.get() != 0 {
          return Err(StakingError::DoubleStake.into());
      }
-     if us.owner != *user.key || us.pool != *pool_ai.key {
-         return Err(StakingError::InvalidOwner.into());
-     }
 
-     // Transfer user's tokens into the pool vault (authority = user)
-     let transfer_ix = token_ix::transfer(
+     let clock = Clock::get()?;
+     us.amount = amount.into();
+     us.start_time = clock.unix_timestamp.into();
+     us.last_claim_time = clock.unix_timestamp.into();
+     us.last_stake_time = clock.unix_timestamp.into();
+     us.start_slot = clock.slot.into();
+     us.last_claim_slot = clock.slot.into();
+     us.unlock_at = clock.unix_timestamp.saturating_add(pool.min_lock_period.get()).into();
+     us.principal_claimed = 0.into();
+     us.effective_rate_bps = 0.into();
+     us.sponsored = 1;
+     pool.total_staked = pool.total_staked.get().checked_add(amount).ok_or(StakingError::Overflow)?.into();
+     us.save(user_stake_ai)?;
+     pool.save(pool_ai)?;
+
+     log!("Sponsored stake of {} recorded for", amount; user_wallet);
+     Ok(())
+ }
+
+ fn process_convert_sponsored_stake(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("ConvertSponsoredStake", accounts, 7, 7)?;
+     let account_info_iter = &mut accounts.iter();
+     let user = next_account_info(account_info_iter)?; // signer
+     let user_ata = next_account_info(account_info_iter)?; // writable
+     let mint_ai = next_account_info(account_info_iter)?; // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let vault_ai = next_account_info(account_info_iter)?; // writable
+     let token_program_ai = next_account_info(account_info_iter)?;
+
+     validate!(user.is_signer, StakingError::Unauthorized, "user: missing signature");
+     check_known_id(token_program_ai, &spl_token::id(), "token_program")?;
+     check_program_accounts(program_id, pool_ai, user_stake_ai)?;
+
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     check_pool_state(&pool, true)?;
+     let mut us = UserStake::load_checked(user_stake_ai, program_id)?;
+     validate!(us.owner == *user.key, StakingError::InvalidOwner, "user_stake_pda: owned by another wallet");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     validate!(us.frozen == 0, StakingError::AccountFrozen, "user_stake_pda: frozen");
+     validate!(us.sponsored != 0, ProgramError::InvalidArgument, "user_stake_pda: not a sponsored stake");
+     validate!(*mint_ai.key == pool.mint, StakingError::InvalidMint, "mint: not the pool's mint");
+     validate!(pool.vault == *vault_ai.key, StakingError::InvalidVault, "vault: not the pool's vault");
+     check_user_token_account(&pool, user.key, user_ata)?;
+
+     // Paying the principal in hands the protocol's tokens that backed it back to the vault's surplus
+     let amount = us.amount.get();
+     let transfer_ix = token_ix::transfer_checked(
          token_program_ai.key,
          user_ata.key,
+         mint_ai.key,
          vault_ai.key,
          user.key,
          &[],
          amount,
+         mint_decimals(mint_ai)?,
+     )?;
+     invoke(
+         &transfer_ix,
+         &[user_ata.clone(), mint_ai.clone(), vault_ai.clone(), user.clone(), token_program_ai.clone()],
      )?;
-     invoke(&transfer_ix, &[user_ata.clone(), vault_ai.clone(), user.clone(), token_program_ai.clone()])?;
+     us.sponsored = 0;
+     us.save(user_stake_ai)?;
 
-     // Update user stake and pool totals
-     let now = Clock::get()?.unix_timestamp;
-     us.amount = amount;
-     us.start_time = now;
-     us.last_claim_time = now;
-     us.serialize(&mut &mut user_stake_ai.data.borrow_mut()[..])
-         .map_err(|_| ProgramError::AccountDataTooSmall)?;
+     log!("Sponsored stake of {} converted by", amount; user.key);
+     Ok(())
+ }
 
-     pool.total_staked = pool
-         .total_staked
-         .checked_add(amount)
-         .ok_or(StakingError::Overflow)?;
-     pool.serialize(&mut &mut pool_ai.data.borrow_mut()[..])
-         .map_err(|_| ProgramError::AccountDataTooSmall)?;
+ fn process_initialize_global_tvl_tracker(program_id: &Pubkey, accounts: &[AccountInfo], cap: u64) -> ProgramResult {
+     check_account_count("InitializeGlobalTVLTracker", accounts, 4, 4)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer, writable
+     let tracker_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
+
+     let (expected, bump) = find_global_tvl_tracker_pda(program_id);
+     validate!(*tracker_ai.key == expected, ProgramError::InvalidArgument, "global_tvl_pda: derivation mismatch");
+     // Singleton, like the global pause: re-initializing would let anyone reset the total or lift the cap
+     if !tracker_ai.data_is_empty() {
+         return Err(ProgramError::AccountAlreadyInitialized);
+     }
+
+     let rent = Rent::from_account_info(rent_sysvar_ai)?;
+     let create_ix = solana_program::system_instruction::create_account(
+         authority.key,
+         tracker_ai.key,
+         rent.minimum_balance(GLOBAL_TVL_TRACKER_SIZE),
+         GLOBAL_TVL_TRACKER_SIZE as u64,
+         program_id,
+     );
+     invoke_signed(
+         &create_ix,
+         &[authority.clone(), tracker_ai.clone(), system_program_ai.clone()],
+         &[&[SEED_GLOBAL_TVL, &[bump]]],
+     )?;
 
-     msg!("Staked: {} tokens by {}", amount, user.key);
+     let mut tracker_data = tracker_ai.try_borrow_mut_data()?;
+     *GlobalTVLTracker::load_mut(&mut tracker_data)? = GlobalTVLTracker {
+         authority: *authority.key,
+         total_staked: 0.into(),
+         total_tvl_cap: cap.into(),
+         bump,
+         _reserved: [0u8; 23],
+     };
+
+     log!("Global TVL tracker initialized with cap {}. Authority:", cap; authority.key);
      Ok(())
  }
 
- fn process_claim(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+ fn process_set_global_stake_cap(program_id: &Pubkey, accounts: &[AccountInfo], cap: u64) -> ProgramResult {
+     check_account_count("SetGlobalStakeCap", accounts, 2, 2)?;
      let account_info_iter = &mut accounts.iter();
-     let user = next_account_info(account_info_iter)?; // signer
-     let user_ata = next_account_info(account_info_iter)?; // writable
-     let mint_ai = next_account_info(account_info_iter)?; // read-only
-     let user_stake_ai = next_account_info(account_info_iter)?; // writable
-     let pool_ai = next_account_info(account_info_iter)?; // writable
-     let vault_ai = next_account_info(account_info_iter)?; // writable
-     let token_program_ai = next_account_info(account_info_iter)?;
+     let authority = next_account_info(account_info_iter)?; // signer
+     let tracker_ai = next_account_info(account_info_iter)?; // writable
 
-     if !user.is_signer {
-         return Err(StakingError::Unauthorized.into());
-     }
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     validate!(tracker_ai.owner == program_id, StakingError::InvalidOwner, "global_tvl_pda: not owned by this program");
+     validate!(tracker_ai.data_len() == GLOBAL_TVL_TRACKER_SIZE, ProgramError::InvalidAccountData, "global_tvl_pda: wrong size");
 
-     let mut pool: StakingPool = StakingPool::try_from_slice(&pool_ai.try_borrow_data()?)
-         .map_err(|_| ProgramError::InvalidAccountData)?;
-     let mut us: UserStake = UserStake::try_from_slice(&user_stake_ai.try_borrow_data()?)
-         .map_err(|_| ProgramError::InvalidAccountData)?;
+     let mut tracker_data = tracker_ai.try_borrow_mut_data()?;
+     let tracker = GlobalTVLTracker::load_mut(&mut tracker_data)?;
+     let expected = Pubkey::create_program_address(&[SEED_GLOBAL_TVL, &[tracker.bump]], program_id);
+     validate!(expected == Ok(*tracker_ai.key), ProgramError::InvalidArgument, "global_tvl_pda: derivation mismatch");
+     validate!(tracker.authority == *authority.key, StakingError::Unauthorized, "authority: not the TVL tracker authority");
+     tracker.total_tvl_cap = cap.into();
 
-     if us.owner != *user.key || us.pool != *pool_ai.key {
-         return Err(StakingError::InvalidOwner.into());
-     }
+     log!("Global stake cap set to {}, {} staked", cap, tracker.total_staked.get());
+     Ok(())
+ }
 
-     // Verify token accounts and mint
-     let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
-         .map_err(|_| ProgramError::InvalidAccountData)?;
-     let user_ata_data = spl_token::state::Account::unpack(&user_ata.try_borrow_data()?)
-         .map_err(|_| ProgramError::InvalidAccountData)?;
-     if vault_data.owner != *pool_ai.key || pool.vault != *vault_ai.key {
-         return Err(StakingError::InvalidOwner.into());
-     }
-     if vault_data.mint != *mint_ai.key || user_ata_data.mint != *mint_ai.key {
-         return Err(StakingError::InvalidMint.into());
-     }
-     if user_ata_data.owner != *user.key {
-         return Err(StakingError::InvalidOwner.into());
-     }
+ fn process_set_access_signer(program_id: &Pubkey, accounts: &[AccountInfo], access_signer: Pubkey) -> ProgramResult {
+     check_account_count("SetAccessSigner", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
 
-     let now = Clock::get()?.unix_timestamp;
-     if now < us.last_claim_time {
-         return Err(StakingError::TimeWentBackwards.into());
-     }
-     if us.amount == 0 {
-         // Nothing to claim
-         return Ok(());
-     }
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
 
-     let elapsed = (now - us.last_claim_time) as u128;
-     let amount = us.amount as u128;
-     let rate = pool.reward_rate as u128;
-     let pending = elapsed
-         .checked_mul(amount).ok_or(StakingError::Overflow)?
-         .checked_mul(rate).ok_or(StakingError::Overflow)?
-         / 1_000_000_000u128;
-     let pending_u64: u64 = pending.try_into().map_err(|_| StakingError::Overflow)?;
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     pool.access_signer = access_signer;
+     pool.save(pool_ai)?;
 
-     if pending_u64 > 0 {
-         if vault_data.amount < pending_u64 {
-             return Err(StakingError::VaultInsufficient.into());
-         }
+     log!("Access signer set:"; &access_signer);
+     Ok(())
+ }
 
-         // Transfer reward from vault to user ATA, signed by pool PDA
-         let transfer_ix = token_ix::transfer(
-             token_program_ai.key,
-             vault_ai.key,
-             user_ata.key,
-             pool_ai.key,
-             &[],
-             pending_u64,
-         )?;
-         let (expected_pool, bump) = find_pool_pda(program_id, &vault_data.mint);
-         if *pool_ai.key != expected_pool {
-             return Err(ProgramError::InvalidArgument);
-         }
-         let seeds: &[&[u8]] = &[SEED_POOL, vault_data.mint.as_ref(), &[bump]];
-         invoke_signed(
-             &transfer_ix,
-             &[vault_ai.clone(), user_ata.clone(), pool_ai.clone(), token_program_ai.clone()],
-             &[seeds],
-         )?;
+ fn process_stake_with_access_permit(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64, expiry: i64) -> ProgramResult {
+     check_account_count("StakeWithPermit", accounts, 8, 14)?;
+     let user = &accounts[0];
+     let pool_ai = &accounts[3];
+     let instructions_ai = &accounts[7];
 
-         us.rewards_claimed = us
-             .rewards_claimed
-             .checked_add(pending_u64)
-             .ok_or(StakingError::Overflow)?;
-     }
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.access_signer != Pubkey::default(), StakingError::InvalidConfig, "pool_pda: no access signer set");
+     let (key, signed, _) = previous_ed25519_verify(instructions_ai)?;
+     validate!(key == pool.access_signer, StakingError::PermitMismatch, "permit: not signed by the access signer");
+     let message = stake_access_permit_message(pool_ai.key, user.key, expiry);
+     validate!(signed == message, StakingError::PermitMismatch, "permit: for another pool, user or expiry");
+     validate!(Clock::get()?.unix_timestamp <= expiry, StakingError::PermitExpired, "permit: expired");
+
+     // The Stake accounts without the instructions sysvar
+     let stake_accounts: Vec<AccountInfo> = accounts[..7].iter().chain(&accounts[8..]).cloned().collect();
+     stake_tokens(program_id, &stake_accounts, amount, false, false, None, true)
+ }
 
-     us.last_claim_time = now;
-     us.serialize(&mut &mut user_stake_ai.data.borrow_mut()[..])
-         .map_err(|_| ProgramError::AccountDataTooSmall)?;
+ fn process_set_stake_position_transferable(program_id: &Pubkey, accounts: &[AccountInfo], transferable: bool) -> ProgramResult {
+     check_account_count("SetStakePositionTransferable", accounts, 2, 2)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
 
-     msg!("Rewards claimed: {} by {}", pending_u64, user.key);
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     pool.position_transfers_disabled = u8::from(!transferable);
+     pool.save(pool_ai)?;
+
+     log!("Stake positions transferable: {}", transferable);
      Ok(())
  }
 
- fn process_unstake(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+ fn process_set_pool_shared(program_id: &Pubkey, accounts: &[AccountInfo], sibling_vault: Pubkey) -> ProgramResult {
+     let accounts_needed = if sibling_vault == Pubkey::default() { 2 } else { 4 };
+     check_account_count("SetPoolShared", accounts, accounts_needed, accounts_needed)?;
      let account_info_iter = &mut accounts.iter();
-     let user = next_account_info(account_info_iter)?; // signer
-     let user_ata = next_account_info(account_info_iter)?; // writable
-     let mint_ai = next_account_info(account_info_iter)?; // read-only
-     let user_stake_ai = next_account_info(account_info_iter)?; // writable
-     let pool_ai = next_account_info(account_info_iter)?; // writable
-     let vault_ai = next_account_info(account_info_iter)?; // writable
-     let token_program_ai = next_account_info(account_info_iter)?;
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?;   // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
 
-     if !user.is_signer {
-         return Err(StakingError::Unauthorized.into());
+     let mut pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     if sibling_vault != Pubkey::default() {
+         let sibling_vault_ai = next_account_info(account_info_iter)?;
+         let sibling_ai = next_account_info(account_info_iter)?;
+         validate!(*sibling_vault_ai.key == sibling_vault, StakingError::InvalidVault, "sibling_vault: not the vault being shared");
+         validate!(*sibling_vault_ai.owner == spl_token::id(), StakingError::InvalidVault, "sibling_vault: not a token account");
+         let vault_data = spl_token::state::Account::unpack(&sibling_vault_ai.try_borrow_data()?)
+             .map_err(|_| ProgramError::InvalidAccountData)?;
+         validate!(vault_data.mint == pool.mint, StakingError::InvalidMint, "sibling_vault: wrong mint");
+         validate!(vault_data.owner == *sibling_ai.key, StakingError::InvalidOwner, "sibling_vault: not owned by sibling_pool_pda");
+         load_sibling_pool(program_id, &pool, pool_ai.key, sibling_ai)?;
      }
+     pool.shared_reward_vault = sibling_vault;
+     pool.save(pool_ai)?;
 
-     let mut pool: StakingPool = StakingPool::try_from_slice(&pool_ai.try_borrow_data()?)
-         .map_err(|_| ProgramError::InvalidAccountData)?;
-     let mut us: UserStake = UserStake::try_from_slice(&user_stake_ai.try_borrow_data()?)
-         .map_err(|_| ProgramError::InvalidAccountData)?;
+     log!("Reward vault set to"; &sibling_vault);
+     Ok(())
+ }
 
-     if us.owner != *user.key || us.pool != *pool_ai.key {
-         return Err(StakingError::InvalidOwner.into());
-     }
+ fn process_create_boost_campaign(
+     program_id: &Pubkey,
+     accounts: &[AccountInfo],
+     campaign_id: u64,
+     boost_bps: u16,
+     start_time: i64,
+     end_time: i64,
+ ) -> ProgramResult {
+     check_account_count("CreateBoostCampaign", accounts, 6, 6)?;
+     let account_info_iter = &mut accounts.iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let authority = next_account_info(account_info_iter)?; // signer
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let campaign_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
 
-     // Verify token accounts and mint
-     let vault_data = spl_token::state::Account::unpack(&vault_ai.try_borrow_data()?)
-         .map_err(|_| ProgramError::InvalidAccountData)?;
-     let user_ata_data = spl_token::state::Account::unpack(&user_ata.try_borrow_data()?)
-         .map_err(|_| ProgramError::InvalidAccountData)?;
-     if vault_data.owner != *pool_ai.key || pool.vault != *vault_ai.key {
-         return Err(StakingError::InvalidOwner.into());
-     }
-     if vault_data.mint != *mint_ai.key || user_ata_data.mint != *mint_ai.key {
-         return Err(StakingError::InvalidMint.into());
-     }
-     if user_ata_data.owner != *user.key {
-         return Err(StakingError::InvalidOwner.into());
-     }
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     check_known_id(rent_sysvar_ai, &solana_program::sysvar::rent::id(), "rent_sysvar")?;
 
-     let now = Clock::get()?.unix_timestamp;
-     if now < us.start_time {
-         return Err(StakingError::TimeWentBackwards.into());
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     // The boosted multiplier must stay within what a user multiplier may be
+     if boost_bps == 0 || boost_bps > MAX_USER_MULTIPLIER_BPS - MULTIPLIER_BPS_DENOMINATOR {
+         return Err(StakingError::InvalidMultiplier.into());
      }
-     let staked = us.amount;
-     if staked == 0 {
-         return Ok(());
+     if start_time <= 0 || end_time <= start_time {
+         return Err(StakingError::InvalidConfig.into());
      }
-     let elapsed = now - us.start_time;
-     if elapsed < pool.min_lock_period {
-         return Err(StakingError::LockActive.into());
+
+     let (expected, bump) = find_boost_campaign_pda(program_id, pool_ai.key, campaign_id);
+     validate!(*campaign_ai.key == expected, ProgramError::InvalidArgument, "boost_campaign_pda: derivation mismatch");
+     validate!(campaign_ai.data_is_empty(), ProgramError::AccountAlreadyInitialized, "boost_campaign_pda: already exists");
+     let rent = Rent::from_account_info(rent_sysvar_ai)?;
+     let create_ix = solana_program::system_instruction::create_account(
+         payer.key,
+         campaign_ai.key,
+         rent.minimum_balance(BOOST_CAMPAIGN_SIZE),
+         BOOST_CAMPAIGN_SIZE as u64,
+         program_id,
+     );
+     invoke_signed(
+         &create_ix,
+         &[payer.clone(), campaign_ai.clone(), system_program_ai.clone()],
+         &[&[SEED_BOOST_CAMPAIGN, pool_ai.key.as_ref(), &campaign_id.to_le_bytes(), &[bump]]],
+     )?;
+
+     let mut campaign_data = campaign_ai.try_borrow_mut_data()?;
+     *BoostCampaign::load_mut(&mut campaign_data)? = BoostCampaign {
+         pool: *pool_ai.key,
+         campaign_id: campaign_id.into(),
+         boost_bps: boost_bps.into(),
+         start_time: start_time.into(),
+         end_time: end_time.into(),
+         bump,
+         _reserved: [0u8; 29],
+     };
+
+     log!("Boost campaign {} created: +{} bps from {} to {}", campaign_id, boost_bps, start_time, end_time);
+     Ok(())
+ }
+
+ fn process_close_boost_campaign(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("CloseBoostCampaign", accounts, 3, 3)?;
+     let account_info_iter = &mut accounts.iter();
+     let authority = next_account_info(account_info_iter)?; // signer, writable
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let campaign_ai = next_account_info(account_info_iter)?; // writable
+
+     validate!(authority.is_signer, StakingError::Unauthorized, "authority: missing signature");
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     validate!(pool.authority == *authority.key, StakingError::Unauthorized, "authority: not the pool authority");
+     validate!(
+         campaign_ai.owner == program_id && campaign_ai.data_len() == BOOST_CAMPAIGN_SIZE,
+         StakingError::InvalidOwner,
+         "boost_campaign_pda: not a boost campaign"
+     );
+
+     let campaign = *BoostCampaign::load(&campaign_ai.try_borrow_data()?)?;
+     validate!(campaign.pool == *pool_ai.key, ProgramError::InvalidArgument, "boost_campaign_pda: belongs to another pool");
+     if Clock::get()?.unix_timestamp <= campaign.end_time.get() {
+         return Err(StakingError::CampaignStillActive.into());
      }
 
-     // First, settle any pending rewards to keep accounting consistent
-     // Reuse claim logic inline for simplicity
-     {
-         if now < us.last_claim_time {
-             return Err(StakingError::TimeWentBackwards.into());
-         }
-         let elapsed_reward = (now - us.last_claim_time) as u128;
-         let pending = elapsed_reward
-             .checked_mul(us.amount as u128).ok_or(StakingError::Overflow)?
-             .checked_mul(pool.reward_rate as u128).ok_or(StakingError::Overflow)?
-             / 1_000_000_000u128;
-         let pending_u64: u64 = pending.try_into().map_err(|_| StakingError::Overflow)?;
-         if pending_u64 > 0 {
-             if vault_data.amount < pending_u64 {
-                 return Err(StakingError::VaultInsufficient.into());
-             }
-             let transfer_ix = token_ix::transfer(
-                 token_program_ai.key,
-                 vault_ai.key,
-                 user_ata.key,
-                 pool_ai.key,
-                 &[],
-                 pending_u64,
-             )?;
-             let (expected_pool, bump) = find_pool_pda(program_id, &vault_data.mint);
-             if *pool_ai.key != expected_pool {
-                 return Err(ProgramError::InvalidArgument);
-             }
-             let seeds: &[&[u8]] = &[SEED_POOL, vault_data.mint.as_ref(), &[bump]];
-             invoke_signed(
-                 &transfer_ix,
-                 &[vault_ai.clone(), user_ata.clone(), pool_ai.clone(), token_program_ai.clone()],
-                 &[seeds],
-             )?;
-             us.rewards_claimed = us
-                 .rewards_claimed
-                 .checked_add(pending_u64)
-                 .ok_or(StakingError::Overflow)?;
-         }
+     // Drain rent to the authority and hand the emptied account back to the system program
+     let lamports = campaign_ai.lamports();
+     **campaign_ai.try_borrow_mut_lamports()? = 0;
+     **authority.try_borrow_mut_lamports()? = authority
+         .lamports()
+         .checked_add(lamports)
+         .ok_or(StakingError::Overflow)?;
+     campaign_ai.realloc(0, false)?;
+     campaign_ai.assign(&solana_program::system_program::id());
+
+     log!("Boost campaign {} closed", campaign.campaign_id.get());
+     Ok(())
+ }
+
+ /// `account` at an older pool layout grown to STAKING_POOL_SIZE, or None unless it is the pool PDA of
+ /// `mint`. Bytes past the old end take StakingPool::new's defaults.
+ fn migrated_pool(program_id: &Pubkey, account: &AccountInfo, mint: &Pubkey) -> Result<Option<Vec<u8>>, ProgramError> {
+     let data = account.try_borrow_data()?;
+     let mut bytes = vec![0u8; STAKING_POOL_SIZE];
+     bytes[..data.len()].copy_from_slice(&data);
+     let old = *StakingPool::load(&bytes)?;
+     // Pools from before the mint was recorded left it zeroed, but their PDA was always seeded by it
+     validate!(old.mint == Pubkey::default() || old.mint == *mint, StakingError::InvalidMint, "mint: not the pool's mint");
+     let defaults = StakingPool::new(
+         old.authority,
+         old.vault,
+         *mint,
+         old.reward_rate.get(),
+         old.min_lock_period.get(),
+         old.bump,
+         old.pool_id.get(),
+     );
+     bytes[data.len()..].copy_from_slice(&bytemuck::bytes_of(&defaults)[data.len()..]);
+     let pool = StakingPool::load_mut(&mut bytes)?;
+     pool.mint = *mint;
+     if Pubkey::create_program_address(&pool.signer_seeds(), program_id) != Ok(*account.key) {
+         return Ok(None);
      }
+     Ok(Some(bytes))
+ }
 
-     // Now return principal
-     if vault_data.amount < staked {
-         return Err(StakingError::VaultInsufficient.into());
+ /// `account` at an older user stake layout grown to USER_STAKE_SIZE, or None unless it is a user stake PDA.
+ /// Every field added since defaults to zero.
+ fn migrated_user_stake(program_id: &Pubkey, account: &AccountInfo) -> Result<Option<Vec<u8>>, ProgramError> {
+     let data = account.try_borrow_data()?;
+     let mut bytes = vec![0u8; USER_STAKE_SIZE];
+     bytes[..data.len()].copy_from_slice(&data);
+     let us = UserStake::load_mut(&mut bytes)?;
+     let seeds: &[&[u8]] = &[SEED_USER, us.pool.as_ref(), us.owner.as_ref(), &[us.bump]];
+     if Pubkey::create_program_address(seeds, program_id) != Ok(*account.key) {
+         return Ok(None);
      }
-     let transfer_ix = token_ix::transfer(
-         token_program_ai.key,
-         vault_ai.key,
-         user_ata.key,
-         pool_ai.key,
-         &[],
-         staked,
-     )?;
-     let (expected_pool, bump) = find_pool_pda(program_id, &vault_data.mint);
-     if *pool_ai.key != expected_pool {
-         return Err(ProgramError::InvalidArgument);
+     // A token minted under the old flag keeps its PDA mint, now recorded like any other
+     if us.legacy_position_nft != 0 {
+         us.position_nft_mint = find_position_mint_pda(program_id, account.key).0;
+         us.legacy_position_nft = 0;
      }
-     let seeds: &[&[u8]] = &[SEED_POOL, vault_data.mint.as_ref(), &[bump]];
-     invoke_signed(
-         &transfer_ix,
-         &[vault_ai.clone(), user_ata.clone(), pool_ai.clone(), token_program_ai.clone()],
-         &[seeds],
-     )?;
+     Ok(Some(bytes))
+ }
 
-     // Update states
-     us.amount = 0;
-     us.start_time = 0;
-     us.last_claim_time = 0;
-     us.serialize(&mut &mut user_stake_ai.data.borrow_mut()[..])
-         .map_err(|_| ProgramError::AccountDataTooSmall)?;
+ fn process_migrate_account(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("MigrateAccount", accounts, 3, 4)?;
+     let account_info_iter = &mut accounts.iter();
+     let payer = next_account_info(account_info_iter)?; // signer, writable
+     let account_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let mint_ai = account_info_iter.next();
 
-     pool.total_staked = pool
-         .total_staked
-         .checked_sub(staked)
-         .ok_or(StakingError::Overflow)?;
-     pool.serialize(&mut &mut pool_ai.data.borrow_mut()[..])
-         .map_err(|_| ProgramError::AccountDataTooSmall)?;
+     validate!(payer.is_signer, StakingError::Unauthorized, "payer: missing signature");
+     check_known_id(system_program_ai, &solana_program::system_program::id(), "system_program")?;
+     validate!(account_ai.owner == program_id, StakingError::InvalidOwner, "account: not owned by this program");
 
-     msg!("Unstaked: {} returned to {}", staked, user.key);
+     let old_len = account_ai.data_len();
+     let mut migrated = match mint_ai {
+         Some(mint_ai) if LEGACY_STAKING_POOL_SIZES.contains(&old_len) => migrated_pool(program_id, account_ai, mint_ai.key)?,
+         _ => None,
+     };
+     if migrated.is_none() && LEGACY_USER_STAKE_SIZES.contains(&old_len) {
+         migrated = migrated_user_stake(program_id, account_ai)?;
+     }
+     let Some(migrated) = migrated else {
+         msg!("account: not a pool or user stake at an older layout");
+         return Err(ProgramError::InvalidAccountData);
+     };
+
+     let shortfall = Rent::get()?.minimum_balance(migrated.len()).saturating_sub(account_ai.lamports());
+     if shortfall > 0 {
+         invoke(
+             &solana_program::system_instruction::transfer(payer.key, account_ai.key, shortfall),
+             &[payer.clone(), account_ai.clone(), system_program_ai.clone()],
+         )?;
+     }
+     account_ai.realloc(migrated.len(), false)?;
+     account_ai.try_borrow_mut_data()?.copy_from_slice(&migrated);
+
+     log!("Account migrated from {} to {} bytes:", old_len, migrated.len(); account_ai.key);
      Ok(())
  }
 
+ fn process_transfer_stake_position(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+     check_account_count("TransferStakePosition", accounts, 7, 7)?;
+     let account_info_iter = &mut accounts.iter();
+     let owner = next_account_info(account_info_iter)?; // signer, writable
+     let pool_ai = next_account_info(account_info_iter)?; // read-only
+     let user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let new_owner = next_account_info(account_info_iter)?; // read-only
+     let new_user_stake_ai = next_account_info(account_info_iter)?; // writable
+     let system_program_ai = next_account_info(account_info_iter)?;
+     let rent_sysvar_ai = next_account_info(account_info_iter)?;
+
+     validate!(owner.is_signer, StakingError::Unauthorized, "owner: missing signature");
+     check_program_accounts(program_id, pool_ai, user_stake_ai)?;
+
+     let pool = StakingPool::load_checked(pool_ai, program_id)?;
+     check_pool_state(&pool, false)?;
+     validate!(pool.positions_transferable(), StakingError::Unauthorized, "pool_pda: positions are not transferable");
+     let us = UserStake::load_checked(user_stake_ai, program_id)?;
+     let expected_user_pda = user_pda_with_bump(program_id, pool_ai.key, owner.key, us.bump)?;
+     validate!(*user_stake_ai.key == expected_user_pda, ProgramError::InvalidArgument, "user_stake_pda: derivation mismatch");
+     validate!(us.pool == *pool_ai.key, StakingError::InvalidOwner, "user_stake_pda: belongs to another pool");
+     validate!(us.frozen == 0, StakingError::AccountFrozen, "user_stake_pda: frozen");
+     validate!(!us.tokenized(), StakingError::PositionTokenized, "user_stake_pda: position token outstanding");
+     // The protocol's principal and a sponsor's RentPayer record stay tied to the original account
+     validate!(us.sponsored == 0 && us.rent_sponsored == 0, ProgramError::InvalidArgument, "user_stake_pda: sponsored positions cannot move");
+     if us.amount.get() == 0 {
+         return Err(StakingError::NoActiveStake.into());
+     }
+     validate!(new_user_stake_ai.data_is_empty(), ProgramError::AccountAlreadyInitialized, "new_user_stake_pda: already in use");
+
+     create_user_stake(program_id, owner, new_owner.key, pool_ai, new_user_stake_ai, system_program_ai, rent_sysvar_ai)?;
+     let mut moved = us;
+     moved.owner = *new_owner.key;
+     moved.bump = UserStake::load_checked(new_user_stake_ai, program_id)?.bump;
+     moved.save(new_user_stake_ai)?;
+     close_program_account(user_stake_ai, owner)?;
 
+     log!("Stake position of {} transferred to", us.amount.get(); new_owner.key);
+     Ok(())
+ }
@@ -0,0 +1,417 @@
+ //! A ready-to-use `solana-program-test` setup for staking_program: a mint, a pool with a funded
+ //! vault, and users with token balances, plus helpers for the common user flows.
+ //!
+ //! ```ignore
+ //! let mut fixture = StakingFixture::new(program_id).with_mint(6).with_user(1_000_000).build().await;
+ //! let user = fixture.user(0);
+ //! fixture.stake(&user, 500_000).await.unwrap();
+ //! fixture.warp_seconds(60).await;
+ //! fixture.claim(&user).await.unwrap();
+ //! ```
+
+ use solana_program::{
+     account_info::AccountInfo,
+     entrypoint::ProgramResult,
+     instruction::{AccountMeta, Instruction},
+     program::{invoke, invoke_signed},
+     program_error::ProgramError,
+     program_option::COption,
+     program_pack::Pack,
+     pubkey::Pubkey,
+     sysvar::{clock::Clock, rent::Rent, Sysvar},
+ };
+ use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+ use solana_sdk::{
+     signature::{Keypair, Signer},
+     system_instruction,
+     transaction::Transaction,
+ };
+ use spl_associated_token_account::get_associated_token_address;
+ use spl_token::{
+     instruction::{self as token_ix, AuthorityType},
+     state::{Account as TokenAccount, Mint},
+ };
+ use staking_program::{
+     find_pool_pda, find_protocol_config_pda, find_registry_pda, find_user_pda, StakingInstruction, StakingPool, UserStake,
+ };
+
+ /// Unix timestamp every fixture starts at, so reward math is reproducible
+ pub const FIXTURE_START: i64 = 1_700_000_000;
+
+ /// A wallet with an ATA for the fixture mint and an initialized stake PDA
+ pub struct FixtureUser {
+     pub wallet: Keypair,
+     pub ata: Pubkey,
+     pub stake_pda: Pubkey,
+ }
+
+ impl Clone for FixtureUser {
+     fn clone(&self) -> Self {
+         Self { wallet: self.wallet.insecure_clone(), ata: self.ata, stake_pda: self.stake_pda }
+     }
+ }
+
+ /// Configures a fixture; see [`StakingFixture::new`]
+ pub struct StakingFixtureBuilder {
+     program_test: ProgramTest,
+     program_id: Pubkey,
+     decimals: u8,
+     reward_rate: u64,
+     min_lock_period: i64,
+     vault_funding: u64,
+     user_balances: Vec<u64>,
+ }
+
+ impl StakingFixtureBuilder {
+     /// Mint decimals (default 9)
+     pub fn with_mint(mut self, decimals: u8) -> Self {
+         self.decimals = decimals;
+         self
+     }
+
+     /// Pool reward rate, 1e9-scaled per token-second (default 5_000_000)
+     pub fn with_reward_rate(mut self, reward_rate: u64) -> Self {
+         self.reward_rate = reward_rate;
+         self
+     }
+
+     /// Pool minimum lock in seconds (default 0)
+     pub fn with_min_lock_period(mut self, min_lock_period: i64) -> Self {
+         self.min_lock_period = min_lock_period;
+         self
+     }
+
+     /// Reward tokens minted into the vault after the pool is created (default 1_000_000_000_000)
+     pub fn with_vault_funding(mut self, amount: u64) -> Self {
+         self.vault_funding = amount;
+         self
+     }
+
+     /// Adds a user holding `balance` tokens; users are indexed in the order they are added
+     pub fn with_user(mut self, balance: u64) -> Self {
+         self.user_balances.push(balance);
+         self
+     }
+
+     /// Access to the underlying `ProgramTest`, e.g. to add other programs before starting
+     pub fn program_test_mut(&mut self) -> &mut ProgramTest {
+         &mut self.program_test
+     }
+
+     /// Starts the bank, then creates the mint, pool, vault funding and users at `FIXTURE_START`
+     pub async fn build(self) -> StakingFixture {
+         let context = self.program_test.start_with_context().await;
+         let mint = Keypair::new();
+         let authority = Keypair::new();
+         let (pool_pda, _) = find_pool_pda(&self.program_id, &mint.pubkey(), 0);
+         let vault = get_associated_token_address(&pool_pda, &mint.pubkey());
+         let mut fixture = StakingFixture {
+             context,
+             program_id: self.program_id,
+             mint,
+             authority,
+             pool_pda,
+             vault,
+             users: Vec::with_capacity(self.user_balances.len()),
+         };
+         fixture.warp_to_timestamp(FIXTURE_START).await;
+
+         let payer = fixture.payer().pubkey();
+         let mint_rent = fixture.context.banks_client.get_rent().await.unwrap().minimum_balance(spl_token::state::Mint::LEN);
+         let ixs = [
+             system_instruction::create_account(
+                 &payer,
+                 &fixture.mint.pubkey(),
+                 mint_rent,
+                 spl_token::state::Mint::LEN as u64,
+                 &spl_token::id(),
+             ),
+             token_ix::initialize_mint(&spl_token::id(), &fixture.mint.pubkey(), &payer, None, self.decimals).unwrap(),
+         ];
+         let mint_signer = fixture.mint.insecure_clone();
+         fixture.process(&ixs, &[&mint_signer]).await.unwrap();
+
+         let init_pool = fixture.instruction(
+             vec![
+                 AccountMeta::new(payer, true),
+                 AccountMeta::new_readonly(fixture.authority.pubkey(), true),
+                 AccountMeta::new(pool_pda, false),
+                 AccountMeta::new_readonly(fixture.mint.pubkey(), false),
+                 AccountMeta::new(vault, false),
+                 AccountMeta::new_readonly(spl_token::id(), false),
+                 AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+                 AccountMeta::new_readonly(solana_program::system_program::id(), false),
+                 AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+                 AccountMeta::new(find_protocol_config_pda(&fixture.program_id).0, false),
+                 AccountMeta::new(find_registry_pda(&fixture.program_id, 0).0, false),
+             ],
+             StakingInstruction::InitializePool { reward_rate: self.reward_rate, min_lock_period: self.min_lock_period, pool_id: 0, allow_freezable_mint: false },
+         );
+         let authority = fixture.authority.insecure_clone();
+         fixture.process(&[init_pool], &[&authority]).await.unwrap();
+         if self.vault_funding > 0 {
+             fixture.mint_to(vault, self.vault_funding).await.unwrap();
+         }
+
+         for balance in self.user_balances {
+             let user = fixture.add_user(balance).await.unwrap();
+             fixture.users.push(user);
+         }
+         fixture
+     }
+ }
+
+ /// A started bank with one pool; the context payer is also the mint authority
+ pub struct StakingFixture {
+     pub context: ProgramTestContext,
+     pub program_id: Pubkey,
+     pub mint: Keypair,
+     pub authority: Keypair,
+     pub pool_pda: Pubkey,
+     pub vault: Pubkey,
+     pub users: Vec<FixtureUser>,
+ }
+
+ impl StakingFixture {
+     /// Starts a builder that runs staking_program natively under `program_id`
+     #[allow(clippy::new_ret_no_self)]
+     pub fn new(program_id: Pubkey) -> StakingFixtureBuilder {
+         let mut program_test = ProgramTest::new("staking_program", program_id, processor!(staking_program::process_instruction));
+         program_test.add_program("mpl_token_metadata_stub", mpl_token_metadata::ID, processor!(token_metadata_stub));
+         StakingFixtureBuilder {
+             program_test,
+             program_id,
+             decimals: 9,
+             reward_rate: 5_000_000,
+             min_lock_period: 0,
+             vault_funding: 1_000_000_000_000,
+             user_balances: Vec::new(),
+         }
+     }
+
+     pub fn payer(&self) -> &Keypair {
+         &self.context.payer
+     }
+
+     /// A handle to the `index`th user added with `with_user` or `add_user`
+     pub fn user(&self, index: usize) -> FixtureUser {
+         self.users[index].clone()
+     }
+
+     /// Signs with the payer plus `signers` against a fresh blockhash, so identical
+     /// instructions can be resubmitted
+     pub async fn process(&mut self, ixs: &[Instruction], signers: &[&Keypair]) -> Result<(), BanksClientError> {
+         let blockhash = self.context.get_new_latest_blockhash().await?;
+         let mut all_signers: Vec<&Keypair> = vec![&self.context.payer];
+         all_signers.extend_from_slice(signers);
+         let tx = Transaction::new_signed_with_payer(ixs, Some(&self.context.payer.pubkey()), &all_signers, blockhash);
+         self.context.banks_client.process_transaction(tx).await
+     }
+
+     /// A staking_program instruction with `accounts` and Borsh-encoded `data`
+     pub fn instruction(&self, accounts: Vec<AccountMeta>, data: StakingInstruction) -> Instruction {
+         Instruction { program_id: self.program_id, accounts, data: borsh::to_vec(&data).unwrap() }
+     }
+
+     pub async fn mint_to(&mut self, dest: Pubkey, amount: u64) -> Result<(), BanksClientError> {
+         let payer = self.payer().pubkey();
+         let ix = token_ix::mint_to(&spl_token::id(), &self.mint.pubkey(), &dest, &payer, &[], amount).unwrap();
+         self.process(&[ix], &[]).await
+     }
+
+     /// Funds a new wallet with SOL and `balance` tokens and initializes its stake PDA
+     pub async fn add_user(&mut self, balance: u64) -> Result<FixtureUser, BanksClientError> {
+         let payer = self.payer().pubkey();
+         let wallet = Keypair::new();
+         let ata = get_associated_token_address(&wallet.pubkey(), &self.mint.pubkey());
+         let (stake_pda, _) = find_user_pda(&self.program_id, &self.pool_pda, &wallet.pubkey());
+         let ixs = [
+             system_instruction::transfer(&payer, &wallet.pubkey(), 1_000_000_000),
+             spl_associated_token_account::instruction::create_associated_token_account(
+                 &payer,
+                 &wallet.pubkey(),
+                 &self.mint.pubkey(),
+                 &spl_token::id(),
+             ),
+         ];
+         self.process(&ixs, &[]).await?;
+         if balance > 0 {
+             self.mint_to(ata, balance).await?;
+         }
+         let init_user = self.instruction(
+             vec![
+                 AccountMeta::new(payer, true),
+                 AccountMeta::new_readonly(wallet.pubkey(), true),
+                 AccountMeta::new_readonly(self.pool_pda, false),
+                 AccountMeta::new(stake_pda, false),
+                 AccountMeta::new_readonly(solana_program::system_program::id(), false),
+                 AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+             ],
+             StakingInstruction::InitializeUser,
+         );
+         self.process(&[init_user], &[&wallet]).await?;
+         Ok(FixtureUser { wallet, ata, stake_pda })
+     }
+
+     pub fn stake_ix(&self, user: &FixtureUser, amount: u64) -> Instruction {
+         self.instruction(
+             vec![
+                 AccountMeta::new(user.wallet.pubkey(), true),
+                 AccountMeta::new(user.ata, false),
+                 AccountMeta::new_readonly(self.mint.pubkey(), false),
+                 AccountMeta::new(self.pool_pda, false),
+                 AccountMeta::new(user.stake_pda, false),
+                 AccountMeta::new(self.vault, false),
+                 AccountMeta::new_readonly(spl_token::id(), false),
+             ],
+             StakingInstruction::Stake { amount },
+         )
+     }
+
+     /// ClaimRewards and Unstake share this account list
+     fn user_exit_ix(&self, user: &FixtureUser, data: StakingInstruction) -> Instruction {
+         self.instruction(
+             vec![
+                 AccountMeta::new(user.wallet.pubkey(), true),
+                 AccountMeta::new(user.ata, false),
+                 AccountMeta::new_readonly(self.mint.pubkey(), false),
+                 AccountMeta::new(user.stake_pda, false),
+                 AccountMeta::new(self.pool_pda, false),
+                 AccountMeta::new(self.vault, false),
+                 AccountMeta::new_readonly(spl_token::id(), false),
+             ],
+             data,
+         )
+     }
+
+     pub fn claim_ix(&self, user: &FixtureUser) -> Instruction {
+         self.user_exit_ix(user, StakingInstruction::ClaimRewards)
+     }
+
+     pub fn unstake_ix(&self, user: &FixtureUser) -> Instruction {
+         self.user_exit_ix(user, StakingInstruction::Unstake)
+     }
+
+     pub async fn stake(&mut self, user: &FixtureUser, amount: u64) -> Result<(), BanksClientError> {
+         let ix = self.stake_ix(user, amount);
+         self.process(&[ix], &[&user.wallet]).await
+     }
+
+     pub async fn claim(&mut self, user: &FixtureUser) -> Result<(), BanksClientError> {
+         let ix = self.claim_ix(user);
+         self.process(&[ix], &[&user.wallet]).await
+     }
+
+     pub async fn unstake(&mut self, user: &FixtureUser) -> Result<(), BanksClientError> {
+         let ix = self.unstake_ix(user);
+         self.process(&[ix], &[&user.wallet]).await
+     }
+
+     /// Moves to a fresh slot and pins `Clock::unix_timestamp` there
+     pub async fn warp_to_timestamp(&mut self, unix_timestamp: i64) {
+         let mut clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+         self.context.warp_to_slot(clock.slot + 2).unwrap();
+         clock = self.context.banks_client.get_sysvar().await.unwrap();
+         clock.unix_timestamp = unix_timestamp;
+         self.context.set_sysvar(&clock);
+     }
+
+     /// Advances the clock `seconds` past its current timestamp, in a fresh slot
+     pub async fn warp_seconds(&mut self, seconds: i64) {
+         let clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+         self.warp_to_timestamp(clock.unix_timestamp + seconds).await;
+     }
+
+     pub async fn token_balance(&mut self, token_account: Pubkey) -> u64 {
+         let account = self.context.banks_client.get_account(token_account).await.unwrap().unwrap();
+         TokenAccount::unpack(&account.data).unwrap().amount
+     }
+
+     pub async fn pool(&mut self) -> StakingPool {
+         let account = self.context.banks_client.get_account(self.pool_pda).await.unwrap().unwrap();
+         *StakingPool::load(&account.data).unwrap()
+     }
+
+     pub async fn user_stake(&mut self, user: &FixtureUser) -> UserStake {
+         let account = self.context.banks_client.get_account(user.stake_pda).await.unwrap().unwrap();
+         *UserStake::load(&account.data).unwrap()
+     }
+ }
+
+ /// Stand-in for the Metaplex token metadata program, which has no native build for this SDK, handling the
+ /// two instructions MintStakePositionNFT sends; fixtures load it at `mpl_token_metadata::ID`. It checks their signers and mint supply and writes what
+ /// the real program would: metadata from the instruction's DataV2, then a master edition that takes over
+ /// the mint authority. Metaplex's other rules (name lengths, creator shares, ...) are not enforced.
+ pub fn token_metadata_stub(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+     use borsh010::{BorshDeserialize, BorshSerialize};
+     use mpl_token_metadata::{accounts::{MasterEdition, Metadata}, instructions, types::{Key, TokenStandard}};
+
+     let mint_authority = |mint: &AccountInfo| Mint::unpack(&mint.try_borrow_data()?).map(|mint| (mint.mint_authority, mint.supply));
+
+     match data.first() {
+         // CreateMetadataAccountV3: metadata, mint, mint_authority, payer, update_authority, system_program
+         Some(33) => {
+             let [metadata, mint, authority, payer, update_authority, system, ..] = accounts else { return Err(ProgramError::NotEnoughAccountKeys) };
+             let args = instructions::CreateMetadataAccountV3InstructionArgs::try_from_slice(&data[1..])?;
+             assert!(authority.is_signer && update_authority.is_signer, "stub: authorities must sign");
+             assert_eq!(mint_authority(mint)?.0, COption::Some(*authority.key), "stub: not the mint authority");
+             let record = Metadata {
+                 key: Key::MetadataV1,
+                 update_authority: *update_authority.key,
+                 mint: *mint.key,
+                 name: args.data.name,
+                 symbol: args.data.symbol,
+                 uri: args.data.uri,
+                 seller_fee_basis_points: args.data.seller_fee_basis_points,
+                 creators: args.data.creators,
+                 primary_sale_happened: false,
+                 is_mutable: args.is_mutable,
+                 edition_nonce: None,
+                 token_standard: None,
+                 collection: args.data.collection,
+                 uses: args.data.uses,
+                 collection_details: args.collection_details.map(|_| unimplemented!("stub: collection details")),
+                 programmable_config: None,
+             };
+             create_stub_account(program_id, payer, metadata, system, &record.try_to_vec()?, &[b"metadata", program_id.as_ref(), mint.key.as_ref()])
+         }
+         // CreateMasterEditionV3: edition, mint, update_authority, mint_authority, payer, metadata, token_program, system_program
+         Some(17) => {
+             let [edition, mint, update_authority, authority, payer, metadata, token_program, system, ..] = accounts else {
+                 return Err(ProgramError::NotEnoughAccountKeys);
+             };
+             let args = instructions::CreateMasterEditionV3InstructionArgs::try_from_slice(&data[1..])?;
+             assert!(authority.is_signer && update_authority.is_signer, "stub: authorities must sign");
+             assert_eq!(mint_authority(mint)?, (COption::Some(*authority.key), 1), "stub: editions need exactly one token");
+             let mut record = Metadata::from_bytes(&metadata.try_borrow_data()?)?;
+             assert_eq!(record.update_authority, *update_authority.key, "stub: not the update authority");
+             let master = MasterEdition { key: Key::MasterEditionV2, supply: 0, max_supply: args.max_supply };
+             create_stub_account(program_id, payer, edition, system, &master.try_to_vec()?, &[b"metadata", program_id.as_ref(), mint.key.as_ref(), b"edition"])?;
+             record.token_standard = Some(TokenStandard::NonFungible);
+             let bytes = record.try_to_vec()?;
+             metadata.try_borrow_mut_data()?[..bytes.len()].copy_from_slice(&bytes);
+             let ix = token_ix::set_authority(token_program.key, mint.key, Some(edition.key), AuthorityType::MintTokens, authority.key, &[])?;
+             invoke(&ix, &[mint.clone(), authority.clone(), token_program.clone()])
+         }
+         _ => Err(ProgramError::InvalidInstructionData),
+     }
+ }
+
+ /// Creates the stub's PDA at `seeds`, paid by `payer`, and writes `bytes` at its start
+ fn create_stub_account<'a>(
+     program_id: &Pubkey,
+     payer: &AccountInfo<'a>,
+     account: &AccountInfo<'a>,
+     system: &AccountInfo<'a>,
+     bytes: &[u8],
+     seeds: &[&[u8]],
+ ) -> ProgramResult {
+     let (address, bump) = Pubkey::find_program_address(seeds, program_id);
+     assert_eq!(address, *account.key, "stub: PDA mismatch");
+     // Metaplex's own metadata account size, which fits a master edition too
+     let space = bytes.len().max(679);
+     let ix = system_instruction::create_account(payer.key, account.key, Rent::get()?.minimum_balance(space), space as u64, program_id);
+     invoke_signed(&ix, &[payer.clone(), account.clone(), system.clone()], &[&[seeds, &[&[bump]]].concat()])?;
+     account.try_borrow_mut_data()?[..bytes.len()].copy_from_slice(bytes);
+     Ok(())
+ }
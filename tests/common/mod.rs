@@ -0,0 +1,383 @@
+ // Shared harness for the integration test binaries: program setup, instruction builders
+ // and account readers. Each binary uses a different subset.
+ #![allow(dead_code)]
+
+ use borsh::BorshSerialize;
+ use solana_program::{instruction::Instruction, program_pack::Pack, pubkey::Pubkey, sysvar::clock::Clock};
+ use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+ use solana_sdk::{
+     account::ReadableAccount,
+     instruction::{AccountMeta, InstructionError},
+     signature::{Keypair, Signer},
+     transaction::{Transaction, TransactionError},
+ };
+ use spl_associated_token_account::get_associated_token_address;
+ use spl_token::{instruction as token_ix, state::Account as TokenAccount};
+ use staking_program::{
+     find_pool_pda, find_protocol_config_pda, find_registry_pda, find_user_pda, ProtocolConfig, StakingError, StakingInstruction,
+     StakingPool, UserStake,
+ };
+
+ pub fn program_id() -> Pubkey {
+     staking_program::id()
+ }
+
+ pub fn build_ix<T: BorshSerialize>(pid: Pubkey, keys: Vec<AccountMeta>, data: T) -> Instruction {
+     let mut v = Vec::with_capacity(64);
+     data.serialize(&mut v).unwrap();
+     Instruction { program_id: pid, accounts: keys, data: v }
+ }
+
+ pub async fn read_token_account(banks_client: &mut solana_program_test::BanksClient, pubkey: Pubkey) -> TokenAccount {
+     let acc = banks_client.get_account(pubkey).await.unwrap().unwrap();
+     TokenAccount::unpack(acc.data()).unwrap()
+ }
+
+ pub async fn read_user_stake(banks_client: &mut solana_program_test::BanksClient, pubkey: Pubkey) -> UserStake {
+     let acc = banks_client.get_account(pubkey).await.unwrap().unwrap();
+     *UserStake::load(acc.data()).unwrap()
+ }
+
+ pub async fn read_pool(banks_client: &mut solana_program_test::BanksClient, pubkey: Pubkey) -> StakingPool {
+     let acc = banks_client.get_account(pubkey).await.unwrap().unwrap();
+     *StakingPool::load(acc.data()).unwrap()
+ }
+
+ /// Asserts the first instruction failed with the given program error.
+ pub fn assert_staking_err(err: BanksClientError, expected: StakingError) {
+     assert_eq!(
+         err.unwrap(),
+         TransactionError::InstructionError(0, InstructionError::Custom(expected as u32)),
+     );
+ }
+
+ pub fn program_test(pid: Pubkey) -> ProgramTest {
+     // SPL Token and ATA programs are loaded by ProgramTest by default
+     let mut pt = ProgramTest::new("staking_program", pid, processor!(staking_program::process_instruction));
+     pt.add_program("mpl_token_metadata_stub", mpl_token_metadata::ID, processor!(staking_test_utils::token_metadata_stub));
+     pt
+ }
+
+ /// Signs with the context payer plus `signers` against a fresh blockhash, so identical
+ /// instructions can be resubmitted without being deduplicated.
+ pub async fn process(ctx: &mut ProgramTestContext, ixs: &[Instruction], signers: &[&Keypair]) -> Result<(), BanksClientError> {
+     let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+     let mut all_signers: Vec<&Keypair> = vec![&ctx.payer];
+     all_signers.extend_from_slice(signers);
+     let tx = Transaction::new_signed_with_payer(ixs, Some(&ctx.payer.pubkey()), &all_signers, blockhash);
+     ctx.banks_client.process_transaction(tx).await
+ }
+
+ /// Pins `Clock::unix_timestamp` so reward math is deterministic.
+ pub async fn set_clock(ctx: &mut ProgramTestContext, unix_timestamp: i64) {
+     let mut clock: Clock = ctx.banks_client.get_sysvar().await.unwrap();
+     clock.unix_timestamp = unix_timestamp;
+     ctx.set_sysvar(&clock);
+ }
+
+ /// Moves to a fresh slot, then pins `Clock::unix_timestamp` there, so the next transaction
+ /// runs in a new bank at exactly `unix_timestamp`.
+ pub async fn warp_to_timestamp(ctx: &mut ProgramTestContext, unix_timestamp: i64) {
+     let clock: Clock = ctx.banks_client.get_sysvar().await.unwrap();
+     ctx.warp_to_slot(clock.slot + 2).unwrap();
+     set_clock(ctx, unix_timestamp).await;
+ }
+
+ pub async fn token_balance(ctx: &mut ProgramTestContext, pubkey: Pubkey) -> u64 {
+     read_token_account(&mut ctx.banks_client, pubkey).await.amount
+ }
+
+ /// A pool with a funded vault, ready for users to join.
+ pub struct TestPool {
+     pub pid: Pubkey,
+     pub mint: Keypair,
+     pub authority: Keypair,
+     pub pool_pda: Pubkey,
+     pub vault_ata: Pubkey,
+ }
+
+ pub struct TestUser {
+     pub wallet: Keypair,
+     pub ata: Pubkey,
+     pub stake_pda: Pubkey,
+ }
+
+ /// InitializePool registering at index 0, which is right while no ProtocolConfig exists
+ /// (point `accounts[10]` at `next_registry_pda` once one does)
+ pub fn init_pool_ix(
+     pid: Pubkey,
+     payer: &Pubkey,
+     authority: &Pubkey,
+     mint: &Pubkey,
+     pool_id: u64,
+     reward_rate: u64,
+     min_lock_period: i64,
+ ) -> Instruction {
+     let (pool_pda, _) = find_pool_pda(&pid, mint, pool_id);
+     build_ix(
+         pid,
+         vec![
+             AccountMeta::new(*payer, true),
+             AccountMeta::new_readonly(*authority, true),
+             AccountMeta::new(pool_pda, false),
+             AccountMeta::new_readonly(*mint, false),
+             AccountMeta::new(get_associated_token_address(&pool_pda, mint), false),
+             AccountMeta::new_readonly(spl_token::id(), false),
+             AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+             AccountMeta::new_readonly(solana_sdk::system_program::id(), false),
+             AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+             AccountMeta::new(find_protocol_config_pda(&pid).0, false),
+             AccountMeta::new(find_registry_pda(&pid, 0).0, false),
+         ],
+         StakingInstruction::InitializePool { reward_rate, min_lock_period, pool_id, allow_freezable_mint: false },
+     )
+ }
+
+ /// Registry entry the next InitializePool must pass, from the ProtocolConfig's pool_count (0 without one)
+ pub async fn next_registry_pda(ctx: &mut ProgramTestContext, pid: Pubkey) -> Pubkey {
+     let config = ctx.banks_client.get_account(find_protocol_config_pda(&pid).0).await.unwrap();
+     let index = config.map_or(0, |account| ProtocolConfig::load(&account.data).unwrap().pool_count.get());
+     find_registry_pda(&pid, index).0
+ }
+
+ /// A fresh 9-decimal mint whose authority is the payer
+ pub async fn create_mint(ctx: &mut ProgramTestContext) -> Keypair {
+     let mint = Keypair::new();
+     let mint_rent = ctx.banks_client.get_rent().await.unwrap().minimum_balance(spl_token::state::Mint::LEN);
+     let payer = ctx.payer.pubkey();
+     let ixs = [
+         solana_sdk::system_instruction::create_account(&payer, &mint.pubkey(), mint_rent, spl_token::state::Mint::LEN as u64, &spl_token::id()),
+         token_ix::initialize_mint(&spl_token::id(), &mint.pubkey(), &payer, None, 9).unwrap(),
+     ];
+     process(ctx, &ixs, &[&mint]).await.unwrap();
+     mint
+ }
+
+ pub async fn setup_pool(ctx: &mut ProgramTestContext, pid: Pubkey, reward_rate: u64, min_lock_period: i64) -> TestPool {
+     let mint = create_mint(ctx).await;
+     setup_pool_for_mint(ctx, pid, mint, 0, reward_rate, min_lock_period).await
+ }
+
+ /// Pool `pool_id` of an existing mint, with its vault funded like `setup_pool`
+ pub async fn setup_pool_for_mint(
+     ctx: &mut ProgramTestContext,
+     pid: Pubkey,
+     mint: Keypair,
+     pool_id: u64,
+     reward_rate: u64,
+     min_lock_period: i64,
+ ) -> TestPool {
+     let pool = init_test_pool(ctx, pid, mint, pool_id, reward_rate, min_lock_period).await;
+     // Fund vault for rewards (payer is the mint authority)
+     mint_to(ctx, &pool, pool.vault_ata, 1_000_000_000_000).await;
+     pool
+ }
+
+ /// Pool `pool_id` of an existing mint, its vault left empty
+ pub async fn init_test_pool(
+     ctx: &mut ProgramTestContext,
+     pid: Pubkey,
+     mint: Keypair,
+     pool_id: u64,
+     reward_rate: u64,
+     min_lock_period: i64,
+ ) -> TestPool {
+     let payer = ctx.payer.pubkey();
+     let authority = Keypair::new();
+     let (pool_pda, _) = find_pool_pda(&pid, &mint.pubkey(), pool_id);
+     let vault_ata = get_associated_token_address(&pool_pda, &mint.pubkey());
+     let init_ix = init_pool_ix(pid, &payer, &authority.pubkey(), &mint.pubkey(), pool_id, reward_rate, min_lock_period);
+     process(ctx, &[init_ix], &[&authority]).await.unwrap();
+     TestPool { pid, mint, authority, pool_pda, vault_ata }
+ }
+
+ pub async fn mint_to(ctx: &mut ProgramTestContext, pool: &TestPool, dest: Pubkey, amount: u64) {
+     let ix = token_ix::mint_to(&spl_token::id(), &pool.mint.pubkey(), &dest, &ctx.payer.pubkey(), &[], amount).unwrap();
+     process(ctx, &[ix], &[]).await.unwrap();
+ }
+
+ /// Creates a funded wallet with an ATA holding `balance` tokens; the stake PDA is not created.
+ pub async fn fund_user(ctx: &mut ProgramTestContext, pool: &TestPool, balance: u64) -> TestUser {
+     let payer = ctx.payer.pubkey();
+     let wallet = Keypair::new();
+     let ata = get_associated_token_address(&wallet.pubkey(), &pool.mint.pubkey());
+     let (stake_pda, _) = find_user_pda(&pool.pid, &pool.pool_pda, &wallet.pubkey());
+     let ixs = [
+         solana_sdk::system_instruction::transfer(&payer, &wallet.pubkey(), 1_000_000_000),
+         spl_associated_token_account::instruction::create_associated_token_account(
+             &payer, &wallet.pubkey(), &pool.mint.pubkey(), &spl_token::id(),
+         ),
+     ];
+     process(ctx, &ixs, &[]).await.unwrap();
+     mint_to(ctx, pool, ata, balance).await;
+     TestUser { wallet, ata, stake_pda }
+ }
+
+ pub fn init_user_ix(pool: &TestPool, payer: &Pubkey, user: &TestUser) -> Instruction {
+     build_ix(
+         pool.pid,
+         vec![
+             AccountMeta::new(*payer, true),
+             AccountMeta::new_readonly(user.wallet.pubkey(), true),
+             AccountMeta::new_readonly(pool.pool_pda, false),
+             AccountMeta::new(user.stake_pda, false),
+             AccountMeta::new_readonly(solana_sdk::system_program::id(), false),
+             AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+         ],
+         StakingInstruction::InitializeUser,
+     )
+ }
+
+ /// Like `fund_user`, plus an initialized stake PDA.
+ pub async fn setup_user(ctx: &mut ProgramTestContext, pool: &TestPool, balance: u64) -> TestUser {
+     let user = fund_user(ctx, pool, balance).await;
+     let ix = init_user_ix(pool, &ctx.payer.pubkey(), &user);
+     process(ctx, &[ix], &[&user.wallet]).await.unwrap();
+     user
+ }
+
+ pub fn stake_ix(pool: &TestPool, user: &TestUser, amount: u64) -> Instruction {
+     build_ix(
+         pool.pid,
+         vec![
+             AccountMeta::new(user.wallet.pubkey(), true),
+             AccountMeta::new(user.ata, false),
+             AccountMeta::new_readonly(pool.mint.pubkey(), false),
+             AccountMeta::new(pool.pool_pda, false),
+             AccountMeta::new(user.stake_pda, false),
+             AccountMeta::new(pool.vault_ata, false),
+             AccountMeta::new_readonly(spl_token::id(), false),
+         ],
+         StakingInstruction::Stake { amount },
+     )
+ }
+
+ /// Account list shared by ClaimRewards and Unstake.
+ pub fn user_exit_ix(pool: &TestPool, user: &TestUser, data: StakingInstruction) -> Instruction {
+     build_ix(
+         pool.pid,
+         vec![
+             AccountMeta::new(user.wallet.pubkey(), true),
+             AccountMeta::new(user.ata, false),
+             AccountMeta::new_readonly(pool.mint.pubkey(), false),
+             AccountMeta::new(user.stake_pda, false),
+             AccountMeta::new(pool.pool_pda, false),
+             AccountMeta::new(pool.vault_ata, false),
+             AccountMeta::new_readonly(spl_token::id(), false),
+         ],
+         data,
+     )
+ }
+
+ /// Authority-style instruction taking just `[signer, writable pool_pda]`.
+ pub fn admin_ix(pool: &TestPool, signer: &Pubkey, data: StakingInstruction) -> Instruction {
+     build_ix(
+         pool.pid,
+         vec![AccountMeta::new_readonly(*signer, true), AccountMeta::new(pool.pool_pda, false)],
+         data,
+     )
+ }
+
+ pub fn force_claim_ix(pool: &TestPool, signer: &Pubkey, user: &TestUser) -> Instruction {
+     build_ix(
+         pool.pid,
+         vec![
+             AccountMeta::new_readonly(*signer, true),
+             AccountMeta::new(user.ata, false),
+             AccountMeta::new_readonly(pool.mint.pubkey(), false),
+             AccountMeta::new(user.stake_pda, false),
+             AccountMeta::new(pool.pool_pda, false),
+             AccountMeta::new(pool.vault_ata, false),
+             AccountMeta::new_readonly(spl_token::id(), false),
+         ],
+         StakingInstruction::ForceClaimForUser { user_wallet: user.wallet.pubkey() },
+     )
+ }
+
+ pub fn set_multiplier_ix(pool: &TestPool, signer: &Pubkey, user: &TestUser, multiplier_bps: u16) -> Instruction {
+     build_ix(
+         pool.pid,
+         vec![
+             AccountMeta::new_readonly(*signer, true),
+             AccountMeta::new_readonly(pool.pool_pda, false),
+             AccountMeta::new(user.stake_pda, false),
+         ],
+         StakingInstruction::SetUserRewardMultiplier { user_wallet: user.wallet.pubkey(), multiplier_bps },
+     )
+ }
+
+ pub fn auto_compound_ix(pool: &TestPool, caller: &Pubkey, user: &TestUser) -> Instruction {
+     build_ix(
+         pool.pid,
+         vec![
+             AccountMeta::new(*caller, true),
+             AccountMeta::new(user.stake_pda, false),
+             AccountMeta::new(pool.pool_pda, false),
+             AccountMeta::new_readonly(pool.vault_ata, false),
+         ],
+         StakingInstruction::AutoCompound { user_wallet: user.wallet.pubkey() },
+     )
+ }
+
+ /// A plain (non-ATA) token account for `mint` owned by `owner`.
+ pub async fn create_token_account(ctx: &mut ProgramTestContext, mint: &Pubkey, owner: &Pubkey) -> Pubkey {
+     let account = Keypair::new();
+     let rent = ctx.banks_client.get_rent().await.unwrap().minimum_balance(TokenAccount::LEN);
+     let ixs = [
+         solana_sdk::system_instruction::create_account(
+             &ctx.payer.pubkey(),
+             &account.pubkey(),
+             rent,
+             TokenAccount::LEN as u64,
+             &spl_token::id(),
+         ),
+         token_ix::initialize_account(&spl_token::id(), &account.pubkey(), mint, owner).unwrap(),
+     ];
+     process(ctx, &ixs, &[&account]).await.unwrap();
+     account.pubkey()
+ }
+
+ pub fn recover_ix(pool: &TestPool, target: Pubkey, recorded_pool: Pubkey) -> Instruction {
+     build_ix(
+         pool.pid,
+         vec![
+             AccountMeta::new(pool.authority.pubkey(), true),
+             AccountMeta::new_readonly(pool.pool_pda, false),
+             AccountMeta::new(target, false),
+             AccountMeta::new_readonly(recorded_pool, false),
+         ],
+         StakingInstruction::RecoverOrphanedAccount { target_pda: target },
+     )
+ }
+
+ /// Runs VerifyPoolIntegrity and returns the violation count from its final log line.
+ pub async fn integrity_violations(ctx: &mut ProgramTestContext, pool: &TestPool, user_stakes: &[Pubkey]) -> u32 {
+     let mut keys = vec![AccountMeta::new_readonly(pool.pool_pda, false), AccountMeta::new_readonly(pool.vault_ata, false)];
+     keys.extend(user_stakes.iter().map(|pk| AccountMeta::new_readonly(*pk, false)));
+     let ix = build_ix(pool.pid, keys, StakingInstruction::VerifyPoolIntegrity);
+     let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+     let tx = Transaction::new_signed_with_payer(&[ix], Some(&ctx.payer.pubkey()), &[&ctx.payer], blockhash);
+     let result = ctx.banks_client.process_transaction_with_metadata(tx).await.unwrap();
+     result.result.unwrap();
+     let logs = result.metadata.unwrap().log_messages;
+     logs.iter()
+         .find_map(|line| line.strip_prefix("Program log: INTEGRITY_DONE violations="))
+         .expect("missing INTEGRITY_DONE log")
+         .parse()
+         .unwrap()
+ }
+
+ /// Submits `ix` expecting it to fail; returns the error and the program's `msg!` lines
+ pub async fn process_failing(ctx: &mut ProgramTestContext, ix: Instruction, signers: &[&Keypair]) -> (TransactionError, Vec<String>) {
+     let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+     let mut all_signers: Vec<&Keypair> = vec![&ctx.payer];
+     all_signers.extend_from_slice(signers);
+     let tx = Transaction::new_signed_with_payer(&[ix], Some(&ctx.payer.pubkey()), &all_signers, blockhash);
+     let result = ctx.banks_client.process_transaction_with_metadata(tx).await.unwrap();
+     let logs = result.metadata.unwrap().log_messages;
+     (result.result.unwrap_err(), logs)
+ }
+
+ pub fn assert_logged(logs: &[String], needle: &str) {
+     assert!(logs.iter().any(|line| line.contains(needle)), "expected a log containing {needle:?}, got {logs:#?}");
+ }
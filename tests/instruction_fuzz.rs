@@ -0,0 +1,184 @@
+ // Property tests for instruction decoding: arbitrary bytes must never panic,
+ // and valid payloads must be rejected once anything trails them.
+
+ use proptest::prelude::*;
+ use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+ use staking_program::{LockResetPolicy, PoolState, StakingInstruction};
+
+ fn instruction() -> impl Strategy<Value = StakingInstruction> {
+     prop_oneof![
+         (any::<u64>(), any::<i64>(), any::<u64>(), any::<bool>()).prop_map(
+             |(reward_rate, min_lock_period, pool_id, allow_freezable_mint)| StakingInstruction::InitializePool {
+                 reward_rate,
+                 min_lock_period,
+                 pool_id,
+                 allow_freezable_mint,
+             }
+         ),
+         (any::<Option<u64>>(), any::<Option<i64>>()).prop_map(|(new_reward_rate, new_min_lock_period)| {
+             StakingInstruction::UpdateConfig { new_reward_rate, new_min_lock_period }
+         }),
+         Just(StakingInstruction::InitializeUser),
+         any::<u64>().prop_map(|amount| StakingInstruction::Stake { amount }),
+         Just(StakingInstruction::ClaimRewards),
+         Just(StakingInstruction::Unstake),
+         any::<[u8; 32]>().prop_map(|k| StakingInstruction::ForceClaimForUser { user_wallet: Pubkey::new_from_array(k) }),
+         (any::<[u8; 32]>(), any::<u16>()).prop_map(|(k, multiplier_bps)| {
+             StakingInstruction::SetUserRewardMultiplier { user_wallet: Pubkey::new_from_array(k), multiplier_bps }
+         }),
+         any::<bool>().prop_map(|paused| StakingInstruction::SetGlobalPause { paused }),
+         any::<u64>().prop_map(|max_rate| StakingInstruction::SetMaxRewardRate { max_rate }),
+         any::<u8>().prop_map(|version| StakingInstruction::SetProtocolVersion { version }),
+         any::<[u8; 32]>().prop_map(|k| StakingInstruction::FreezeStalePDA { user_wallet: Pubkey::new_from_array(k) }),
+         any::<[u8; 32]>().prop_map(|k| StakingInstruction::UnfreezePDA { user_wallet: Pubkey::new_from_array(k) }),
+         any::<bool>().prop_map(|enabled| StakingInstruction::SetMintAuthorityMode { enabled }),
+         any::<u64>().prop_map(|amount| StakingInstruction::MintRewardsToVault { amount }),
+         any::<bool>().prop_map(|use_slots| StakingInstruction::SetAccrualMode { use_slots }),
+         Just(StakingInstruction::UnstakeAndClose),
+         (any::<u64>(), any::<i64>()).prop_map(|(new_reward_rate, new_min_lock_period)| {
+             StakingInstruction::ReInitializePool { new_reward_rate, new_min_lock_period }
+         }),
+         (any::<u8>(), any::<u8>()).prop_map(|(start_utc_hour, end_utc_hour)| {
+             StakingInstruction::SetStakingWindow { start_utc_hour, end_utc_hour }
+         }),
+         any::<u64>().prop_map(|min_reward| StakingInstruction::SetRewardFloor { min_reward }),
+         (any::<u16>(), any::<i64>(), any::<i64>(), any::<u64>()).prop_map(|(multiplier_bps, start, end, campaign_id)| {
+             StakingInstruction::SetIncentiveCampaign { multiplier_bps, start, end, campaign_id }
+         }),
+         Just(StakingInstruction::CloseIncentiveCampaign),
+         any::<i64>().prop_map(|cooldown_seconds| StakingInstruction::SetClaimCooldown { cooldown_seconds }),
+         (any::<u16>(), any::<u64>()).prop_map(|(target_utilization_bps, pool_capacity)| {
+             StakingInstruction::SetRebalanceParams { target_utilization_bps, pool_capacity }
+         }),
+         Just(StakingInstruction::Rebalance),
+         any::<i64>().prop_map(|delay_seconds| StakingInstruction::SetMinAccrualDelay { delay_seconds }),
+         any::<u16>().prop_map(|bps| StakingInstruction::SetWithdrawFeeBps { bps }),
+         any::<[u8; 32]>().prop_map(|k| StakingInstruction::SetPenaltyRecipient { recipient: Pubkey::new_from_array(k) }),
+         any::<u16>().prop_map(|penalty_bps| StakingInstruction::SetEarlyUnstakePenalty { penalty_bps }),
+         Just(StakingInstruction::EarlyUnstake),
+         any::<u16>().prop_map(|bps| StakingInstruction::UnstakeBps { bps }),
+         (any::<[u8; 32]>(), any::<u16>()).prop_map(|(k, required_for_rate_change_above_bps)| {
+             StakingInstruction::SetGovernanceAddress { governance: Pubkey::new_from_array(k), required_for_rate_change_above_bps }
+         }),
+         any::<u64>().prop_map(|amount| StakingInstruction::IncreaseStake { amount }),
+         prop_oneof![Just(LockResetPolicy::None), Just(LockResetPolicy::ResetAll), Just(LockResetPolicy::WeightedAverage)]
+             .prop_map(|policy| StakingInstruction::SetLockResetPolicy { policy }),
+         any::<i64>().prop_map(|seconds| StakingInstruction::SetIdleThreshold { seconds }),
+         any::<u8>().prop_map(|user_count| StakingInstruction::FreezeIdleAccounts { user_count }),
+         any::<[u8; 32]>().prop_map(|k| StakingInstruction::InitializeProtocol { super_admin: Pubkey::new_from_array(k) }),
+         (any::<[u8; 32]>(), any::<bool>(), any::<u16>(), any::<bool>(), any::<[[u8; 32]; 4]>()).prop_map(
+             |(k, pool_creation_open, default_fee_bps, global_pause, creators)| StakingInstruction::SetProtocolConfig {
+                 super_admin: Pubkey::new_from_array(k),
+                 pool_creation_open,
+                 default_fee_bps,
+                 global_pause,
+                 pool_creators: creators.map(Pubkey::new_from_array),
+             }
+         ),
+         (any::<i64>(), any::<i64>()).prop_map(|(vesting_duration_seconds, cliff_seconds)| {
+             StakingInstruction::SetVestingSchedule { vesting_duration_seconds, cliff_seconds }
+         }),
+         Just(StakingInstruction::ClaimVestedPrincipal),
+         Just(StakingInstruction::MintStakePositionNFT),
+         Just(StakingInstruction::BurnStakeNFT),
+         any::<i64>().prop_map(|max_seconds| StakingInstruction::SetMaxClaimInterval { max_seconds }),
+         (any::<u64>(), any::<[(i64, u64); 3]>())
+             .prop_map(|(reward_rate, epochs)| StakingInstruction::SetRewardSchedule { reward_rate, epochs }),
+         (any::<u64>(), any::<u64>(), any::<[u8; 64]>()).prop_map(|(amount, nonce, signature)| {
+             StakingInstruction::StakeWithPermitSignature { amount, nonce, signature }
+         }),
+         (any::<u64>(), any::<i64>(), any::<u16>()).prop_map(|(min_amount, lock_seconds, bonus_rate_bps)| {
+             StakingInstruction::SetLockPeriodTier { min_amount, lock_seconds, bonus_rate_bps }
+         }),
+         any::<u64>().prop_map(|max_total_rewards| StakingInstruction::SetMaxTotalRewards { max_total_rewards }),
+         any::<i64>().prop_map(|future_seconds| StakingInstruction::GetUserRewardEstimate { future_seconds }),
+         any::<u8>().prop_map(|count| StakingInstruction::ClaimMany { count }),
+         any::<bool>().prop_map(|enabled| StakingInstruction::SetAnalyticsMode { enabled }),
+         any::<[u8; 32]>()
+             .prop_map(|k| StakingInstruction::SetReferralAuthority { referral_authority: Pubkey::new_from_array(k) }),
+         (any::<u64>(), any::<[u8; 32]>()).prop_map(|(amount, k)| {
+             StakingInstruction::StakeFor { amount, beneficiary: Pubkey::new_from_array(k) }
+         }),
+         any::<u64>().prop_map(|buffer| StakingInstruction::SetMinimumVaultBuffer { buffer }),
+         any::<bool>().prop_map(|enabled| StakingInstruction::SetRedistributePenalties { enabled }),
+         (any::<u64>(), any::<i64>(), any::<u64>(), any::<[(i64, u64); 3]>()).prop_map(
+             |(reward_rate, min_lock_period, pool_id, epochs)| StakingInstruction::CreatePoolWithSchedule {
+                 reward_rate,
+                 min_lock_period,
+                 pool_id,
+                 epochs,
+             }
+         ),
+         any::<u64>().prop_map(|max_amount| StakingInstruction::ClaimUpTo { max_amount }),
+         any::<[u8; 32]>()
+             .prop_map(|k| StakingInstruction::InitializeCleanupAuthority { authority: Pubkey::new_from_array(k) }),
+         any::<i64>().prop_map(|pool_age_threshold_seconds| StakingInstruction::CloseInactivePool { pool_age_threshold_seconds }),
+         prop_oneof![Just(PoolState::Active), Just(PoolState::DepositsPaused), Just(PoolState::FullyPaused)]
+             .prop_map(|state| StakingInstruction::SetPoolState { state }),
+         (any::<[u8; 32]>(), any::<i64>()).prop_map(|(k, lock_end_time)| {
+             StakingInstruction::SetUserLockOverride { user_wallet: Pubkey::new_from_array(k), lock_end_time }
+         }),
+         any::<bool>().prop_map(|enabled| StakingInstruction::SetSharesMode { enabled }),
+         any::<[u8; 32]>().prop_map(|k| StakingInstruction::RotateVault { new_vault_ata: Pubkey::new_from_array(k) }),
+         proptest::collection::vec(any::<[u8; 32]>(), 0..=8).prop_map(|keys| StakingInstruction::InitializeUserBatch {
+             user_wallets: keys.into_iter().map(Pubkey::new_from_array).collect(),
+         }),
+         any::<[u8; 32]>().prop_map(|k| StakingInstruction::SetFunder { funder: Pubkey::new_from_array(k) }),
+         any::<[u8; 32]>().prop_map(|k| StakingInstruction::SetPauser { pauser: Pubkey::new_from_array(k) }),
+         proptest::collection::vec(any::<u8>(), 256).prop_map(|bytes| StakingInstruction::SetPoolDescription {
+             description: bytes.try_into().unwrap(),
+         }),
+         any::<bool>().prop_map(|enabled| StakingInstruction::SetMintOnClaim { enabled }),
+         Just(StakingInstruction::QueryPendingReward),
+         (any::<[u8; 32]>(), any::<i64>()).prop_map(|(k, bypass_until)| {
+             StakingInstruction::SetClaimCooldownOverride { user_wallet: Pubkey::new_from_array(k), bypass_until }
+         }),
+         any::<bool>().prop_map(|enabled| StakingInstruction::SetRequireCanonicalAta { enabled }),
+         (any::<[u8; 32]>(), any::<u64>()).prop_map(|(k, amount)| {
+             StakingInstruction::RecordStakeOnBehalf { user_wallet: Pubkey::new_from_array(k), amount }
+         }),
+         Just(StakingInstruction::ConvertSponsoredStake),
+         any::<u64>().prop_map(|cap| StakingInstruction::InitializeGlobalTVLTracker { cap }),
+         any::<u64>().prop_map(|cap| StakingInstruction::SetGlobalStakeCap { cap }),
+         any::<[u8; 32]>().prop_map(|k| StakingInstruction::SetAccessSigner { access_signer: Pubkey::new_from_array(k) }),
+         (any::<u64>(), any::<i64>()).prop_map(|(amount, expiry)| StakingInstruction::StakeWithPermit { amount, expiry }),
+         any::<bool>().prop_map(|transferable| StakingInstruction::SetStakePositionTransferable { transferable }),
+         any::<[u8; 32]>().prop_map(|k| StakingInstruction::SetPoolShared { sibling_vault: Pubkey::new_from_array(k) }),
+         Just(StakingInstruction::SoftPause),
+         Just(StakingInstruction::HardPause),
+         (any::<u64>(), any::<u16>(), any::<i64>(), any::<i64>()).prop_map(|(campaign_id, boost_bps, start_time, end_time)| {
+             StakingInstruction::CreateBoostCampaign { campaign_id, boost_bps, start_time, end_time }
+         }),
+         Just(StakingInstruction::CloseBoostCampaign),
+         Just(StakingInstruction::MigrateAccount),
+         Just(StakingInstruction::TransferStakePosition),
+         any::<[([u8; 32], u64, i64); 8]>().prop_map(|entries| StakingInstruction::AirdropStake {
+             recipients: entries.map(|(k, amount, unlock_at)| (Pubkey::new_from_array(k), amount, unlock_at)),
+         }),
+     ]
+ }
+
+ proptest! {
+     #[test]
+     fn unpack_never_panics(data in proptest::collection::vec(any::<u8>(), 0..512)) {
+         let _ = StakingInstruction::unpack(&data);
+     }
+
+     #[test]
+     fn process_instruction_never_panics_without_accounts(data in proptest::collection::vec(any::<u8>(), 0..512)) {
+         let _ = staking_program::process_instruction(&Pubkey::new_unique(), &[], &data);
+     }
+
+     #[test]
+     fn unpack_round_trips_exact_payloads(ix in instruction()) {
+         let data = borsh::to_vec(&ix).unwrap();
+         prop_assert_eq!(StakingInstruction::unpack(&data).unwrap(), ix);
+     }
+
+     #[test]
+     fn unpack_rejects_trailing_bytes(ix in instruction(), extra in proptest::collection::vec(any::<u8>(), 1..16)) {
+         let mut data = borsh::to_vec(&ix).unwrap();
+         data.extend_from_slice(&extra);
+         prop_assert_eq!(StakingInstruction::unpack(&data).unwrap_err(), ProgramError::InvalidInstructionData);
+     }
+ }
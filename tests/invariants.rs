@@ -0,0 +1,95 @@
+ // Stateful property test: random sequences of Stake, IncreaseStake, UnstakeBps, Unstake and clock
+ // advances for up to five users, checking after every step that `pool.total_staked` is exactly the
+ // sum of the users' `amount`s and that the vault still holds at least that much.
+ //
+ // It finds nothing in the current tree; it is there for the next exit or top-up path that moves
+ // tokens without updating both sides (e.g. a partial unstake that forgets total_staked, or a
+ // reward payout that dips into principal). Failing operations are fine, since only the state they
+ // leave behind is checked. Shrinking is capped at 1000 iterations, each of which replays a
+ // whole sequence against a fresh bank.
+
+ use proptest::prelude::*;
+ use solana_program_test::tokio;
+ use solana_sdk::signature::Signer;
+ use staking_program::StakingInstruction;
+
+ mod common;
+ use common::*;
+
+ const USERS: usize = 5;
+ const BALANCE: u64 = 1_000_000_000_000;
+ const START: i64 = 1_700_000_000;
+
+ #[derive(Debug, Clone)]
+ enum Op {
+     Stake(usize, u64),
+     IncreaseStake(usize, u64),
+     UnstakeBps(usize, u16),
+     Unstake(usize),
+     Wait(i64),
+ }
+
+ /// Dust, mid-range and whole-balance amounts, so both rounding and insufficient-balance paths come up
+ fn amount() -> impl Strategy<Value = u64> {
+     prop_oneof![1..=1_000u64, 1..=BALANCE / 4, Just(BALANCE)]
+ }
+
+ fn op() -> impl Strategy<Value = Op> {
+     let user = 0..USERS;
+     prop_oneof![
+         (user.clone(), amount()).prop_map(|(u, a)| Op::Stake(u, a)),
+         (user.clone(), amount()).prop_map(|(u, a)| Op::IncreaseStake(u, a)),
+         (user.clone(), 0..=10_001u16).prop_map(|(u, bps)| Op::UnstakeBps(u, bps)),
+         user.prop_map(Op::Unstake),
+         (0..=100_000i64).prop_map(Op::Wait),
+     ]
+ }
+
+ async fn run(ops: Vec<Op>) {
+     let pid = program_id();
+     let mut ctx = program_test(pid).start_with_context().await;
+     let pool = setup_pool(&mut ctx, pid, 5_000_000, 0).await;
+     let mut users = Vec::with_capacity(USERS);
+     for _ in 0..USERS {
+         users.push(setup_user(&mut ctx, &pool, BALANCE).await);
+     }
+     let mut now = START;
+     set_clock(&mut ctx, now).await;
+
+     for (step, op) in ops.iter().enumerate() {
+         let (ix, user) = match *op {
+             Op::Stake(u, amount) => (stake_ix(&pool, &users[u], amount), &users[u]),
+             Op::IncreaseStake(u, amount) => {
+                 let mut ix = stake_ix(&pool, &users[u], amount);
+                 ix.data = borsh::to_vec(&StakingInstruction::IncreaseStake { amount }).unwrap();
+                 (ix, &users[u])
+             }
+             Op::UnstakeBps(u, bps) => (user_exit_ix(&pool, &users[u], StakingInstruction::UnstakeBps { bps }), &users[u]),
+             Op::Unstake(u) => (user_exit_ix(&pool, &users[u], StakingInstruction::Unstake), &users[u]),
+             Op::Wait(seconds) => {
+                 now += seconds;
+                 warp_to_timestamp(&mut ctx, now).await;
+                 continue;
+             }
+         };
+         let _ = process(&mut ctx, &[ix], &[&user.wallet]).await;
+
+         let mut sum_user_amounts = 0u128;
+         for user in &users {
+             sum_user_amounts += read_user_stake(&mut ctx.banks_client, user.stake_pda).await.amount.get() as u128;
+         }
+         let total_staked = read_pool(&mut ctx.banks_client, pool.pool_pda).await.total_staked.get();
+         let vault = token_balance(&mut ctx, pool.vault_ata).await;
+         assert_eq!(sum_user_amounts, total_staked as u128, "after step {step} {op:?} by {}", user.wallet.pubkey());
+         assert!(vault >= total_staked, "after step {step} {op:?}: vault {vault} < total_staked {total_staked}");
+     }
+ }
+
+ proptest! {
+     #![proptest_config(ProptestConfig { cases: 24, max_shrink_iters: 1000, ..ProptestConfig::default() })]
+
+     #[test]
+     fn total_staked_matches_user_amounts(ops in proptest::collection::vec(op(), 1..16)) {
+         tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(run(ops));
+     }
+ }
@@ -0,0 +1,294 @@
+ // Account layout checks: the Borsh encoding of every state struct must fit its account size,
+ // and decoding (including from a zero-padded account buffer) must give back the same value.
+ // PDA seeds are pinned here too, since changing them orphans every deployed account,
+ // as are the owner, size and initialization checks in the typed loaders.
+
+ use borsh::BorshDeserialize;
+ use bytemuck::Zeroable;
+ use proptest::prelude::*;
+ use solana_program::{account_info::AccountInfo, program_error::ProgramError, program_pack::Pack, pubkey::Pubkey};
+ use staking_program::{
+     find_boost_campaign_pda, find_campaign_pda, find_claim_cooldown_bypass_pda, find_cleanup_authority_pda, find_global_tvl_tracker_pda, find_lock_tier_pda, find_permit_nonce_pda, find_pool_pda, find_pool_stats_pda, find_position_mint_pda,
+     find_pool_description_pda, find_pool_roles_pda, find_protocol_config_pda, find_registry_pda, find_rent_payer_pda, find_user_pda, find_vault_pda, find_vesting_pda, BoostCampaign, ClaimCooldownBypass, CleanupAuthority, GlobalPause, GlobalTVLTracker,
+     IncentiveCampaign, LockTierConfig, PermitNonce, PoolDescription, PoolMetadata, PoolRoles, PoolStats, ProtocolConfig, RegistryEntry, RentPayer, StakingError,
+     StakingPool, UserStake, VestingConfig, BOOST_CAMPAIGN_SIZE, CLAIM_COOLDOWN_BYPASS_SIZE, CLEANUP_AUTHORITY_SIZE, GLOBAL_PAUSE_SIZE, GLOBAL_TVL_TRACKER_SIZE, INCENTIVE_CAMPAIGN_SIZE, LOCK_TIER_CONFIG_SIZE,
+     PERMIT_NONCE_SIZE, POOL_DESCRIPTION_SIZE, POOL_METADATA_SIZE, POOL_ROLES_SIZE, POOL_STATS_SIZE, PROTOCOL_CONFIG_SIZE, REGISTRY_ENTRY_SIZE, RENT_PAYER_SIZE,
+     STAKING_POOL_SIZE, USER_STAKE_SIZE, VESTING_CONFIG_SIZE,
+ };
+
+ fn sample_pool() -> StakingPool {
+     let mut pool = StakingPool::new(
+         Pubkey::new_unique(),
+         Pubkey::new_unique(),
+         Pubkey::new_unique(),
+         5_000_000,
+         3_600,
+         254,
+         3,
+     );
+     pool.total_staked = 42_000_000_000.into();
+     pool.max_lock_period = 86_400.into();
+     pool
+ }
+
+ fn sample_user_stake() -> UserStake {
+     UserStake {
+         owner: Pubkey::new_unique(),
+         pool: Pubkey::new_unique(),
+         amount: 1_000.into(),
+         start_time: 1_700_000_000.into(),
+         last_claim_time: 1_700_000_100.into(),
+         rewards_claimed: 7.into(),
+         bump: 253,
+         individual_multiplier_bps: 15_000.into(),
+         auto_compound_enabled: 1,
+         auto_compound_interval_seconds: (-1).into(),
+         compound_mode: 1,
+         position_nft_mint: Pubkey::new_unique(),
+         ..UserStake::default()
+     }
+ }
+
+ #[test]
+ fn serialized_sizes_match_account_sizes() {
+     assert_eq!(STAKING_POOL_SIZE, 1024);
+     assert_eq!(USER_STAKE_SIZE, 192);
+     assert_eq!(GLOBAL_PAUSE_SIZE, 64);
+     assert_eq!(POOL_METADATA_SIZE, 224);
+     assert_eq!(INCENTIVE_CAMPAIGN_SIZE, 64);
+     assert_eq!(PROTOCOL_CONFIG_SIZE, 256);
+     assert_eq!(VESTING_CONFIG_SIZE, 64);
+     assert_eq!(REGISTRY_ENTRY_SIZE, 96);
+     assert_eq!(PERMIT_NONCE_SIZE, 96);
+     assert_eq!(LOCK_TIER_CONFIG_SIZE, 128);
+     assert_eq!(POOL_STATS_SIZE, 112);
+     assert_eq!(CLEANUP_AUTHORITY_SIZE, 80);
+     assert_eq!(RENT_PAYER_SIZE, 64);
+     assert_eq!(POOL_ROLES_SIZE, 128);
+     assert_eq!(POOL_DESCRIPTION_SIZE, 320);
+     assert_eq!(CLAIM_COOLDOWN_BYPASS_SIZE, 48);
+     assert_eq!(GLOBAL_TVL_TRACKER_SIZE, 72);
+     assert_eq!(BOOST_CAMPAIGN_SIZE, 88);
+
+     assert_eq!(borsh::to_vec(&sample_pool()).unwrap().len(), STAKING_POOL_SIZE);
+     assert_eq!(borsh::to_vec(&UserStake::default()).unwrap().len(), USER_STAKE_SIZE);
+     assert_eq!(borsh::to_vec(&GlobalPause::zeroed()).unwrap().len(), GLOBAL_PAUSE_SIZE);
+     assert_eq!(borsh::to_vec(&PoolMetadata::zeroed()).unwrap().len(), POOL_METADATA_SIZE);
+     assert_eq!(borsh::to_vec(&IncentiveCampaign::zeroed()).unwrap().len(), INCENTIVE_CAMPAIGN_SIZE);
+     assert_eq!(borsh::to_vec(&ProtocolConfig::zeroed()).unwrap().len(), PROTOCOL_CONFIG_SIZE);
+     assert_eq!(borsh::to_vec(&VestingConfig::zeroed()).unwrap().len(), VESTING_CONFIG_SIZE);
+     assert_eq!(borsh::to_vec(&RegistryEntry::zeroed()).unwrap().len(), REGISTRY_ENTRY_SIZE);
+     assert_eq!(borsh::to_vec(&PermitNonce::zeroed()).unwrap().len(), PERMIT_NONCE_SIZE);
+     assert_eq!(borsh::to_vec(&LockTierConfig::zeroed()).unwrap().len(), LOCK_TIER_CONFIG_SIZE);
+     assert_eq!(borsh::to_vec(&PoolStats::zeroed()).unwrap().len(), POOL_STATS_SIZE);
+     assert_eq!(borsh::to_vec(&CleanupAuthority::zeroed()).unwrap().len(), CLEANUP_AUTHORITY_SIZE);
+     assert_eq!(borsh::to_vec(&RentPayer::zeroed()).unwrap().len(), RENT_PAYER_SIZE);
+     assert_eq!(borsh::to_vec(&PoolRoles::zeroed()).unwrap().len(), POOL_ROLES_SIZE);
+     assert_eq!(borsh::to_vec(&PoolDescription::zeroed()).unwrap().len(), POOL_DESCRIPTION_SIZE);
+     assert_eq!(borsh::to_vec(&ClaimCooldownBypass::zeroed()).unwrap().len(), CLAIM_COOLDOWN_BYPASS_SIZE);
+     assert_eq!(borsh::to_vec(&GlobalTVLTracker::zeroed()).unwrap().len(), GLOBAL_TVL_TRACKER_SIZE);
+     assert_eq!(borsh::to_vec(&BoostCampaign::zeroed()).unwrap().len(), BOOST_CAMPAIGN_SIZE);
+ }
+
+ #[test]
+ fn state_round_trips_through_borsh_and_bytemuck() {
+     let pool = sample_pool();
+     let bytes = borsh::to_vec(&pool).unwrap();
+     assert_eq!(StakingPool::try_from_slice(&bytes).unwrap(), pool);
+     assert_eq!(*StakingPool::load(&bytes).unwrap(), pool);
+
+     let us = sample_user_stake();
+     let bytes = borsh::to_vec(&us).unwrap();
+     assert_eq!(UserStake::try_from_slice(&bytes).unwrap(), us);
+     assert_eq!(*UserStake::load(&bytes).unwrap(), us);
+ }
+
+ #[test]
+ fn load_rejects_short_account_data() {
+     let bytes = borsh::to_vec(&sample_pool()).unwrap();
+     assert!(StakingPool::load(&bytes[..STAKING_POOL_SIZE - 1]).is_err());
+     let bytes = borsh::to_vec(&UserStake::default()).unwrap();
+     assert!(UserStake::load(&bytes[..USER_STAKE_SIZE - 1]).is_err());
+ }
+
+ /// Runs `f` against an account holding `data`, owned by `owner`
+ fn with_account<R>(owner: &Pubkey, data: &mut [u8], f: impl FnOnce(&AccountInfo) -> R) -> R {
+     let key = Pubkey::new_unique();
+     let mut lamports = 0;
+     f(&AccountInfo::new(&key, false, true, &mut lamports, data, owner, false, 0))
+ }
+
+ #[test]
+ fn load_checked_rejects_foreign_short_and_zeroed_accounts() {
+     let program_id = Pubkey::new_unique();
+     let foreign = Pubkey::new_unique();
+
+     let mut bytes = borsh::to_vec(&sample_pool()).unwrap();
+     let err = with_account(&foreign, &mut bytes, |ai| StakingPool::load_checked(ai, &program_id).unwrap_err());
+     assert_eq!(err, StakingError::InvalidOwner.into());
+     let err = with_account(&program_id, &mut bytes[..STAKING_POOL_SIZE - 1], |ai| {
+         StakingPool::load_checked(ai, &program_id).unwrap_err()
+     });
+     assert_eq!(err, ProgramError::InvalidAccountData);
+     let err = with_account(&program_id, &mut [0u8; STAKING_POOL_SIZE], |ai| {
+         StakingPool::load_checked(ai, &program_id).unwrap_err()
+     });
+     assert_eq!(err, ProgramError::UninitializedAccount);
+
+     let mut bytes = borsh::to_vec(&sample_user_stake()).unwrap();
+     let err = with_account(&foreign, &mut bytes, |ai| UserStake::load_checked(ai, &program_id).unwrap_err());
+     assert_eq!(err, StakingError::InvalidOwner.into());
+     let err = with_account(&program_id, &mut bytes[..USER_STAKE_SIZE - 1], |ai| {
+         UserStake::load_checked(ai, &program_id).unwrap_err()
+     });
+     assert_eq!(err, ProgramError::InvalidAccountData);
+     let err = with_account(&program_id, &mut [0u8; USER_STAKE_SIZE], |ai| {
+         UserStake::load_checked(ai, &program_id).unwrap_err()
+     });
+     assert_eq!(err, ProgramError::UninitializedAccount);
+ }
+
+ #[test]
+ fn save_writes_back_what_load_checked_reads() {
+     let program_id = Pubkey::new_unique();
+     let pool = sample_pool();
+     let loaded = with_account(&program_id, &mut [0u8; STAKING_POOL_SIZE], |ai| {
+         pool.save(ai).unwrap();
+         StakingPool::load_checked(ai, &program_id).unwrap()
+     });
+     assert_eq!(loaded, pool);
+
+     let us = sample_user_stake();
+     let loaded = with_account(&program_id, &mut [0u8; USER_STAKE_SIZE], |ai| {
+         us.save(ai).unwrap();
+         UserStake::load_checked(ai, &program_id).unwrap()
+     });
+     assert_eq!(loaded, us);
+
+     // Pack agrees with the loaders on what counts as initialized
+     assert_eq!(StakingPool::unpack(&[0u8; STAKING_POOL_SIZE]).unwrap_err(), ProgramError::UninitializedAccount);
+     assert_eq!(UserStake::unpack(&borsh::to_vec(&us).unwrap()).unwrap(), us);
+ }
+
+ fn pubkey() -> impl Strategy<Value = Pubkey> {
+     any::<[u8; 32]>().prop_map(Pubkey::new_from_array)
+ }
+
+ prop_compose! {
+     fn arb_pool()(
+         keys in [pubkey(), pubkey(), pubkey(), pubkey()],
+         rates in any::<[u64; 3]>(),
+         periods in any::<[i64; 2]>(),
+         bump in any::<u8>(),
+         pool_id in any::<u64>(),
+     ) -> StakingPool {
+         let [authority, vault, mint, pending_authority] = keys;
+         let mut pool = StakingPool::new(authority, vault, mint, rates[0], periods[0], bump, pool_id);
+         pool.total_staked = rates[1].into();
+         pool.max_reward_rate = rates[2].into();
+         pool.pending_authority = pending_authority;
+         pool.max_lock_period = periods[1].into();
+         pool
+     }
+ }
+
+ prop_compose! {
+     fn arb_user_stake()(
+         owner in pubkey(),
+         pool in pubkey(),
+         amounts in any::<[u64; 2]>(),
+         times in any::<[i64; 4]>(),
+         small in any::<(u8, u16, u8, u8)>(),
+     ) -> UserStake {
+         UserStake {
+             owner,
+             pool,
+             amount: amounts[0].into(),
+             start_time: times[0].into(),
+             last_claim_time: times[1].into(),
+             rewards_claimed: amounts[1].into(),
+             bump: small.0,
+             individual_multiplier_bps: small.1.into(),
+             auto_compound_enabled: small.2,
+             auto_compound_interval_seconds: times[2].into(),
+             compound_mode: small.3,
+             unlock_at: times[3].into(),
+             ..UserStake::default()
+         }
+     }
+ }
+
+ proptest! {
+     #[test]
+     fn pool_round_trips(pool in arb_pool(), padding in 0usize..64) {
+         let bytes = borsh::to_vec(&pool).unwrap();
+         prop_assert!(bytes.len() <= STAKING_POOL_SIZE);
+         prop_assert_eq!(StakingPool::try_from_slice(&bytes).unwrap(), pool);
+
+         // Accounts are fixed-size and may be larger than the encoding; the tail is zeroes
+         let mut account = vec![0u8; STAKING_POOL_SIZE + padding];
+         account[..bytes.len()].copy_from_slice(&bytes);
+         prop_assert_eq!(StakingPool::deserialize(&mut account.as_slice()).unwrap(), pool);
+         prop_assert_eq!(*StakingPool::load(&account).unwrap(), pool);
+     }
+
+     #[test]
+     fn user_stake_round_trips(us in arb_user_stake(), padding in 0usize..64) {
+         let bytes = borsh::to_vec(&us).unwrap();
+         prop_assert!(bytes.len() <= USER_STAKE_SIZE);
+         prop_assert_eq!(UserStake::try_from_slice(&bytes).unwrap(), us);
+
+         let mut account = vec![0u8; USER_STAKE_SIZE + padding];
+         account[..bytes.len()].copy_from_slice(&bytes);
+         prop_assert_eq!(UserStake::deserialize(&mut account.as_slice()).unwrap(), us);
+         prop_assert_eq!(*UserStake::load(&account).unwrap(), us);
+     }
+ }
+
+ #[test]
+ fn pda_helpers_use_the_documented_seeds() {
+     let program_id = Pubkey::new_unique();
+     let mint = Pubkey::new_unique();
+     let owner = Pubkey::new_unique();
+     let pool = find_pool_pda(&program_id, &mint, 0);
+     assert_eq!(pool, Pubkey::find_program_address(&[b"pool", mint.as_ref()], &program_id));
+     assert_eq!(
+         find_pool_pda(&program_id, &mint, 7),
+         Pubkey::find_program_address(&[b"pool", mint.as_ref(), &7u64.to_le_bytes()], &program_id),
+     );
+     assert_eq!(
+         find_user_pda(&program_id, &pool.0, &owner),
+         Pubkey::find_program_address(&[b"user", pool.0.as_ref(), owner.as_ref()], &program_id),
+     );
+     assert_eq!(
+         find_campaign_pda(&program_id, &pool.0, 7),
+         Pubkey::find_program_address(&[b"campaign", pool.0.as_ref(), &7u64.to_le_bytes()], &program_id),
+     );
+     assert_eq!(find_protocol_config_pda(&program_id), Pubkey::find_program_address(&[b"config"], &program_id));
+     assert_eq!(find_vesting_pda(&program_id, &pool.0), Pubkey::find_program_address(&[b"vest", pool.0.as_ref()], &program_id));
+     assert_eq!(
+         find_position_mint_pda(&program_id, &owner),
+         Pubkey::find_program_address(&[b"position", owner.as_ref()], &program_id),
+     );
+     assert_eq!(find_registry_pda(&program_id, 3), Pubkey::find_program_address(&[b"registry", &3u32.to_le_bytes()], &program_id));
+     assert_eq!(
+         find_permit_nonce_pda(&program_id, &pool.0, &owner),
+         Pubkey::find_program_address(&[b"permit", pool.0.as_ref(), owner.as_ref()], &program_id),
+     );
+     assert_eq!(find_lock_tier_pda(&program_id, &pool.0), Pubkey::find_program_address(&[b"ltier", pool.0.as_ref()], &program_id));
+     assert_eq!(find_pool_stats_pda(&program_id, &pool.0), Pubkey::find_program_address(&[b"stats", pool.0.as_ref()], &program_id));
+     assert_eq!(find_cleanup_authority_pda(&program_id), Pubkey::find_program_address(&[b"cleanup_auth"], &program_id));
+     assert_eq!(find_rent_payer_pda(&program_id, &owner), Pubkey::find_program_address(&[b"rent_payer", owner.as_ref()], &program_id));
+     assert_eq!(find_pool_roles_pda(&program_id, &pool.0), Pubkey::find_program_address(&[b"roles", pool.0.as_ref()], &program_id));
+     assert_eq!(find_pool_description_pda(&program_id, &pool.0), Pubkey::find_program_address(&[b"desc", pool.0.as_ref()], &program_id));
+     assert_eq!(
+         find_claim_cooldown_bypass_pda(&program_id, &owner),
+         Pubkey::find_program_address(&[b"cooldown_bypass", owner.as_ref()], &program_id),
+     );
+     assert_eq!(find_vault_pda(&program_id, &pool.0), Pubkey::find_program_address(&[b"vault", pool.0.as_ref()], &program_id));
+     assert_eq!(find_global_tvl_tracker_pda(&program_id), Pubkey::find_program_address(&[b"tvl"], &program_id));
+     assert_eq!(
+         find_boost_campaign_pda(&program_id, &pool.0, 7),
+         Pubkey::find_program_address(&[b"bcmp", pool.0.as_ref(), &7u64.to_le_bytes()], &program_id),
+     );
+ }
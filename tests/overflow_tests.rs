@@ -0,0 +1,74 @@
+ // Reward formula at extreme parameters, both on the pure helper and end to end through
+ // ClaimRewards. The result must be exact or `Overflow`, never a wrapped value.
+ //
+ // Safe parameter space: `elapsed * amount * reward_rate` is computed in u128 with checked
+ // multiplies, so it never wraps; the claim succeeds while that product is at most
+ // `u64::MAX * 1e9` (~1.8e28) and fails with `Overflow` above it. For example a full year
+ // (~3.2e7 s) at reward_rate 1e9 (1 token per token-second) stays exact up to ~5.8e11 staked.
+
+ use solana_program_test::{tokio, BanksClientError};
+ use staking_program::{calculate_pending_rewards, StakingError, StakingInstruction};
+
+ mod common;
+ use common::*;
+
+ const START: i64 = 1_700_000_000;
+
+ /// (elapsed, amount, reward_rate) for each case
+ const EXACT: (i64, u64, u64) = (1_000_000_000, 1_000_000_000, 1_000_000_000);
+ const OVERFLOWING: (i64, u64, u64) = (i64::MAX / 1000, u64::MAX / 1000, u64::MAX);
+ const ZERO_RATE: (i64, u64, u64) = (i64::MAX / 1000, 1_000_000_000, 0);
+
+ #[test]
+ fn pending_rewards_at_extremes() {
+     let (elapsed, amount, rate) = EXACT;
+     // 1e9 * 1e9 * 1e9 / 1e9
+     assert_eq!(calculate_pending_rewards(elapsed as u64, amount, rate), Ok(1_000_000_000_000_000_000));
+
+     let (elapsed, amount, rate) = OVERFLOWING;
+     assert_eq!(calculate_pending_rewards(elapsed as u64, amount, rate), Err(StakingError::Overflow));
+     // Each factor alone is fine; only the product overflows, and even u128 would wrap here
+     assert!((elapsed as u128).checked_mul(amount as u128).unwrap().checked_mul(rate as u128).is_none());
+
+     let (elapsed, amount, rate) = ZERO_RATE;
+     assert_eq!(calculate_pending_rewards(elapsed as u64, amount, rate), Ok(0));
+     assert_eq!(calculate_pending_rewards(u64::MAX, u64::MAX, 0), Ok(0));
+ }
+
+ /// Stakes `amount` at `START` in a pool paying `rate`, then claims `elapsed` seconds later.
+ /// Returns the claim result and the tokens the user received.
+ async fn claim_after((elapsed, amount, rate): (i64, u64, u64), vault_funding: u64) -> (Result<(), BanksClientError>, u64) {
+     let pid = program_id();
+     let mut ctx = program_test(pid).start_with_context().await;
+     let pool = setup_pool(&mut ctx, pid, rate, 0).await;
+     mint_to(&mut ctx, &pool, pool.vault_ata, vault_funding).await;
+     let user = setup_user(&mut ctx, &pool, amount).await;
+     warp_to_timestamp(&mut ctx, START).await;
+     process(&mut ctx, &[stake_ix(&pool, &user, amount)], &[&user.wallet]).await.unwrap();
+
+     warp_to_timestamp(&mut ctx, START + elapsed).await;
+     let claim = user_exit_ix(&pool, &user, StakingInstruction::ClaimRewards);
+     let result = process(&mut ctx, &[claim], &[&user.wallet]).await;
+     (result, token_balance(&mut ctx, user.ata).await)
+ }
+
+ #[tokio::test]
+ async fn claim_pays_exact_reward_at_large_parameters() {
+     let (result, paid) = claim_after(EXACT, 1_000_000_000_000_000_000).await;
+     result.unwrap();
+     assert_eq!(paid, 1_000_000_000_000_000_000);
+ }
+
+ #[tokio::test]
+ async fn claim_fails_with_overflow_instead_of_wrapping() {
+     let (result, paid) = claim_after(OVERFLOWING, 0).await;
+     assert_staking_err(result.unwrap_err(), StakingError::Overflow);
+     assert_eq!(paid, 0);
+ }
+
+ #[tokio::test]
+ async fn claim_at_zero_rate_pays_nothing() {
+     let (result, paid) = claim_after(ZERO_RATE, 0).await;
+     result.unwrap();
+     assert_eq!(paid, 0);
+ }
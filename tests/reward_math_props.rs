@@ -0,0 +1,120 @@
+ // Property tests for `calculate_pending_rewards`: `elapsed * amount * rate / 1e9` in u128,
+ // checked for overflow, monotonicity, zero behaviour and split-claim truncation; plus the APR helpers.
+ // Failing seeds are saved under proptest-regressions/ so a shrunk case replays on the next run.
+
+ use proptest::prelude::*;
+ use staking_program::{
+     apr_bps_from_reward_rate, calculate_pending_rewards, reward_rate_from_apr_bps, StakingError, SECONDS_PER_YEAR,
+ };
+
+ const SCALE: u128 = 1_000_000_000;
+
+ /// Small, 32-bit and full-range values, so both the exact and the overflowing regions get hit
+ fn factor() -> impl Strategy<Value = u64> {
+     prop_oneof![0..=16u64, 0..=u32::MAX as u64, any::<u64>()]
+ }
+
+ /// The exact product, or `None` once it cannot fit in u128 (and so cannot fit u64 after scaling)
+ fn exact_product(elapsed: u64, amount: u64, rate: u64) -> Option<u128> {
+     (elapsed as u128).checked_mul(amount as u128)?.checked_mul(rate as u128)
+ }
+
+ #[test]
+ fn apr_conversions_match_hand_computed_rates() {
+     // 10% APR: 0.1 / 31_536_000s * 1e9 = 3.17, but 3 units pay 3 * 315.36 = 946 bps, 5.4% short, so it is refused
+     assert_eq!(reward_rate_from_apr_bps(1_000), Err(StakingError::AprNotRepresentable));
+     assert_eq!(apr_bps_from_reward_rate(3), 946);
+     assert_eq!(reward_rate_from_apr_bps(946), Ok(3));
+     // 100 units pay exactly 31_536 bps; the 0.001-per-second rate the integration tests use is 315_360_000 bps
+     assert_eq!(reward_rate_from_apr_bps(31_536), Ok(100));
+     assert_eq!(apr_bps_from_reward_rate(100), 31_536);
+     assert_eq!(apr_bps_from_reward_rate(1_000_000), 315_360_000);
+     // 1000% APR is 317.1 units; 317 pays 99_969 bps, within the 1% tolerance
+     assert_eq!(reward_rate_from_apr_bps(100_000), Ok(317));
+     // Below one unit nothing but 0 is representable
+     assert_eq!(reward_rate_from_apr_bps(100), Err(StakingError::AprNotRepresentable));
+     assert_eq!((reward_rate_from_apr_bps(0), apr_bps_from_reward_rate(0)), (Ok(0), 0));
+         // A year at the converted rate pays the APR on a 9-decimal token: 1_000 tokens at 31_536 bps earn 3_153.6
+     let year = calculate_pending_rewards(SECONDS_PER_YEAR, 1_000_000_000_000, reward_rate_from_apr_bps(31_536).unwrap());
+     assert_eq!(year, Ok(3_153_600_000_000));
+     assert_eq!(apr_bps_from_reward_rate(u64::MAX), u64::MAX);
+ }
+
+ proptest! {
+     #![proptest_config(ProptestConfig::with_cases(10_000))]
+
+     #[test]
+     fn result_is_exact_or_overflow(elapsed in factor(), amount in factor(), rate in factor()) {
+         let expected = exact_product(elapsed, amount, rate).map(|p| p / SCALE).filter(|q| *q <= u64::MAX as u128);
+         match calculate_pending_rewards(elapsed, amount, rate) {
+             Ok(pending) => prop_assert_eq!(Some(pending as u128), expected),
+             Err(err) => {
+                 prop_assert_eq!(err, StakingError::Overflow);
+                 prop_assert_eq!(expected, None);
+             }
+         }
+     }
+
+     #[test]
+     fn monotonic_in_each_argument(args in [factor(), factor(), factor()], which in 0..3usize, bump in factor()) {
+         let mut larger = args;
+         larger[which] = larger[which].saturating_add(bump);
+         let lo = calculate_pending_rewards(args[0], args[1], args[2]);
+         let hi = calculate_pending_rewards(larger[0], larger[1], larger[2]);
+         match (lo, hi) {
+             (Ok(lo), Ok(hi)) => prop_assert!(lo <= hi),
+             (Ok(_), Err(err)) => prop_assert_eq!(err, StakingError::Overflow),
+             // Once a smaller input overflows, every larger one must too
+             (Err(_), hi) => prop_assert!(hi.is_err()),
+         }
+     }
+
+     #[test]
+     fn zero_factor_gives_zero(elapsed in factor(), amount in factor(), rate in factor(), which in 0..3usize) {
+         let mut args = [elapsed, amount, rate];
+         args[which] = 0;
+         prop_assert_eq!(calculate_pending_rewards(args[0], args[1], args[2]), Ok(0));
+     }
+
+     #[test]
+     fn nonzero_factors_give_zero_only_below_one_unit(elapsed in 1..=u64::MAX, amount in 1..=u64::MAX, rate in 1..=u64::MAX) {
+         // Truncation, not a zero factor, is the only other way to earn nothing
+         if let Ok(0) = calculate_pending_rewards(elapsed, amount, rate) {
+             prop_assert!(exact_product(elapsed, amount, rate).unwrap() < SCALE);
+         }
+     }
+
+     #[test]
+     fn split_claims_never_beat_one_claim(first in factor(), second in factor(), amount in factor(), rate in factor()) {
+         let whole = first.checked_add(second).map(|elapsed| calculate_pending_rewards(elapsed, amount, rate));
+         let (Some(Ok(whole)), Ok(a), Ok(b)) = (
+             whole,
+             calculate_pending_rewards(first, amount, rate),
+             calculate_pending_rewards(second, amount, rate),
+         ) else {
+             return Ok(());
+         };
+         let split = a as u128 + b as u128;
+         // Each claim truncates once, so splitting loses at most one unit and never gains
+         prop_assert!(split <= whole as u128);
+         prop_assert!(whole as u128 - split <= 1);
+     }
+
+     #[test]
+     fn rate_survives_apr_round_trip(rate in 0..=u64::MAX / 315_360) {
+         prop_assert_eq!(reward_rate_from_apr_bps(apr_bps_from_reward_rate(rate)), Ok(rate));
+     }
+
+     #[test]
+     fn accepted_aprs_are_paid_within_tolerance(apr_bps in any::<u64>()) {
+         // What the rate pays, to the nearest bps, is within 1% of the request (plus that rounding), or the
+         // conversion fails; from 16_000 bps on, half a 315.36 bps unit is always within 1%
+         match reward_rate_from_apr_bps(apr_bps) {
+             Ok(rate) => prop_assert!(apr_bps_from_reward_rate(rate).abs_diff(apr_bps) <= apr_bps / 100 + 1),
+             Err(err) => {
+                 prop_assert_eq!(err, StakingError::AprNotRepresentable);
+                 prop_assert!(apr_bps < 16_000);
+             }
+         }
+     }
+ }
@@ -0,0 +1,165 @@
+ // Adversarial account substitution: every test swaps one account for something an attacker
+ // controls and asserts the exact error, so a dropped validation shows up as a test failure.
+
+ use solana_program::pubkey::Pubkey;
+ use solana_program_test::{tokio, BanksClientError, ProgramTestContext};
+ use solana_sdk::{
+     instruction::{AccountMeta, Instruction, InstructionError},
+     signature::{Keypair, Signer},
+     transaction::TransactionError,
+ };
+ use spl_associated_token_account::get_associated_token_address;
+ use staking_program::{find_pool_pda, StakingError, StakingInstruction, StakingPool};
+
+ mod common;
+ use common::*;
+
+ fn assert_program_err(err: BanksClientError, expected: InstructionError) {
+     assert_eq!(err.unwrap(), TransactionError::InstructionError(0, expected));
+ }
+
+ async fn setup_staked(ctx: &mut ProgramTestContext, pool: &TestPool) -> TestUser {
+     let user = setup_user(ctx, pool, 1_000_000_000_000).await;
+     process(ctx, &[stake_ix(pool, &user, 100_000_000_000)], &[&user.wallet]).await.unwrap();
+     user
+ }
+
+ /// A byte-for-byte copy of the real pool with `authority` swapped in, owned by another program
+ async fn fake_pool(ctx: &mut ProgramTestContext, pool: &TestPool, authority: &Pubkey) -> Pubkey {
+     let mut account = ctx.banks_client.get_account(pool.pool_pda).await.unwrap().unwrap();
+     StakingPool::load_mut(&mut account.data).unwrap().authority = *authority;
+     account.owner = Pubkey::new_unique();
+     let fake = Pubkey::new_unique();
+     ctx.set_account(&fake, &account.into());
+     fake
+ }
+
+ fn with_account(mut ix: Instruction, index: usize, pubkey: Pubkey) -> Instruction {
+     ix.accounts[index].pubkey = pubkey;
+     ix
+ }
+
+ #[tokio::test]
+ async fn test_signer_cannot_use_another_users_stake_pda() {
+     let pid = program_id();
+     let mut ctx = program_test(pid).start_with_context().await;
+     let pool = setup_pool(&mut ctx, pid, 5_000_000, 0).await;
+     let alice = setup_staked(&mut ctx, &pool).await;
+     let bob = setup_staked(&mut ctx, &pool).await;
+
+     // Alice signs, with her own ATA, but points at Bob's position
+     for data in [StakingInstruction::ClaimRewards, StakingInstruction::Unstake] {
+         let ix = with_account(user_exit_ix(&pool, &alice, data), 3, bob.stake_pda);
+         let err = process(&mut ctx, &[ix], &[&alice.wallet]).await.unwrap_err();
+         assert_staking_err(err, StakingError::InvalidOwner);
+     }
+
+     let ix = with_account(stake_ix(&pool, &alice, 1_000), 4, bob.stake_pda);
+     let err = process(&mut ctx, &[ix], &[&alice.wallet]).await.unwrap_err();
+     assert_program_err(err, InstructionError::InvalidArgument);
+
+     let keys = vec![
+         AccountMeta::new_readonly(alice.wallet.pubkey(), true),
+         AccountMeta::new_readonly(pool.pool_pda, false),
+         AccountMeta::new(bob.stake_pda, false),
+     ];
+     let ix = build_ix(pid, keys, StakingInstruction::SetupAutoCompound { interval_seconds: 1 });
+     let err = process(&mut ctx, &[ix], &[&alice.wallet]).await.unwrap_err();
+     assert_program_err(err, InstructionError::InvalidArgument);
+
+     assert_eq!(read_user_stake(&mut ctx.banks_client, bob.stake_pda).await.amount.get(), 100_000_000_000);
+ }
+
+ #[tokio::test]
+ async fn test_rejects_vault_of_another_pool_with_same_mint() {
+     let pid = program_id();
+     let mut ctx = program_test(pid).start_with_context().await;
+     let pool = setup_pool(&mut ctx, pid, 5_000_000, 0).await;
+     let user = setup_staked(&mut ctx, &pool).await;
+
+     // The same mint's pool under another deployment has its own canonical vault ATA
+     let (other_pool, _) = find_pool_pda(&Pubkey::new_unique(), &pool.mint.pubkey(), 0);
+     let create_ata = spl_associated_token_account::instruction::create_associated_token_account(
+         &ctx.payer.pubkey(), &other_pool, &pool.mint.pubkey(), &spl_token::id(),
+     );
+     process(&mut ctx, &[create_ata], &[]).await.unwrap();
+     let other_vault = get_associated_token_address(&other_pool, &pool.mint.pubkey());
+
+     let ix = with_account(stake_ix(&pool, &user, 1_000), 5, other_vault);
+     let err = process(&mut ctx, &[ix], &[&user.wallet]).await.unwrap_err();
+     assert_staking_err(err, StakingError::InvalidVault);
+     for data in [StakingInstruction::ClaimRewards, StakingInstruction::Unstake] {
+         let ix = with_account(user_exit_ix(&pool, &user, data), 5, other_vault);
+         let err = process(&mut ctx, &[ix], &[&user.wallet]).await.unwrap_err();
+         assert_staking_err(err, StakingError::InvalidVault);
+     }
+     let ix = with_account(force_claim_ix(&pool, &pool.authority.pubkey(), &user), 5, other_vault);
+     let err = process(&mut ctx, &[ix], &[&pool.authority]).await.unwrap_err();
+     assert_staking_err(err, StakingError::InvalidVault);
+ }
+
+ #[tokio::test]
+ async fn test_rejects_pool_owned_by_another_program() {
+     let pid = program_id();
+     let mut ctx = program_test(pid).start_with_context().await;
+     let pool = setup_pool(&mut ctx, pid, 5_000_000, 0).await;
+     let user = setup_staked(&mut ctx, &pool).await;
+     let attacker = Keypair::new();
+     let fake = fake_pool(&mut ctx, &pool, &attacker.pubkey()).await;
+
+     // User flows: the pool slot must hold this program's pool
+     let ix = with_account(stake_ix(&pool, &user, 1_000), 3, fake);
+     let err = process(&mut ctx, &[ix], &[&user.wallet]).await.unwrap_err();
+     assert_program_err(err, InstructionError::InvalidAccountData);
+     for data in [StakingInstruction::ClaimRewards, StakingInstruction::Unstake] {
+         let ix = with_account(user_exit_ix(&pool, &user, data), 4, fake);
+         let err = process(&mut ctx, &[ix], &[&user.wallet]).await.unwrap_err();
+         assert_program_err(err, InstructionError::InvalidAccountData);
+     }
+
+     // Authority flows: the attacker is the fake pool's authority, but it is not our account
+     let ix = with_account(force_claim_ix(&pool, &attacker.pubkey(), &user), 4, fake);
+     let err = process(&mut ctx, &[ix], &[&attacker]).await.unwrap_err();
+     assert_program_err(err, InstructionError::InvalidAccountData);
+     let ix = with_account(set_multiplier_ix(&pool, &attacker.pubkey(), &user, 50_000), 1, fake);
+     let err = process(&mut ctx, &[ix], &[&attacker]).await.unwrap_err();
+     assert_staking_err(err, StakingError::InvalidOwner);
+     let admin_ixs = [
+         StakingInstruction::UpdateConfig { new_reward_rate: Some(u64::MAX), new_min_lock_period: None },
+         StakingInstruction::ProposeAuthority { proposed: attacker.pubkey() },
+         StakingInstruction::SetMaxLockPeriod { max_seconds: 1 },
+         StakingInstruction::SetMaxRewardRate { max_rate: 5_000_000 },
+         StakingInstruction::SetProtocolVersion { version: 2 },
+     ];
+     for data in admin_ixs {
+         let ix = with_account(admin_ix(&pool, &attacker.pubkey(), data), 1, fake);
+         let err = process(&mut ctx, &[ix], &[&attacker]).await.unwrap_err();
+         assert_staking_err(err, StakingError::InvalidOwner);
+     }
+
+     let us = read_user_stake(&mut ctx.banks_client, user.stake_pda).await;
+     assert_eq!(us.reward_multiplier_bps(), 10_000);
+ }
+
+ #[tokio::test]
+ async fn test_rejects_another_users_token_account() {
+     let pid = program_id();
+     let mut ctx = program_test(pid).start_with_context().await;
+     let pool = setup_pool(&mut ctx, pid, 5_000_000, 0).await;
+     let alice = setup_staked(&mut ctx, &pool).await;
+     let bob = setup_staked(&mut ctx, &pool).await;
+     let bob_balance = token_balance(&mut ctx, bob.ata).await;
+
+     // Alice's own position, paid out to Bob's token account
+     for data in [StakingInstruction::ClaimRewards, StakingInstruction::Unstake] {
+         let ix = with_account(user_exit_ix(&pool, &alice, data), 1, bob.ata);
+         let err = process(&mut ctx, &[ix], &[&alice.wallet]).await.unwrap_err();
+         assert_staking_err(err, StakingError::InvalidOwner);
+     }
+     // Force-claim must pay Alice's canonical ATA, not one the authority picks
+     let ix = with_account(force_claim_ix(&pool, &pool.authority.pubkey(), &alice), 1, bob.ata);
+     let err = process(&mut ctx, &[ix], &[&pool.authority]).await.unwrap_err();
+     assert_program_err(err, InstructionError::InvalidArgument);
+
+     assert_eq!(token_balance(&mut ctx, bob.ata).await, bob_balance);
+ }